@@ -1,6 +1,8 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* The last 4K sector holds the persistent settings, see src/storage.rs, and the 1M before
+       it the sample bank, see src/sampler.rs */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 1024K - 4K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
@@ -0,0 +1,217 @@
+//! Additive voice engine, drawbar organ style.
+//!
+//! Nine sine partials at the footages of a tonewheel organ's drawbars are summed, each at the
+//! level of its drawbar. A spectral tilt scales the partials by their distance in octaves from the
+//! fundamental, to darken or brighten a registration without redrawing it.
+use crate::adsr::{Adsr, MAX_LEVEL};
+use crate::fm::sine;
+use crate::intercore::DrawbarPreset;
+use crate::synth::{Param, Synth};
+use crate::wavetables::{pitch_ratio, MIDI_NOTE_TO_SAMPLE_INTERVAL_NS};
+
+pub const DRAWBARS: usize = 9;
+pub const MAX_DRAWBAR_LEVEL: u8 = 127;
+/// Tilt at either end, -64 raises and 63 lowers the partials by about 6dB an octave
+pub const MAX_TILT: i8 = 64;
+/// Frequency of each drawbar as a multiple of the note, in halves: 16', 5 1/3', 8', 4',
+/// 2 2/3', 2', 1 3/5', 1 1/3' and 1'
+const DRAWBAR_RATIO_HALVES: [u32; DRAWBARS] = [1, 3, 2, 4, 6, 8, 10, 12, 16];
+/// Pitch of each drawbar above the note, for the tilt
+const DRAWBAR_CENTS: [i32; DRAWBARS] = [-1200, 702, 0, 1200, 1902, 2400, 2786, 3102, 3600];
+/// Drawbar settings of the presets, 0..=8 as on an organ
+const PRESETS: [[u8; DRAWBARS]; 6] = [
+    [0, 0, 8, 0, 0, 0, 0, 0, 0],
+    [8, 8, 8, 0, 0, 0, 0, 0, 0],
+    [8, 8, 8, 8, 0, 0, 0, 0, 0],
+    [0, 0, 8, 4, 0, 0, 0, 0, 0],
+    [8, 0, 8, 0, 0, 0, 0, 0, 8],
+    [8, 8, 8, 8, 8, 8, 8, 8, 8],
+];
+const MAX_PRESET_LEVEL: u8 = 8;
+const SAMPLE_CENTER: i32 = 128;
+/// Samples in a cycle of the note, the interval table is for one wavetable sample
+const CYCLE_SAMPLES_BITS: u32 = 7;
+
+/// Drawbar levels of a preset
+pub fn preset_drawbars(preset: DrawbarPreset) -> [u8; DRAWBARS] {
+    let index = match preset {
+        DrawbarPreset::Sine => 0,
+        DrawbarPreset::Jazz => 1,
+        DrawbarPreset::Gospel => 2,
+        DrawbarPreset::Flute => 3,
+        DrawbarPreset::Bright => 4,
+        DrawbarPreset::Full => 5,
+    };
+    PRESETS[index]
+        .map(|level| (level as u16 * MAX_DRAWBAR_LEVEL as u16 / MAX_PRESET_LEVEL as u16) as u8)
+}
+
+pub struct AdditiveSynth {
+    envelope: Adsr,
+    drawbars: [u8; DRAWBARS],
+    tilt: i8,
+    /// Weight of each partial in the mix, from its drawbar and the tilt, 8.8 fixed point
+    gains: [i32; DRAWBARS],
+    gain_sum: i32,
+    /// Position in the cycle of the 16' partial, an octave below the note, so every other
+    /// partial is a whole multiple of it
+    phase: u32,
+    note: u8,
+    /// Phase increment of the 16' partial per microsecond
+    phase_per_us: u32,
+    pitch_bend_cents: i16,
+    /// Pitch bend of just this note, from an MPE member channel
+    note_bend_cents: i16,
+}
+
+impl AdditiveSynth {
+    pub fn note(&self) -> u8 {
+        self.note
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.envelope.is_done()
+    }
+
+    pub fn note_pitch_bend(&mut self, cents: i16) {
+        self.note_bend_cents = cents;
+        self.update_pitch();
+    }
+
+    fn update_pitch(&mut self) {
+        let interval_ns = MIDI_NOTE_TO_SAMPLE_INTERVAL_NS[self.note as usize] as u64;
+        if interval_ns == 0 {
+            self.phase_per_us = 0;
+            return;
+        }
+        let cents = self.pitch_bend_cents as i32 + self.note_bend_cents as i32;
+        let period_ns = (interval_ns * pitch_ratio(cents) as u64) << CYCLE_SAMPLES_BITS;
+        // Half the note frequency, for the 16' partial
+        self.phase_per_us = ((1000u64 << (32 + 16 - 1)) / period_ns) as u32;
+    }
+
+    fn update_gains(&mut self) {
+        for ((gain, level), cents) in self
+            .gains
+            .iter_mut()
+            .zip(self.drawbars.iter())
+            .zip(DRAWBAR_CENTS.iter())
+        {
+            // pitch_ratio is 2^(-cents/1200), so a positive tilt halves each octave up at 64
+            let tilt = pitch_ratio(cents * self.tilt as i32 / MAX_TILT as i32);
+            *gain = ((*level as u32 * tilt) >> 8) as i32;
+        }
+        self.gain_sum = self.gains.iter().sum();
+    }
+}
+
+impl Synth for AdditiveSynth {
+    fn new() -> Self {
+        let mut synth = Self {
+            envelope: Adsr::new(),
+            drawbars: preset_drawbars(DrawbarPreset::default()),
+            tilt: 0,
+            gains: [0; DRAWBARS],
+            gain_sum: 0,
+            phase: 0,
+            note: 69,
+            phase_per_us: 0,
+            pitch_bend_cents: 0,
+            note_bend_cents: 0,
+        };
+        synth.update_gains();
+        synth.update_pitch();
+        synth
+    }
+
+    fn update(&mut self, elapsed_time_us: u32) -> u8 {
+        let level = self.envelope.update(elapsed_time_us) as i32;
+        self.phase = self
+            .phase
+            .wrapping_add(self.phase_per_us.wrapping_mul(elapsed_time_us));
+        if self.gain_sum == 0 {
+            return 0;
+        }
+        let mix: i32 = self
+            .gains
+            .iter()
+            .zip(DRAWBAR_RATIO_HALVES.iter())
+            .filter(|(gain, _)| **gain != 0)
+            .map(|(gain, ratio_halves)| sine(self.phase.wrapping_mul(*ratio_halves)) * gain)
+            .sum();
+        // Like the wavetable voices, the output sits on a level that follows the envelope
+        let sample = (SAMPLE_CENTER + mix / self.gain_sum) * level / MAX_LEVEL as i32;
+        sample.clamp(0, u8::MAX as i32) as u8
+    }
+
+    fn note_on(&mut self, note: u8, velocity: u8) {
+        self.note = note;
+        self.update_pitch();
+        self.envelope.trigger(velocity);
+    }
+
+    fn note_off(&mut self, _note: u8) {
+        self.envelope.release();
+    }
+
+    fn set_param(&mut self, param: Param) {
+        match param {
+            Param::Attack(attack_ms) => {
+                self.envelope.set_attack(attack_ms as u32);
+            }
+            Param::Decay(decay_ms) => {
+                self.envelope.set_decay(decay_ms as u32);
+            }
+            Param::Sustain(sustain_level) => {
+                self.envelope
+                    .set_sustain(u32::min(sustain_level as u32, MAX_LEVEL));
+            }
+            Param::Release(release_ms) => {
+                self.envelope.set_release(release_ms as u32);
+            }
+            Param::AttackCurve(curve) => {
+                self.envelope.set_attack_curve(curve);
+            }
+            Param::DecayCurve(curve) => {
+                self.envelope.set_decay_curve(curve);
+            }
+            Param::ReleaseCurve(curve) => {
+                self.envelope.set_release_curve(curve);
+            }
+            Param::Drawbar { drawbar, level } => {
+                if let Some(existing) = self.drawbars.get_mut(drawbar as usize) {
+                    *existing = u8::min(level, MAX_DRAWBAR_LEVEL);
+                    self.update_gains();
+                }
+            }
+            Param::DrawbarPreset(preset) => {
+                self.drawbars = preset_drawbars(preset);
+                self.update_gains();
+            }
+            Param::DrawbarTilt(tilt) => {
+                self.tilt = tilt.clamp(-MAX_TILT, MAX_TILT - 1);
+                self.update_gains();
+            }
+            _ => {}
+        }
+    }
+
+    fn all_sound_off(&mut self) {
+        self.envelope.reset();
+    }
+
+    fn all_notes_off(&mut self) {
+        self.envelope.release();
+    }
+
+    fn reset_all_controllers(&mut self) {
+        self.pitch_bend_cents = 0;
+        self.note_bend_cents = 0;
+        self.update_pitch();
+    }
+
+    fn pitch_bend(&mut self, cents: i16) {
+        self.pitch_bend_cents = cents;
+        self.update_pitch();
+    }
+}
@@ -1,20 +1,50 @@
+use crate::wavetables::pitch_ratio;
 use defmt::info;
 pub const MAX_LEVEL: u32 = 4095;
-const DEFAULT_ATTACK_MS: u32 = 100;
-const DEFAULT_DECAY_MS: u32 = 50;
-const DEFAULT_RELEASE_MS: u32 = 500;
+pub const DEFAULT_ATTACK_MS: u32 = 100;
+pub const DEFAULT_DECAY_MS: u32 = 50;
+pub const DEFAULT_SUSTAIN_LEVEL: u32 = MAX_LEVEL / 3;
+pub const DEFAULT_RELEASE_MS: u32 = 500;
+/// Curve of a segment at either end. 0 is a straight line, a positive curve is exponential like
+/// an analog envelope, fast at first and slowing as it nears its level, and a negative curve is
+/// logarithmic, slow at first and speeding up
+pub const MAX_CURVE: i8 = 64;
+/// Time constants of the exponential a segment covers at the most curve
+const MAX_TIME_CONSTANTS: u32 = 5;
+/// 1200 * log2(e), so `pitch_ratio` of a number of these is e to minus that number
+const TIME_CONSTANT_CENTS: u32 = 1731;
+/// Bits of extra precision the level is kept with, few enough that a curve's distance fits 32 bits
+const PRECISION_BITS: u32 = 12;
+/// Rate of a 0 ms segment, which jumps straight to its target
+const JUMP: u32 = u32::MAX;
 
+/// An envelope whose segments each run in their set time along their own curve.
+///
+/// Each segment works out its rate when it starts, as a fixed point coefficient, so an update is
+/// a multiply and a shift. A curved segment moves at a rate proportional to its distance from a
+/// point just past its target, or just before its start, which traces an exponential and still
+/// gets there in time. Changes to the times and curves apply from the next segment.
 #[derive(Debug, PartialEq)]
 pub struct Adsr {
     attack_ms: u32,
     decay_ms: u32,
     sustain_level: u32,
-    aftertouch: u32,
     release_ms: u32,
+    attack_curve: i8,
+    decay_curve: i8,
+    release_curve: i8,
     state: AdsrState,
-    time_us: u32,
     triggered: bool,
     velocity: u32,
+    /// Level with the extra precision
+    level: i32,
+    /// Level the segment started from
+    start: i32,
+    /// Distance past the target, or before the start, the curve is measured from
+    overshoot: i32,
+    /// Part to cover per microsecond as 0.32 fixed point, of the whole segment when it is
+    /// straight, otherwise of the distance the curve is measured from
+    rate: u32,
 }
 
 #[derive(Debug, PartialEq)]
@@ -31,13 +61,18 @@ impl Adsr {
         Self {
             attack_ms: DEFAULT_ATTACK_MS,
             decay_ms: DEFAULT_DECAY_MS,
-            sustain_level: MAX_LEVEL / 3,
+            sustain_level: DEFAULT_SUSTAIN_LEVEL,
             release_ms: DEFAULT_RELEASE_MS,
-            aftertouch: 0,
+            attack_curve: 0,
+            decay_curve: 0,
+            release_curve: 0,
             state: AdsrState::Done,
-            time_us: 0,
             triggered: false,
             velocity: 127,
+            level: 0,
+            start: 0,
+            overshoot: 0,
+            rate: 0,
         }
     }
 
@@ -53,82 +88,228 @@ impl Adsr {
         self.sustain_level = sustain_level;
     }
 
-    pub fn set_aftertouch(&mut self, aftertouch: u32) {
-        self.aftertouch = aftertouch;
-    }
-
     pub fn set_release(&mut self, release_ms: u32) {
         self.release_ms = release_ms;
     }
 
+    pub fn set_attack_curve(&mut self, curve: i8) {
+        self.attack_curve = curve.clamp(-MAX_CURVE, MAX_CURVE - 1);
+    }
+
+    pub fn set_decay_curve(&mut self, curve: i8) {
+        self.decay_curve = curve.clamp(-MAX_CURVE, MAX_CURVE - 1);
+    }
+
+    pub fn set_release_curve(&mut self, curve: i8) {
+        self.release_curve = curve.clamp(-MAX_CURVE, MAX_CURVE - 1);
+    }
+
     pub fn trigger(&mut self, velocity: u8) {
-        // When triggered reset the time and set the state to attack
+        // When triggered start the attack from silence
         self.velocity = velocity as u32;
         self.triggered = true;
-        self.time_us = 0;
+        self.level = 0;
         self.state = AdsrState::Attack;
+        self.start_segment(self.attack_target(), self.attack_ms, self.attack_curve);
     }
 
     pub fn release(&mut self) {
         self.triggered = false;
     }
 
-    pub fn update(&mut self, dt_us: u32) -> u16 {
-        self.time_us += dt_us;
-        let time_ms = self.time_us / 1000;
+    /// Silence the envelope immediately, skipping the release
+    pub fn reset(&mut self) {
+        self.triggered = false;
+        self.level = 0;
+        self.state = AdsrState::Done;
+    }
+
+    /// True once the release has finished and the envelope is silent
+    pub fn is_done(&self) -> bool {
+        self.state == AdsrState::Done
+    }
 
+    fn attack_target(&self) -> i32 {
+        (MAX_LEVEL as i32) << PRECISION_BITS
+    }
+
+    fn sustain_target(&self) -> i32 {
+        (u32::min(self.sustain_level, MAX_LEVEL) as i32) << PRECISION_BITS
+    }
+
+    /// Work out the rate to get from the current level to `target` in `time_ms` along `curve`
+    fn start_segment(&mut self, target: i32, time_ms: u32, curve: i8) {
+        self.start = self.level;
+        self.overshoot = 0;
+        if time_ms == 0 {
+            self.rate = JUMP;
+            return;
+        }
+        let rate = u32::MAX / time_ms.saturating_mul(1000);
+        if curve == 0 {
+            self.rate = rate;
+            return;
+        }
+        let time_constants =
+            ((curve.unsigned_abs() as u32 * MAX_TIME_CONSTANTS) << 8) / MAX_CURVE as u32;
+        // e^-time_constants as 16.16 fixed point, the part of the way left at the end
+        let remaining = pitch_ratio(((time_constants * TIME_CONSTANT_CENTS) >> 8) as i32);
+        // remaining / (1 - remaining) as 16.16, under 13 at the least curve
+        let overshoot_ratio = (remaining << 16) / ((1 << 16) - remaining);
+        let span = (target - self.level).unsigned_abs();
+        self.overshoot = ((span as u64 * overshoot_ratio as u64) >> 16) as i32;
+        self.rate = ((rate as u64 * time_constants as u64) >> 8) as u32;
+    }
+
+    /// Move the level towards `target` by the elapsed time, true once it gets there
+    fn advance(&mut self, target: i32, dt_us: u32, curve: i8) -> bool {
+        let step = if self.rate == JUMP {
+            i32::MAX
+        } else {
+            let fraction = u64::min(self.rate as u64 * dt_us as u64, 1 << 32) as i64;
+            let distance = match curve {
+                0 => (target - self.start).abs(),
+                1.. => (target - self.level).abs() + self.overshoot,
+                _ => (self.level - self.start).abs() + self.overshoot,
+            };
+            // The distance is within 32 bits, so the product fits 64
+            ((distance as i64 * fraction) >> 32) as i32
+        };
+        // Make sure it gets there rather than stalling on the rounding
+        let step = i32::max(step, 1);
+        let distance = target - self.level;
+        if distance.abs() <= step {
+            self.level = target;
+            return true;
+        }
+        self.level += step * distance.signum();
+        false
+    }
+
+    pub fn update(&mut self, dt_us: u32) -> u16 {
         match self.state {
             AdsrState::Done => {
                 // The trigger() function will reset the state to attack
-                self.time_us = 0;
+                self.level = 0;
                 return 0;
             }
             AdsrState::Attack => {
-                if time_ms >= self.attack_ms {
+                if self.advance(self.attack_target(), dt_us, self.attack_curve) {
                     self.state = AdsrState::Decay;
-                    return MAX_LEVEL as u16;
+                    self.start_segment(self.sustain_target(), self.decay_ms, self.decay_curve);
                 }
-                let level = (((MAX_LEVEL * time_ms / u32::max(self.attack_ms, 1)) * self.velocity)
-                    / 127) as u16;
-                return level;
             }
             AdsrState::Decay => {
-                if time_ms >= self.attack_ms + self.decay_ms {
+                if self.advance(self.sustain_target(), dt_us, self.decay_curve) {
                     self.state = AdsrState::Sustain;
-                    return self.sustain_level as u16;
                 }
-
-                return (((MAX_LEVEL
-                    - (time_ms - u32::min(self.attack_ms, time_ms))
-                        * (MAX_LEVEL - u32::min(self.sustain_level, MAX_LEVEL))
-                        / u32::max(self.decay_ms, 1))
-                    * self.velocity)
-                    / 127) as u16;
             }
             AdsrState::Sustain => {
+                self.level = self.sustain_target();
                 // check to see if the note has been released
                 if !self.triggered {
                     self.state = AdsrState::Release;
-                    self.time_us = 0;
+                    self.start_segment(0, self.release_ms, self.release_curve);
                 }
-
-                let sustain = u16::min(
-                    (((self.sustain_level * self.velocity) / 127) + self.aftertouch) as u16,
-                    MAX_LEVEL as u16,
-                );
-                return sustain;
             }
             AdsrState::Release => {
-                if time_ms > self.release_ms {
+                if self.advance(0, dt_us, self.release_curve) {
                     self.state = AdsrState::Done;
-                    self.time_us = 0;
-                    return 0;
                 }
-
-                let sustain_diff = time_ms * self.sustain_level / u32::max(self.release_ms, 1);
-                let value = ((self.sustain_level - sustain_diff) * self.velocity) / 127;
-                return value as u16;
             }
         }
+        ((((self.level >> PRECISION_BITS) as u32) * self.velocity) / 127) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Close to the audio sample interval, longer steps stretch the curved segments
+    const STEP_US: u32 = 25;
+
+    /// Time for the envelope to reach `level`, checked every `STEP_US`
+    fn time_to(adsr: &mut Adsr, level: u16) -> u32 {
+        let mut time_us = 0;
+        while adsr.update(STEP_US) != level {
+            time_us += STEP_US;
+            assert!(time_us < 10_000_000, "never reached {}", level);
+        }
+        time_us + STEP_US
+    }
+
+    /// Within 1% of the set time
+    fn on_time(time_us: u32, time_ms: u32) -> bool {
+        time_us.abs_diff(time_ms * 1000) <= time_ms * 10
+    }
+
+    fn envelope(attack_ms: u32, decay_ms: u32, sustain_level: u32, release_ms: u32) -> Adsr {
+        let mut adsr = Adsr::new();
+        adsr.set_attack(attack_ms);
+        adsr.set_decay(decay_ms);
+        adsr.set_sustain(sustain_level);
+        adsr.set_release(release_ms);
+        adsr
+    }
+
+    #[test]
+    fn zero_times_jump_in_one_update() {
+        let mut adsr = envelope(0, 0, MAX_LEVEL / 2, 0);
+        adsr.trigger(127);
+        assert_eq!(adsr.update(1), MAX_LEVEL as u16);
+        assert_eq!(adsr.update(1), (MAX_LEVEL / 2) as u16);
+        adsr.release();
+        adsr.update(1);
+        assert_eq!(adsr.update(1), 0);
+        assert!(adsr.is_done());
+    }
+
+    #[test]
+    fn straight_segments_take_their_time() {
+        let mut adsr = envelope(10, 20, 0, 30);
+        adsr.trigger(127);
+        assert!(on_time(time_to(&mut adsr, MAX_LEVEL as u16), 10));
+        assert!(on_time(time_to(&mut adsr, 0), 20));
+
+        let mut adsr = envelope(0, 0, MAX_LEVEL, 30);
+        adsr.trigger(127);
+        adsr.update(STEP_US);
+        adsr.release();
+        assert!(on_time(time_to(&mut adsr, 0), 30));
+    }
+
+    #[test]
+    fn extreme_curves_still_arrive_in_time() {
+        for curve in [-MAX_CURVE, MAX_CURVE - 1] {
+            let mut adsr = envelope(10, 20, 0, 0);
+            adsr.set_attack_curve(curve);
+            adsr.set_decay_curve(curve);
+            adsr.trigger(127);
+            assert!(on_time(time_to(&mut adsr, MAX_LEVEL as u16), 10));
+            assert!(on_time(time_to(&mut adsr, 0), 20));
+        }
+    }
+
+    #[test]
+    fn curves_bend_the_attack() {
+        let halfway = |curve: i8| {
+            let mut adsr = envelope(10, 0, MAX_LEVEL, 0);
+            adsr.set_attack_curve(curve);
+            adsr.trigger(127);
+            adsr.update(5_000)
+        };
+        let straight = halfway(0);
+        assert!(straight.abs_diff(MAX_LEVEL as u16 / 2) <= 1);
+        // An exponential attack rushes up like an analog envelope, a logarithmic one holds back
+        assert!(halfway(MAX_CURVE - 1) > straight + MAX_LEVEL as u16 / 4);
+        assert!(halfway(-MAX_CURVE) < straight - MAX_LEVEL as u16 / 4);
+    }
+
+    #[test]
+    fn velocity_scales_the_level() {
+        let mut adsr = envelope(0, 0, MAX_LEVEL, 0);
+        adsr.trigger(64);
+        assert_eq!(adsr.update(1), (MAX_LEVEL * 64 / 127) as u16);
     }
 }
@@ -0,0 +1,59 @@
+//! Tempo of an incoming MIDI clock, read from the raw USB MIDI event packets.
+
+/// USB MIDI code index number for single byte messages, used by system real-time messages
+const CIN_SINGLE_BYTE: u8 = 0xF;
+const TIMING_CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const STOP: u8 = 0xFC;
+const TICKS_PER_BEAT: u32 = 24;
+
+/// Measures the tempo over a whole beat, which evens out the jitter of the USB frames.
+pub struct MidiClock {
+    ticks: u32,
+    beat_start_us: u32,
+    tempo_bpm_tenths: u16,
+}
+
+impl MidiClock {
+    pub fn new() -> Self {
+        Self {
+            ticks: 0,
+            beat_start_us: 0,
+            tempo_bpm_tenths: 0,
+        }
+    }
+
+    /// Feed a 4 byte USB MIDI event packet, returns the tempo once per beat when it changes
+    pub fn read_packet(&mut self, packet: &[u8], time_us: u32) -> Option<u16> {
+        if packet[0] & 0x0F != CIN_SINGLE_BYTE {
+            return None;
+        }
+        match packet[1] {
+            TIMING_CLOCK => {}
+            START | STOP => {
+                self.ticks = 0;
+                return None;
+            }
+            _ => return None,
+        }
+
+        if self.ticks == 0 {
+            self.beat_start_us = time_us;
+        }
+        self.ticks += 1;
+        if self.ticks <= TICKS_PER_BEAT {
+            return None;
+        }
+
+        // The 25th tick starts the next beat
+        let beat_us = time_us.wrapping_sub(self.beat_start_us);
+        self.beat_start_us = time_us;
+        self.ticks = 1;
+        let tempo_bpm_tenths = (600_000_000 / u32::max(beat_us, 1)) as u16;
+        if tempo_bpm_tenths == self.tempo_bpm_tenths {
+            return None;
+        }
+        self.tempo_bpm_tenths = tempo_bpm_tenths;
+        Some(tempo_bpm_tenths)
+    }
+}
@@ -0,0 +1,964 @@
+use crate::additive::{DRAWBARS, MAX_TILT};
+use crate::adsr::MAX_CURVE;
+use crate::fm::MAX_RATIO_HALVES;
+use crate::intercore::{
+    DrawbarPreset, Engine, FilterMode, FilterType, FmAlgorithm, IntercoreMessage, LfoShape,
+    LfoSync, LfoTarget, ModalExciter, ModalMaterial, NoiseColor, OscillatorMode,
+    PressureDestination, ShaperMode, SubShape, Waveform,
+};
+use crate::synth::MAX_PARTS;
+use defmt::Format;
+
+/// Room reserved for assignments in the persisted settings, leaves space to add parameters.
+const MAX_PARAMETERS: usize = 128;
+/// Parameters whose assignments fit in the first settings page, the rest go in the extension
+const BASE_PARAMETERS: usize = 32;
+/// Marker for a parameter without a knob or CC assigned
+const UNASSIGNED: u8 = 0xFF;
+/// Number of ADS1x15 channels on each `Knobz` device
+const KNOBS_PER_BANK: u8 = 4;
+/// MIDI CC numbers with fixed meanings, never routed through the `ControlMap`
+pub const MOD_WHEEL_CC: u8 = 1;
+pub const SUSTAIN_PEDAL_CC: u8 = 64;
+pub const SOSTENUTO_PEDAL_CC: u8 = 66;
+pub const ALL_SOUND_OFF_CC: u8 = 120;
+pub const RESET_ALL_CONTROLLERS_CC: u8 = 121;
+pub const ALL_NOTES_OFF_CC: u8 = 123;
+/// Sound controller 5 (brightness), the slide dimension of MPE controllers
+pub const TIMBRE_CC: u8 = 74;
+/// Sound controller 2 (timbre/harmonic intensity), the GM2 filter resonance
+const RESONANCE_CC: u8 = 71;
+/// The nine drawbars are on the undefined CCs from here up to 110, in order
+const FIRST_DRAWBAR_CC: u8 = 102;
+/// CCs from here up are channel mode messages
+const FIRST_CHANNEL_MODE_CC: u8 = 120;
+/// Pedals read as pressed at or above this CC value
+const PEDAL_THRESHOLD: u8 = 64;
+/// Range of the second oscillator's tuning either side of the first
+const MAX_OSC2_COARSE_SEMITONES: i32 = 24;
+const MAX_OSC2_FINE_CENTS: i32 = 50;
+/// Time a button has to be stable before a change is accepted
+const DEBOUNCE_US: u32 = 20_000;
+
+/// A synth parameter that can be driven by a knob or a MIDI CC.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Waveform,
+    Portamento,
+    Timbre,
+    PressureDestination,
+    PressureDepth,
+    VibratoRate,
+    VibratoShape,
+    VibratoDelay,
+    /// The settings of the general purpose LFO with the given index
+    LfoRate(u8),
+    LfoShape(u8),
+    LfoDepth(u8),
+    LfoTarget(u8),
+    LfoSync(u8),
+    /// The knob source of the modulation matrix
+    ModKnob,
+    FilterMode,
+    FilterCutoff,
+    FilterResonance,
+    FilterKeyTrack,
+    FilterAttack,
+    FilterDecay,
+    FilterSustain,
+    FilterRelease,
+    FilterEnvDepth,
+    FilterType,
+    FilterDrive,
+    /// The second oscillator
+    Osc2Waveform,
+    Osc2Coarse,
+    Osc2Fine,
+    Osc2Level,
+    Osc2Sync,
+    Osc2Ring,
+    SubShape,
+    SubOctave,
+    SubLevel,
+    NoiseColor,
+    NoiseLevel,
+    PulseWidth,
+    Engine,
+    FmAlgorithm,
+    /// The settings of the FM operator with the given index
+    FmRatio(u8),
+    FmLevel(u8),
+    FmAttack(u8),
+    FmDecay(u8),
+    FmSustain(u8),
+    FmRelease(u8),
+    StringDamping,
+    StringDecay,
+    StringPickPosition,
+    OscillatorMode,
+    PhaseDistortion,
+    ShaperMode,
+    ShaperDrive,
+    Drawbar(u8),
+    DrawbarPreset,
+    DrawbarTilt,
+    ModalMaterial,
+    ModalExciter,
+    ModalBrightness,
+    ModalDecay,
+    AttackCurve,
+    DecayCurve,
+    ReleaseCurve,
+    /// The envelope that is only a modulation source
+    ModAttack,
+    ModDecay,
+    ModSustain,
+    ModRelease,
+}
+
+impl Format for Parameter {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Attack => defmt::write!(f, "Attack"),
+            Self::Decay => defmt::write!(f, "Decay"),
+            Self::Sustain => defmt::write!(f, "Sustain"),
+            Self::Release => defmt::write!(f, "Release"),
+            Self::Waveform => defmt::write!(f, "Waveform"),
+            Self::Portamento => defmt::write!(f, "Portamento"),
+            Self::Timbre => defmt::write!(f, "Timbre"),
+            Self::PressureDestination => defmt::write!(f, "PressureDestination"),
+            Self::PressureDepth => defmt::write!(f, "PressureDepth"),
+            Self::VibratoRate => defmt::write!(f, "VibratoRate"),
+            Self::VibratoShape => defmt::write!(f, "VibratoShape"),
+            Self::VibratoDelay => defmt::write!(f, "VibratoDelay"),
+            Self::LfoRate(lfo) => defmt::write!(f, "Lfo{}Rate", lfo + 1),
+            Self::LfoShape(lfo) => defmt::write!(f, "Lfo{}Shape", lfo + 1),
+            Self::LfoDepth(lfo) => defmt::write!(f, "Lfo{}Depth", lfo + 1),
+            Self::LfoTarget(lfo) => defmt::write!(f, "Lfo{}Target", lfo + 1),
+            Self::LfoSync(lfo) => defmt::write!(f, "Lfo{}Sync", lfo + 1),
+            Self::ModKnob => defmt::write!(f, "ModKnob"),
+            Self::FilterMode => defmt::write!(f, "FilterMode"),
+            Self::FilterCutoff => defmt::write!(f, "FilterCutoff"),
+            Self::FilterResonance => defmt::write!(f, "FilterResonance"),
+            Self::FilterKeyTrack => defmt::write!(f, "FilterKeyTrack"),
+            Self::FilterAttack => defmt::write!(f, "FilterAttack"),
+            Self::FilterDecay => defmt::write!(f, "FilterDecay"),
+            Self::FilterSustain => defmt::write!(f, "FilterSustain"),
+            Self::FilterRelease => defmt::write!(f, "FilterRelease"),
+            Self::FilterEnvDepth => defmt::write!(f, "FilterEnvDepth"),
+            Self::FilterType => defmt::write!(f, "FilterType"),
+            Self::FilterDrive => defmt::write!(f, "FilterDrive"),
+            Self::Osc2Waveform => defmt::write!(f, "Osc2Waveform"),
+            Self::Osc2Coarse => defmt::write!(f, "Osc2Coarse"),
+            Self::Osc2Fine => defmt::write!(f, "Osc2Fine"),
+            Self::Osc2Level => defmt::write!(f, "Osc2Level"),
+            Self::Osc2Sync => defmt::write!(f, "Osc2Sync"),
+            Self::Osc2Ring => defmt::write!(f, "Osc2Ring"),
+            Self::SubShape => defmt::write!(f, "SubShape"),
+            Self::SubOctave => defmt::write!(f, "SubOctave"),
+            Self::SubLevel => defmt::write!(f, "SubLevel"),
+            Self::NoiseColor => defmt::write!(f, "NoiseColor"),
+            Self::NoiseLevel => defmt::write!(f, "NoiseLevel"),
+            Self::PulseWidth => defmt::write!(f, "PulseWidth"),
+            Self::Engine => defmt::write!(f, "Engine"),
+            Self::FmAlgorithm => defmt::write!(f, "FmAlgorithm"),
+            Self::FmRatio(op) => defmt::write!(f, "FmOp{}Ratio", op + 1),
+            Self::FmLevel(op) => defmt::write!(f, "FmOp{}Level", op + 1),
+            Self::FmAttack(op) => defmt::write!(f, "FmOp{}Attack", op + 1),
+            Self::FmDecay(op) => defmt::write!(f, "FmOp{}Decay", op + 1),
+            Self::FmSustain(op) => defmt::write!(f, "FmOp{}Sustain", op + 1),
+            Self::FmRelease(op) => defmt::write!(f, "FmOp{}Release", op + 1),
+            Self::StringDamping => defmt::write!(f, "StringDamping"),
+            Self::StringDecay => defmt::write!(f, "StringDecay"),
+            Self::StringPickPosition => defmt::write!(f, "StringPickPosition"),
+            Self::OscillatorMode => defmt::write!(f, "OscillatorMode"),
+            Self::PhaseDistortion => defmt::write!(f, "PhaseDistortion"),
+            Self::ShaperMode => defmt::write!(f, "ShaperMode"),
+            Self::ShaperDrive => defmt::write!(f, "ShaperDrive"),
+            Self::Drawbar(drawbar) => defmt::write!(f, "Drawbar{}", drawbar + 1),
+            Self::DrawbarPreset => defmt::write!(f, "DrawbarPreset"),
+            Self::DrawbarTilt => defmt::write!(f, "DrawbarTilt"),
+            Self::ModalMaterial => defmt::write!(f, "ModalMaterial"),
+            Self::ModalExciter => defmt::write!(f, "ModalExciter"),
+            Self::ModalBrightness => defmt::write!(f, "ModalBrightness"),
+            Self::ModalDecay => defmt::write!(f, "ModalDecay"),
+            Self::AttackCurve => defmt::write!(f, "AttackCurve"),
+            Self::DecayCurve => defmt::write!(f, "DecayCurve"),
+            Self::ReleaseCurve => defmt::write!(f, "ReleaseCurve"),
+            Self::ModAttack => defmt::write!(f, "ModAttack"),
+            Self::ModDecay => defmt::write!(f, "ModDecay"),
+            Self::ModSustain => defmt::write!(f, "ModSustain"),
+            Self::ModRelease => defmt::write!(f, "ModRelease"),
+        }
+    }
+}
+
+impl Parameter {
+    /// Every parameter, in the order they are stepped through in learn mode
+    pub const ALL: [Parameter; 101] = [
+        Self::Attack,
+        Self::Decay,
+        Self::Sustain,
+        Self::Release,
+        Self::Waveform,
+        Self::Portamento,
+        Self::Timbre,
+        Self::PressureDestination,
+        Self::PressureDepth,
+        Self::VibratoRate,
+        Self::VibratoShape,
+        Self::VibratoDelay,
+        Self::LfoRate(0),
+        Self::LfoShape(0),
+        Self::LfoDepth(0),
+        Self::LfoTarget(0),
+        Self::LfoSync(0),
+        Self::LfoRate(1),
+        Self::LfoShape(1),
+        Self::LfoDepth(1),
+        Self::LfoTarget(1),
+        Self::LfoSync(1),
+        Self::ModKnob,
+        Self::FilterMode,
+        Self::FilterCutoff,
+        Self::FilterResonance,
+        Self::FilterKeyTrack,
+        Self::FilterAttack,
+        Self::FilterDecay,
+        Self::FilterSustain,
+        Self::FilterRelease,
+        Self::FilterEnvDepth,
+        Self::FilterType,
+        Self::FilterDrive,
+        Self::Osc2Waveform,
+        Self::Osc2Coarse,
+        Self::Osc2Fine,
+        Self::Osc2Level,
+        Self::Osc2Sync,
+        Self::Osc2Ring,
+        Self::SubShape,
+        Self::SubOctave,
+        Self::SubLevel,
+        Self::NoiseColor,
+        Self::NoiseLevel,
+        Self::PulseWidth,
+        Self::Engine,
+        Self::FmAlgorithm,
+        Self::FmRatio(0),
+        Self::FmLevel(0),
+        Self::FmAttack(0),
+        Self::FmDecay(0),
+        Self::FmSustain(0),
+        Self::FmRelease(0),
+        Self::FmRatio(1),
+        Self::FmLevel(1),
+        Self::FmAttack(1),
+        Self::FmDecay(1),
+        Self::FmSustain(1),
+        Self::FmRelease(1),
+        Self::FmRatio(2),
+        Self::FmLevel(2),
+        Self::FmAttack(2),
+        Self::FmDecay(2),
+        Self::FmSustain(2),
+        Self::FmRelease(2),
+        Self::FmRatio(3),
+        Self::FmLevel(3),
+        Self::FmAttack(3),
+        Self::FmDecay(3),
+        Self::FmSustain(3),
+        Self::FmRelease(3),
+        Self::StringDamping,
+        Self::StringDecay,
+        Self::StringPickPosition,
+        Self::OscillatorMode,
+        Self::PhaseDistortion,
+        Self::ShaperMode,
+        Self::ShaperDrive,
+        Self::Drawbar(0),
+        Self::Drawbar(1),
+        Self::Drawbar(2),
+        Self::Drawbar(3),
+        Self::Drawbar(4),
+        Self::Drawbar(5),
+        Self::Drawbar(6),
+        Self::Drawbar(7),
+        Self::Drawbar(8),
+        Self::DrawbarPreset,
+        Self::DrawbarTilt,
+        Self::ModalMaterial,
+        Self::ModalExciter,
+        Self::ModalBrightness,
+        Self::ModalDecay,
+        Self::AttackCurve,
+        Self::DecayCurve,
+        Self::ReleaseCurve,
+        Self::ModAttack,
+        Self::ModDecay,
+        Self::ModSustain,
+        Self::ModRelease,
+    ];
+
+    fn index(&self) -> usize {
+        match self {
+            Self::Attack => 0,
+            Self::Decay => 1,
+            Self::Sustain => 2,
+            Self::Release => 3,
+            Self::Waveform => 4,
+            Self::Portamento => 5,
+            Self::Timbre => 6,
+            Self::PressureDestination => 7,
+            Self::PressureDepth => 8,
+            Self::VibratoRate => 9,
+            Self::VibratoShape => 10,
+            Self::VibratoDelay => 11,
+            Self::LfoRate(lfo) => 12 + 5 * *lfo as usize,
+            Self::LfoShape(lfo) => 13 + 5 * *lfo as usize,
+            Self::LfoDepth(lfo) => 14 + 5 * *lfo as usize,
+            Self::LfoTarget(lfo) => 15 + 5 * *lfo as usize,
+            Self::LfoSync(lfo) => 16 + 5 * *lfo as usize,
+            Self::ModKnob => 22,
+            Self::FilterMode => 23,
+            Self::FilterCutoff => 24,
+            Self::FilterResonance => 25,
+            Self::FilterKeyTrack => 26,
+            Self::FilterAttack => 27,
+            Self::FilterDecay => 28,
+            Self::FilterSustain => 29,
+            Self::FilterRelease => 30,
+            Self::FilterEnvDepth => 31,
+            Self::FilterType => 32,
+            Self::FilterDrive => 33,
+            Self::Osc2Waveform => 34,
+            Self::Osc2Coarse => 35,
+            Self::Osc2Fine => 36,
+            Self::Osc2Level => 37,
+            Self::Osc2Sync => 38,
+            Self::Osc2Ring => 39,
+            Self::SubShape => 40,
+            Self::SubOctave => 41,
+            Self::SubLevel => 42,
+            Self::NoiseColor => 43,
+            Self::NoiseLevel => 44,
+            Self::PulseWidth => 45,
+            Self::Engine => 46,
+            Self::FmAlgorithm => 47,
+            Self::FmRatio(op) => 48 + 6 * *op as usize,
+            Self::FmLevel(op) => 49 + 6 * *op as usize,
+            Self::FmAttack(op) => 50 + 6 * *op as usize,
+            Self::FmDecay(op) => 51 + 6 * *op as usize,
+            Self::FmSustain(op) => 52 + 6 * *op as usize,
+            Self::FmRelease(op) => 53 + 6 * *op as usize,
+            Self::StringDamping => 72,
+            Self::StringDecay => 73,
+            Self::StringPickPosition => 74,
+            Self::OscillatorMode => 75,
+            Self::PhaseDistortion => 76,
+            Self::ShaperMode => 77,
+            Self::ShaperDrive => 78,
+            Self::Drawbar(drawbar) => 79 + *drawbar as usize,
+            Self::DrawbarPreset => 88,
+            Self::DrawbarTilt => 89,
+            Self::ModalMaterial => 90,
+            Self::ModalExciter => 91,
+            Self::ModalBrightness => 92,
+            Self::ModalDecay => 93,
+            Self::AttackCurve => 94,
+            Self::DecayCurve => 95,
+            Self::ReleaseCurve => 96,
+            Self::ModAttack => 97,
+            Self::ModDecay => 98,
+            Self::ModSustain => 99,
+            Self::ModRelease => 100,
+        }
+    }
+
+    /// Build the message for core 1 from a control value in the range 0..=1023
+    pub fn message(&self, value: u16) -> IntercoreMessage {
+        match self {
+            Self::Attack => IntercoreMessage::AttackControl {
+                attack_ms: value >> 2,
+            },
+            Self::Decay => IntercoreMessage::DecayControl {
+                decay_ms: value >> 2,
+            },
+            Self::Sustain => IntercoreMessage::SustainControl {
+                sustain_level: value,
+            },
+            Self::Release => IntercoreMessage::ReleaseControl {
+                release_ms: value >> 2,
+            },
+            Self::Waveform => IntercoreMessage::WaveformControl {
+                waveform: Waveform::from_u8((value >> 2) as u8).unwrap_or_default(),
+            },
+            Self::Portamento => IntercoreMessage::PortamentoControl {
+                portamento_time_ms: value,
+            },
+            Self::Timbre => IntercoreMessage::Timbre {
+                timbre: (value >> 3) as u8,
+            },
+            Self::PressureDestination => IntercoreMessage::PressureDestination {
+                destination: PressureDestination::from_u8((value >> 2) as u8),
+            },
+            Self::PressureDepth => IntercoreMessage::PressureDepth {
+                depth: (value >> 3) as u8,
+            },
+            // 0.1Hz to about 20Hz
+            Self::VibratoRate => IntercoreMessage::VibratoRate {
+                rate_centihertz: 10 + value * 2,
+            },
+            Self::VibratoShape => IntercoreMessage::VibratoShape {
+                shape: LfoShape::from_u8((value >> 2) as u8),
+            },
+            Self::VibratoDelay => IntercoreMessage::VibratoDelay {
+                delay_ms: value * 2,
+            },
+            // Finer steps at the slow end, up to about 20Hz
+            Self::LfoRate(lfo) => IntercoreMessage::LfoRate {
+                lfo: *lfo,
+                rate_centihertz: 10 + ((value as u32 * value as u32) >> 9) as u16,
+            },
+            Self::LfoShape(lfo) => IntercoreMessage::LfoShape {
+                lfo: *lfo,
+                shape: LfoShape::from_u8((value >> 2) as u8),
+            },
+            Self::LfoDepth(lfo) => IntercoreMessage::LfoDepth {
+                lfo: *lfo,
+                depth: (value >> 3) as u8,
+            },
+            Self::LfoTarget(lfo) => IntercoreMessage::LfoTarget {
+                lfo: *lfo,
+                target: LfoTarget::from_u8((value >> 2) as u8),
+            },
+            Self::LfoSync(lfo) => IntercoreMessage::LfoSync {
+                lfo: *lfo,
+                sync: LfoSync::OPTIONS[value as usize * LfoSync::OPTIONS.len() / 1024],
+            },
+            Self::ModKnob => IntercoreMessage::ModKnob {
+                value: (value >> 3) as u8,
+            },
+            Self::FilterMode => IntercoreMessage::FilterMode {
+                mode: FilterMode::from_u8((value >> 2) as u8),
+            },
+            Self::FilterCutoff => IntercoreMessage::FilterCutoff {
+                cutoff: (value >> 3) as u8,
+            },
+            Self::FilterResonance => IntercoreMessage::FilterResonance {
+                resonance: (value >> 3) as u8,
+            },
+            Self::FilterKeyTrack => IntercoreMessage::FilterKeyTrack {
+                amount: (value >> 3) as u8,
+            },
+            Self::FilterAttack => IntercoreMessage::FilterAttack {
+                attack_ms: value >> 2,
+            },
+            Self::FilterDecay => IntercoreMessage::FilterDecay {
+                decay_ms: value >> 2,
+            },
+            // Up to the full 12 bit envelope level
+            Self::FilterSustain => IntercoreMessage::FilterSustain {
+                sustain_level: value << 2,
+            },
+            Self::FilterRelease => IntercoreMessage::FilterRelease {
+                release_ms: value >> 2,
+            },
+            Self::FilterEnvDepth => IntercoreMessage::FilterEnvDepth {
+                depth: (value >> 3) as u8,
+            },
+            Self::FilterType => IntercoreMessage::FilterType {
+                filter_type: FilterType::from_u8((value >> 2) as u8),
+            },
+            Self::FilterDrive => IntercoreMessage::FilterDrive {
+                drive: (value >> 3) as u8,
+            },
+            Self::Osc2Waveform => IntercoreMessage::Osc2Waveform {
+                waveform: Waveform::from_u8((value >> 2) as u8).unwrap_or_default(),
+            },
+            Self::Osc2Coarse => IntercoreMessage::Osc2Coarse {
+                semitones: (value as i32 * (2 * MAX_OSC2_COARSE_SEMITONES + 1) / 1024
+                    - MAX_OSC2_COARSE_SEMITONES) as i8,
+            },
+            Self::Osc2Fine => IntercoreMessage::Osc2Fine {
+                cents: (value as i32 * (2 * MAX_OSC2_FINE_CENTS + 1) / 1024 - MAX_OSC2_FINE_CENTS)
+                    as i8,
+            },
+            Self::Osc2Level => IntercoreMessage::Osc2Level {
+                level: (value >> 3) as u8,
+            },
+            // Switches are on in the top half of the range
+            Self::Osc2Sync => IntercoreMessage::Osc2Sync { on: value >= 512 },
+            Self::Osc2Ring => IntercoreMessage::Osc2Ring { on: value >= 512 },
+            Self::SubShape => IntercoreMessage::SubShape {
+                shape: SubShape::from_u8((value >> 2) as u8),
+            },
+            // One octave down in the bottom half of the range, two in the top
+            Self::SubOctave => IntercoreMessage::SubOctave {
+                octaves: 1 + (value >= 512) as u8,
+            },
+            Self::SubLevel => IntercoreMessage::SubLevel {
+                level: (value >> 3) as u8,
+            },
+            Self::NoiseColor => IntercoreMessage::NoiseColor {
+                color: NoiseColor::from_u8((value >> 2) as u8),
+            },
+            Self::NoiseLevel => IntercoreMessage::NoiseLevel {
+                level: (value >> 3) as u8,
+            },
+            Self::PulseWidth => IntercoreMessage::PulseWidth {
+                width: (value >> 3) as u8,
+            },
+            Self::Engine => IntercoreMessage::Engine {
+                engine: Engine::from_u8((value >> 2) as u8),
+            },
+            Self::FmAlgorithm => IntercoreMessage::FmAlgorithm {
+                algorithm: FmAlgorithm::from_u8((value >> 2) as u8),
+            },
+            // Half steps from an octave down up to 16 times the note frequency
+            Self::FmRatio(op) => IntercoreMessage::FmRatio {
+                op: *op,
+                ratio_halves: 1 + (value as u32 * MAX_RATIO_HALVES as u32 / 1024) as u8,
+            },
+            Self::FmLevel(op) => IntercoreMessage::FmLevel {
+                op: *op,
+                level: (value >> 3) as u8,
+            },
+            Self::FmAttack(op) => IntercoreMessage::FmAttack {
+                op: *op,
+                attack_ms: value >> 2,
+            },
+            Self::FmDecay(op) => IntercoreMessage::FmDecay {
+                op: *op,
+                decay_ms: value >> 2,
+            },
+            Self::FmSustain(op) => IntercoreMessage::FmSustain {
+                op: *op,
+                sustain_level: value << 2,
+            },
+            Self::FmRelease(op) => IntercoreMessage::FmRelease {
+                op: *op,
+                release_ms: value >> 2,
+            },
+            Self::StringDamping => IntercoreMessage::StringDamping {
+                damping: (value >> 3) as u8,
+            },
+            Self::StringDecay => IntercoreMessage::StringDecay {
+                decay: (value >> 3) as u8,
+            },
+            Self::StringPickPosition => IntercoreMessage::StringPickPosition {
+                position: (value >> 3) as u8,
+            },
+            Self::OscillatorMode => IntercoreMessage::OscillatorMode {
+                mode: OscillatorMode::from_u8((value >> 2) as u8),
+            },
+            Self::PhaseDistortion => IntercoreMessage::PhaseDistortion {
+                amount: (value >> 3) as u8,
+            },
+            Self::ShaperMode => IntercoreMessage::ShaperMode {
+                mode: ShaperMode::from_u8((value >> 2) as u8),
+            },
+            Self::ShaperDrive => IntercoreMessage::ShaperDrive {
+                drive: (value >> 3) as u8,
+            },
+            Self::Drawbar(drawbar) => IntercoreMessage::Drawbar {
+                drawbar: *drawbar,
+                level: (value >> 3) as u8,
+            },
+            Self::DrawbarPreset => IntercoreMessage::DrawbarPreset {
+                preset: DrawbarPreset::from_u8((value >> 2) as u8),
+            },
+            Self::DrawbarTilt => IntercoreMessage::DrawbarTilt {
+                tilt: ((value >> 3) as i32 - MAX_TILT as i32) as i8,
+            },
+            Self::ModalMaterial => IntercoreMessage::ModalMaterial {
+                material: ModalMaterial::from_u8((value >> 2) as u8),
+            },
+            Self::ModalExciter => IntercoreMessage::ModalExciter {
+                exciter: ModalExciter::from_u8((value >> 2) as u8),
+            },
+            Self::ModalBrightness => IntercoreMessage::ModalBrightness {
+                brightness: (value >> 3) as u8,
+            },
+            Self::ModalDecay => IntercoreMessage::ModalDecay {
+                decay: (value >> 3) as u8,
+            },
+            Self::AttackCurve => IntercoreMessage::AttackCurve {
+                curve: ((value >> 3) as i32 - MAX_CURVE as i32) as i8,
+            },
+            Self::DecayCurve => IntercoreMessage::DecayCurve {
+                curve: ((value >> 3) as i32 - MAX_CURVE as i32) as i8,
+            },
+            Self::ReleaseCurve => IntercoreMessage::ReleaseCurve {
+                curve: ((value >> 3) as i32 - MAX_CURVE as i32) as i8,
+            },
+            Self::ModAttack => IntercoreMessage::ModAttack {
+                attack_ms: value >> 2,
+            },
+            Self::ModDecay => IntercoreMessage::ModDecay {
+                decay_ms: value >> 2,
+            },
+            // Up to the full 12 bit envelope level
+            Self::ModSustain => IntercoreMessage::ModSustain {
+                sustain_level: value << 2,
+            },
+            Self::ModRelease => IntercoreMessage::ModRelease {
+                release_ms: value >> 2,
+            },
+        }
+    }
+}
+
+/// Where a control change came from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ControlSource {
+    /// A physical knob, numbered `bank * 4 + channel` across the `Knobz` devices
+    Knob(u8),
+    /// An incoming MIDI control change number
+    ControlChange(u8),
+}
+
+impl Format for ControlSource {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Knob(knob) => defmt::write!(f, "Knob({})", knob),
+            Self::ControlChange(cc) => defmt::write!(f, "CC({})", cc),
+        }
+    }
+}
+
+impl ControlSource {
+    pub fn knob(bank: u8, channel: knobz::Channel) -> Self {
+        let channel = match channel {
+            knobz::Channel::A0 => 0,
+            knobz::Channel::A1 => 1,
+            knobz::Channel::A2 => 2,
+            knobz::Channel::A3 => 3,
+        };
+        Self::Knob(bank * KNOBS_PER_BANK + channel)
+    }
+
+    /// Scale the raw value from this source to the range 0..=1023
+    pub fn scale(&self, value: u16) -> u16 {
+        match self {
+            Self::Knob(_) => u16::min(value, 1023),
+            Self::ControlChange(_) => {
+                let value = u16::min(value, 127);
+                (value << 3) | (value >> 4)
+            }
+        }
+    }
+}
+
+pub fn pedal_pressed(value: u8) -> bool {
+    value >= PEDAL_THRESHOLD
+}
+
+pub fn is_channel_mode(control: u8) -> bool {
+    control >= FIRST_CHANNEL_MODE_CC
+}
+
+/// The MIDI channel(s) notes and controllers are received on.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReceiveChannel {
+    /// A single channel, 0..=15
+    Channel(u8),
+    Omni,
+}
+
+impl Format for ReceiveChannel {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Channel(channel) => defmt::write!(f, "Channel {}", channel + 1),
+            Self::Omni => defmt::write!(f, "Omni"),
+        }
+    }
+}
+
+impl ReceiveChannel {
+    /// Byte used in the settings, zero is channel 1 to match a blank settings page
+    const OMNI_BYTE: u8 = 0x10;
+    /// Data byte used in SysEx commands
+    const OMNI_SYSEX: u8 = 0x7F;
+
+    pub fn accepts(&self, channel: u8) -> bool {
+        match self {
+            Self::Channel(receive_channel) => *receive_channel == channel,
+            Self::Omni => true,
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Channel(channel) => *channel,
+            Self::Omni => Self::OMNI_BYTE,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..=15 => Self::Channel(byte),
+            _ => Self::Omni,
+        }
+    }
+
+    pub fn from_sysex(byte: u8) -> Option<Self> {
+        match byte {
+            0..=15 => Some(Self::Channel(byte)),
+            Self::OMNI_SYSEX => Some(Self::Omni),
+            _ => None,
+        }
+    }
+}
+
+/// MIDI channels and voice reserves of the parts of the multi-timbral synth.
+///
+/// Part 0 always listens on the receive channel, the other parts are only used when `count`
+/// includes them.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PartMap {
+    count: u8,
+    channels: [u8; MAX_PARTS],
+    reserves: [u8; MAX_PARTS],
+}
+
+impl PartMap {
+    pub const SIZE: usize = 1 + 2 * MAX_PARTS;
+
+    pub fn new() -> Self {
+        let mut channels = [0; MAX_PARTS];
+        for (part, channel) in channels.iter_mut().enumerate() {
+            *channel = part as u8;
+        }
+        Self {
+            count: 1,
+            channels,
+            reserves: [0; MAX_PARTS],
+        }
+    }
+
+    pub fn count(&self) -> u8 {
+        self.count
+    }
+
+    /// Find the part a MIDI channel plays.
+    ///
+    /// The extra parts are checked first, so an omni part 0 picks up the channels they leave.
+    pub fn part(&self, receive_channel: ReceiveChannel, channel: u8) -> Option<u8> {
+        let extra_part = (1..self.count).find(|part| self.channels[*part as usize] == channel);
+        match extra_part {
+            Some(part) => Some(part),
+            None if receive_channel.accepts(channel) => Some(0),
+            None => None,
+        }
+    }
+
+    pub fn set_count(&mut self, count: u8) {
+        self.count = count.clamp(1, MAX_PARTS as u8);
+    }
+
+    pub fn set_channel(&mut self, part: u8, channel: u8) {
+        if let Some(part_channel) = self.channels.get_mut(part as usize) {
+            *part_channel = channel;
+        }
+    }
+
+    pub fn reserve(&self, part: u8) -> u8 {
+        self.reserves.get(part as usize).copied().unwrap_or(0)
+    }
+
+    pub fn set_reserve(&mut self, part: u8, voices: u8) {
+        if let Some(reserve) = self.reserves.get_mut(part as usize) {
+            *reserve = voices;
+        }
+    }
+
+    pub fn to_bytes(&self, bytes: &mut [u8]) {
+        bytes[0] = self.count;
+        bytes[1..1 + MAX_PARTS].copy_from_slice(&self.channels);
+        bytes[1 + MAX_PARTS..Self::SIZE].copy_from_slice(&self.reserves);
+    }
+
+    /// Settings saved before parts existed have a zero count and load the defaults
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut part_map = Self::new();
+        if bytes[0] == 0 {
+            return part_map;
+        }
+        part_map.set_count(bytes[0]);
+        part_map.channels.copy_from_slice(&bytes[1..1 + MAX_PARTS]);
+        part_map
+            .reserves
+            .copy_from_slice(&bytes[1 + MAX_PARTS..Self::SIZE]);
+        part_map
+    }
+}
+
+/// Assignment of knobs and MIDI CCs to synth parameters.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ControlMap {
+    knobs: [u8; MAX_PARAMETERS],
+    control_changes: [u8; MAX_PARAMETERS],
+}
+
+impl ControlMap {
+    /// The factory layout of the front panel, with the GM2 sound controller CCs
+    pub fn new() -> Self {
+        let mut control_map = Self {
+            knobs: [UNASSIGNED; MAX_PARAMETERS],
+            control_changes: [UNASSIGNED; MAX_PARAMETERS],
+        };
+        control_map.assign(Parameter::Attack, ControlSource::Knob(0));
+        control_map.assign(Parameter::Decay, ControlSource::Knob(1));
+        control_map.assign(Parameter::Sustain, ControlSource::Knob(2));
+        control_map.assign(Parameter::Release, ControlSource::Knob(3));
+        control_map.assign(Parameter::Waveform, ControlSource::Knob(6));
+        control_map.assign(Parameter::Portamento, ControlSource::Knob(7));
+        control_map.assign(Parameter::Attack, ControlSource::ControlChange(73));
+        control_map.assign(Parameter::Decay, ControlSource::ControlChange(75));
+        control_map.assign(Parameter::Release, ControlSource::ControlChange(72));
+        control_map.assign(Parameter::Portamento, ControlSource::ControlChange(5));
+        control_map.assign(Parameter::Timbre, ControlSource::ControlChange(TIMBRE_CC));
+        control_map.assign(
+            Parameter::FilterResonance,
+            ControlSource::ControlChange(RESONANCE_CC),
+        );
+        for drawbar in 0..DRAWBARS as u8 {
+            control_map.assign(
+                Parameter::Drawbar(drawbar),
+                ControlSource::ControlChange(FIRST_DRAWBAR_CC + drawbar),
+            );
+        }
+        control_map
+    }
+
+    /// Find the parameter assigned to a source, if any
+    pub fn parameter(&self, source: ControlSource) -> Option<Parameter> {
+        let (assignments, number) = match source {
+            ControlSource::Knob(knob) => (&self.knobs, knob),
+            ControlSource::ControlChange(cc) => (&self.control_changes, cc),
+        };
+        Parameter::ALL
+            .iter()
+            .find(|parameter| assignments[parameter.index()] == number)
+            .copied()
+    }
+
+    /// Assign a source to a parameter, removing it from any parameter it was previously driving
+    pub fn assign(&mut self, parameter: Parameter, source: ControlSource) {
+        let (assignments, number) = match source {
+            ControlSource::Knob(knob) => (&mut self.knobs, knob),
+            ControlSource::ControlChange(cc) => (&mut self.control_changes, cc),
+        };
+        for assignment in assignments.iter_mut() {
+            if *assignment == number {
+                *assignment = UNASSIGNED;
+            }
+        }
+        assignments[parameter.index()] = number;
+    }
+
+    /// Bytes taken in the first settings page
+    pub const SIZE: usize = 2 * BASE_PARAMETERS;
+    pub const EXTENSION_SIZE: usize = 2 * (MAX_PARAMETERS - BASE_PARAMETERS);
+
+    pub fn to_bytes(&self, bytes: &mut [u8], extension: &mut [u8]) {
+        const EXTENSION_PARAMETERS: usize = MAX_PARAMETERS - BASE_PARAMETERS;
+        bytes[..BASE_PARAMETERS].copy_from_slice(&self.knobs[..BASE_PARAMETERS]);
+        bytes[BASE_PARAMETERS..Self::SIZE]
+            .copy_from_slice(&self.control_changes[..BASE_PARAMETERS]);
+        extension[..EXTENSION_PARAMETERS].copy_from_slice(&self.knobs[BASE_PARAMETERS..]);
+        extension[EXTENSION_PARAMETERS..Self::EXTENSION_SIZE]
+            .copy_from_slice(&self.control_changes[BASE_PARAMETERS..]);
+    }
+
+    /// Settings saved without the extension leave the later parameters unassigned
+    pub fn from_bytes(bytes: &[u8], extension: Option<&[u8]>) -> Self {
+        const EXTENSION_PARAMETERS: usize = MAX_PARAMETERS - BASE_PARAMETERS;
+        let mut control_map = Self {
+            knobs: [UNASSIGNED; MAX_PARAMETERS],
+            control_changes: [UNASSIGNED; MAX_PARAMETERS],
+        };
+        control_map.knobs[..BASE_PARAMETERS].copy_from_slice(&bytes[..BASE_PARAMETERS]);
+        control_map.control_changes[..BASE_PARAMETERS]
+            .copy_from_slice(&bytes[BASE_PARAMETERS..Self::SIZE]);
+        if let Some(extension) = extension {
+            control_map.knobs[BASE_PARAMETERS..]
+                .copy_from_slice(&extension[..EXTENSION_PARAMETERS]);
+            control_map.control_changes[BASE_PARAMETERS..]
+                .copy_from_slice(&extension[EXTENSION_PARAMETERS..Self::EXTENSION_SIZE]);
+        }
+        control_map
+    }
+}
+
+/// MIDI learn state: a parameter is selected, then the next knob or CC that moves is assigned to it.
+pub struct MidiLearn {
+    selected: Option<Parameter>,
+}
+
+impl MidiLearn {
+    pub fn new() -> Self {
+        Self { selected: None }
+    }
+
+    /// Step to the next parameter to learn, leaving learn mode after the last one
+    pub fn select_next(&mut self) -> Option<Parameter> {
+        self.selected = match self.selected {
+            None => Some(Parameter::ALL[0]),
+            Some(parameter) => Parameter::ALL.get(parameter.index() + 1).copied(),
+        };
+        self.selected
+    }
+
+    /// Assign the source to the selected parameter and leave learn mode.
+    ///
+    /// Returns the parameter when an assignment was made.
+    pub fn capture(
+        &mut self,
+        control_map: &mut ControlMap,
+        source: ControlSource,
+    ) -> Option<Parameter> {
+        let parameter = self.selected.take()?;
+        control_map.assign(parameter, source);
+        Some(parameter)
+    }
+}
+
+/// Debounced push button.
+pub struct Button {
+    pressed: bool,
+    stable_time_us: u32,
+}
+
+impl Button {
+    /// Start from the current state, so a button held at power up does not register a press
+    pub fn new(pressed: bool) -> Self {
+        Self {
+            pressed,
+            stable_time_us: 0,
+        }
+    }
+
+    /// Feed the raw button state, returns true once for every press
+    pub fn update(&mut self, pressed: bool, elapsed_time_us: u32) -> bool {
+        if pressed == self.pressed {
+            self.stable_time_us = 0;
+            return false;
+        }
+        self.stable_time_us += elapsed_time_us;
+        if self.stable_time_us < DEBOUNCE_US {
+            return false;
+        }
+        self.pressed = pressed;
+        self.stable_time_us = 0;
+        pressed
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+}
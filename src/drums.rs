@@ -0,0 +1,252 @@
+//! Drum machine voices, played from the GM drum channel beside the melodic voices.
+//!
+//! Each drum is synthesized from a sine and the noise source with exponential decays, one voice
+//! per drum so a new hit restarts it. The closed hi-hat chokes the open one, as on a real kit.
+use crate::fm::sine;
+use crate::intercore::Drum;
+use crate::noise::Noise;
+
+/// MIDI channel 10, which GM reserves for drums
+pub const DRUM_CHANNEL: u8 = 9;
+const DRUMS: usize = 5;
+/// Full scale of the envelopes, 16.16 fixed point
+const ENVELOPE_MAX: i32 = 1 << 16;
+/// Bits of extra precision the decays are computed with, so they stay exponential to the end
+const DECAY_PRECISION_BITS: u32 = 8;
+const SAMPLE_CENTER: i32 = 128;
+/// The drums are mixed quieter than a full scale voice, several can sound at once
+const MIX_SHIFT: u32 = 1;
+/// Phase increment per microsecond of a 1Hz sine, 2^32 / 1_000_000
+const PHASE_PER_US_HZ: u32 = 4295;
+/// Bits of the fraction the decay rates are kept with
+const RATE_BITS: u32 = 24;
+const KICK_START_HZ: u32 = 160;
+const KICK_END_HZ: u32 = 45;
+const KICK_PITCH_DECAY_US: u32 = 40_000;
+const KICK_DECAY_US: u32 = 350_000;
+const SNARE_HZ: u32 = 185;
+const SNARE_TONE_DECAY_US: u32 = 60_000;
+const SNARE_NOISE_DECAY_US: u32 = 150_000;
+const CLOSED_HAT_DECAY_US: u32 = 40_000;
+const OPEN_HAT_DECAY_US: u32 = 400_000;
+/// A clap is a few quick bursts of noise, then a longer tail
+const CLAP_BURSTS: u32 = 3;
+const CLAP_BURST_US: u32 = 10_000;
+const CLAP_BURST_DECAY_US: u32 = 4_000;
+const CLAP_DECAY_US: u32 = 150_000;
+
+/// The drum a note of the GM drum map plays, if any
+pub fn gm_drum(note: u8) -> Option<Drum> {
+    match note {
+        35 | 36 => Some(Drum::Kick),
+        38 | 40 => Some(Drum::Snare),
+        39 => Some(Drum::Clap),
+        42 | 44 => Some(Drum::ClosedHat),
+        46 => Some(Drum::OpenHat),
+        _ => None,
+    }
+}
+
+/// Mix the synth voices with the drums in drum mode. Both are taken off the center of the output
+/// and summed at half scale, so the two at full level still fit, then put back on the center.
+pub fn mix(synth: u8, drums: u8) -> u8 {
+    let sum = (synth as i32 - SAMPLE_CENTER) + (drums as i32 - SAMPLE_CENTER);
+    (SAMPLE_CENTER + (sum >> 1)) as u8
+}
+
+/// Exponential decay from full scale
+struct Decay {
+    level: i32,
+    /// Part of the level lost per microsecond, as a fraction of `RATE_BITS` bits
+    rate: u32,
+}
+
+impl Decay {
+    const fn new(decay_us: u32) -> Self {
+        Self {
+            level: 0,
+            rate: (1 << RATE_BITS) / decay_us,
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.level = ENVELOPE_MAX << DECAY_PRECISION_BITS;
+    }
+
+    fn is_done(&self) -> bool {
+        self.level == 0
+    }
+
+    /// Decay by the elapsed time and return the level, the time constant is long against an
+    /// update
+    fn update(&mut self, elapsed_time_us: u32) -> i32 {
+        let rate = u32::min(self.rate.saturating_mul(elapsed_time_us), 1 << RATE_BITS);
+        let fall = ((self.level as i64 * rate as i64) >> RATE_BITS) as i32;
+        // Make sure it reaches zero rather than stalling on the rounding
+        self.level = i32::max(self.level - i32::max(fall, 1), 0);
+        self.level >> DECAY_PRECISION_BITS
+    }
+}
+
+struct DrumVoice {
+    drum: Drum,
+    amplitude: Decay,
+    /// Pitch of the kick, or level of the snare's tone
+    secondary: Decay,
+    phase: u32,
+    /// Time since the hit, for the clap's bursts
+    time_us: u32,
+    /// Level of the hit as 8.8 fixed point
+    velocity_gain: i32,
+    /// Previous noise sample, for the hats' highpass
+    previous_noise: i32,
+}
+
+impl DrumVoice {
+    const fn new(drum: Drum, decay_us: u32, secondary_decay_us: u32) -> Self {
+        Self {
+            drum,
+            amplitude: Decay::new(decay_us),
+            secondary: Decay::new(secondary_decay_us),
+            phase: 0,
+            time_us: 0,
+            velocity_gain: 0,
+            previous_noise: 0,
+        }
+    }
+
+    fn trigger(&mut self, velocity: u8) {
+        self.amplitude.trigger();
+        self.secondary.trigger();
+        self.phase = 0;
+        self.time_us = 0;
+        self.velocity_gain = ((velocity as i32) << 8) / 127;
+    }
+
+    fn stop(&mut self) {
+        self.amplitude.level = 0;
+    }
+
+    /// Advance the sine by the elapsed time at `hz`
+    fn advance(&mut self, hz: u32, elapsed_time_us: u32) -> i32 {
+        let phase_per_us = hz * PHASE_PER_US_HZ;
+        self.phase = self
+            .phase
+            .wrapping_add(phase_per_us.wrapping_mul(elapsed_time_us));
+        sine(self.phase)
+    }
+
+    /// The next sample centered on zero, and the envelope level it sits on
+    fn update(&mut self, elapsed_time_us: u32, noise: &mut Noise) -> (i32, i32) {
+        if self.amplitude.is_done() {
+            return (0, 0);
+        }
+        let mut level = self.amplitude.update(elapsed_time_us);
+        self.time_us = self.time_us.saturating_add(elapsed_time_us);
+        let sample = match self.drum {
+            Drum::Kick => {
+                let sweep = self.secondary.update(elapsed_time_us) as u32;
+                let hz = KICK_END_HZ + (((KICK_START_HZ - KICK_END_HZ) * sweep) >> 16);
+                self.advance(hz, elapsed_time_us)
+            }
+            Drum::Snare => {
+                let tone_level = self.secondary.update(elapsed_time_us);
+                let tone = self.advance(SNARE_HZ, elapsed_time_us);
+                (noise.next_sample() + ((tone * tone_level) >> 16)) / 2
+            }
+            Drum::ClosedHat | Drum::OpenHat => {
+                let white = noise.next_sample();
+                let highpassed = (white - self.previous_noise) / 2;
+                self.previous_noise = white;
+                highpassed
+            }
+            Drum::Clap => {
+                if self.time_us < CLAP_BURSTS * CLAP_BURST_US {
+                    // Each burst starts at full level and falls away fast
+                    let burst_time_us = self.time_us % CLAP_BURST_US;
+                    level = ENVELOPE_MAX * CLAP_BURST_DECAY_US as i32
+                        / (CLAP_BURST_DECAY_US + burst_time_us) as i32;
+                    // Hold the tail at full level until the bursts are over
+                    self.amplitude.trigger();
+                }
+                noise.next_sample()
+            }
+        };
+        let level = (level * self.velocity_gain) >> 8;
+        ((sample * level) >> 16, level)
+    }
+}
+
+/// The drum voices, mixed into one output.
+pub struct DrumKit {
+    voices: [DrumVoice; DRUMS],
+    noise: Noise,
+    /// Set in drum mode, when the kit is mixed into the output
+    enabled: bool,
+}
+
+impl DrumKit {
+    pub fn new() -> Self {
+        Self {
+            voices: [
+                DrumVoice::new(Drum::Kick, KICK_DECAY_US, KICK_PITCH_DECAY_US),
+                DrumVoice::new(Drum::Snare, SNARE_NOISE_DECAY_US, SNARE_TONE_DECAY_US),
+                DrumVoice::new(Drum::ClosedHat, CLOSED_HAT_DECAY_US, CLOSED_HAT_DECAY_US),
+                DrumVoice::new(Drum::OpenHat, OPEN_HAT_DECAY_US, OPEN_HAT_DECAY_US),
+                DrumVoice::new(Drum::Clap, CLAP_DECAY_US, CLAP_DECAY_US),
+            ],
+            noise: Noise::new(),
+            enabled: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.silence();
+        }
+    }
+
+    /// Cut off every drum, for the channel mode messages on the drum channel
+    pub fn silence(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.stop();
+        }
+    }
+
+    pub fn hit(&mut self, drum: Drum, velocity: u8) {
+        if drum == Drum::ClosedHat {
+            self.voice(Drum::OpenHat).stop();
+        }
+        self.voice(drum).trigger(velocity);
+    }
+
+    /// Like the synth voices, the output is 0 when silent and sits on a level that follows the
+    /// envelopes
+    pub fn update(&mut self, elapsed_time_us: u32) -> u8 {
+        let mut mix = 0;
+        let mut peak = 0;
+        for voice in self.voices.iter_mut() {
+            let (sample, level) = voice.update(elapsed_time_us, &mut self.noise);
+            mix += sample;
+            peak = i32::max(peak, level);
+        }
+        let sample = ((SAMPLE_CENTER * peak) >> 16) + (mix >> MIX_SHIFT);
+        sample.clamp(0, u8::MAX as i32) as u8
+    }
+
+    fn voice(&mut self, drum: Drum) -> &mut DrumVoice {
+        let index = match drum {
+            Drum::Kick => 0,
+            Drum::Snare => 1,
+            Drum::ClosedHat => 2,
+            Drum::OpenHat => 3,
+            Drum::Clap => 4,
+        };
+        &mut self.voices[index]
+    }
+}
@@ -0,0 +1,166 @@
+//! Resonant filter, one for each voice.
+//!
+//! Two types are modelled in fixed point. The state variable type is a Chamberlin filter, giving
+//! the lowpass, highpass, bandpass and notch responses from the same two integrators. The ladder
+//! type is four one pole lowpass stages with feedback around them and a soft clipper at the input,
+//! which gives the drive and lets it self-oscillate.
+use crate::intercore::{FilterMode, FilterType};
+use crate::wavetables::pitch_ratio;
+
+pub const MAX_RESONANCE: u8 = 127;
+pub const MAX_DRIVE: u8 = 127;
+/// Highest cutoff, as a MIDI note
+pub const MAX_CUTOFF: u8 = 127;
+/// 2 pi as 16.16 fixed point
+const TWO_PI: u64 = 411_775;
+/// Limit of the frequency coefficient, the filter becomes unstable above about 1.0
+const MAX_FREQUENCY: i32 = 56_000;
+/// Damping (1 / Q) at no and full resonance, as 16.16 fixed point. No resonance is a
+/// Butterworth response, sqrt(2), which also keeps the filter stable up to `MAX_FREQUENCY`.
+const MAX_DAMPING: i32 = 92_682;
+const MIN_DAMPING: i32 = 3_000;
+/// Bits of extra precision the samples are filtered with
+pub const PRECISION_BITS: u32 = 8;
+/// Keeps the integrators from running away when a resonant filter is swept hard
+const MAX_STATE: i32 = 1 << 22;
+/// Feedback of the ladder at full resonance as 4.12 fixed point, a little over the 4 it starts
+/// to self-oscillate at
+const MAX_FEEDBACK: i32 = 18_437;
+/// Input gain of the ladder at full drive, 8 times as 8.8 fixed point
+const MAX_DRIVE_GAIN: i32 = 8 << 8;
+/// A full scale sample with the extra precision is 1 << FULL_SCALE_BITS
+const FULL_SCALE_BITS: u32 = 7 + PRECISION_BITS;
+const FULL_SCALE: i32 = 1 << FULL_SCALE_BITS;
+/// 4 / 27 as 1.15 fixed point, the cube term of the soft clipper
+const CLIP_CURVE: i32 = 4855;
+const A4_CENTS: i32 = 6900;
+const A4_HZ: u64 = 440;
+
+pub struct Filter {
+    filter_type: FilterType,
+    mode: FilterMode,
+    /// 2 sin(pi * cutoff / sample rate), as 16.16 fixed point
+    frequency: i32,
+    damping: i32,
+    /// A lowpass with the cutoff at the top and no resonance is left out, so a fully open filter
+    /// does not color the sound
+    open: bool,
+    low: i32,
+    band: i32,
+    /// Feedback around the ladder, 4.12 fixed point
+    feedback: i32,
+    /// Ladder input gain, 8.8 fixed point
+    drive_gain: i32,
+    stages: [i32; 4],
+}
+
+/// Cubic soft clipper, smooth up to a full scale output at 1.5 times full scale.
+///
+/// Kept to 32 bit multiplies, the square of 1.5 times full scale only fits unsigned. Samples have
+/// `PRECISION_BITS` of extra precision.
+pub fn saturate(sample: i32) -> i32 {
+    let x = sample.clamp(-FULL_SCALE * 3 / 2, FULL_SCALE * 3 / 2);
+    let square = ((x.unsigned_abs() * x.unsigned_abs()) >> FULL_SCALE_BITS) as i32;
+    x - ((((square * CLIP_CURVE) >> 15) * x) >> FULL_SCALE_BITS)
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self {
+            filter_type: FilterType::StateVariable,
+            mode: FilterMode::Lowpass,
+            frequency: MAX_FREQUENCY,
+            damping: MAX_DAMPING,
+            open: true,
+            low: 0,
+            band: 0,
+            feedback: 0,
+            drive_gain: 1 << 8,
+            stages: [0; 4],
+        }
+    }
+
+    pub fn set_type(&mut self, filter_type: FilterType) {
+        if filter_type != self.filter_type {
+            self.filter_type = filter_type;
+            self.reset();
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: FilterMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_resonance(&mut self, resonance: u8) {
+        let resonance = u8::min(resonance, MAX_RESONANCE) as i32;
+        self.damping = MAX_DAMPING - (MAX_DAMPING - MIN_DAMPING) * resonance / MAX_RESONANCE as i32;
+        self.feedback = MAX_FEEDBACK / MAX_RESONANCE as i32 * resonance;
+    }
+
+    /// Drive the ladder input into its soft clipper, the state variable type is not driven
+    pub fn set_drive(&mut self, drive: u8) {
+        let drive = u8::min(drive, MAX_DRIVE) as i32;
+        self.drive_gain = (1 << 8) + (MAX_DRIVE_GAIN - (1 << 8)) * drive / MAX_DRIVE as i32;
+    }
+
+    /// Set the cutoff, in cents above MIDI note 0, for samples `sample_interval_us` apart
+    pub fn set_cutoff(&mut self, cutoff_cents: i32, sample_interval_us: u32) {
+        self.open = self.filter_type == FilterType::StateVariable
+            && self.mode == FilterMode::Lowpass
+            && cutoff_cents >= MAX_CUTOFF as i32 * 100
+            && self.damping == MAX_DAMPING;
+
+        // pitch_ratio is 2^(-cents/1200), so this is the cutoff in Hz as 24.8 fixed point
+        let cutoff_hz = (A4_HZ << 24) / pitch_ratio(cutoff_cents - A4_CENTS) as u64;
+        let frequency = (cutoff_hz * sample_interval_us as u64 * TWO_PI / 1_000_000) >> 8;
+        self.frequency = u64::min(frequency, MAX_FREQUENCY as u64) as i32;
+    }
+
+    /// Clear the filter state, e.g. before a voice is reused
+    pub fn reset(&mut self) {
+        self.low = 0;
+        self.band = 0;
+        self.stages = [0; 4];
+    }
+
+    /// Filter a sample centered on zero
+    pub fn process(&mut self, input: i32) -> i32 {
+        match self.filter_type {
+            FilterType::StateVariable if self.open => input,
+            FilterType::StateVariable => self.process_state_variable(input),
+            FilterType::Ladder => self.process_ladder(input),
+        }
+    }
+
+    fn process_state_variable(&mut self, input: i32) -> i32 {
+        let input = input << PRECISION_BITS;
+        self.low += ((self.frequency as i64 * self.band as i64) >> 16) as i32;
+        self.low = self.low.clamp(-MAX_STATE, MAX_STATE);
+        let high = input - self.low - ((self.damping as i64 * self.band as i64) >> 16) as i32;
+        self.band += ((self.frequency as i64 * high as i64) >> 16) as i32;
+        self.band = self.band.clamp(-MAX_STATE, MAX_STATE);
+
+        let output = match self.mode {
+            FilterMode::Lowpass => self.low,
+            FilterMode::Highpass => high,
+            FilterMode::Bandpass => self.band,
+            FilterMode::Notch => high + self.low,
+        };
+        output >> PRECISION_BITS
+    }
+
+    /// All in 32 bits, which the M0+ multiplies in one cycle. The clipper keeps the stage inputs
+    /// within full scale, and each stage only moves part way towards its input, so the stages stay
+    /// within it too and a Q15 coefficient times their difference fits.
+    fn process_ladder(&mut self, input: i32) -> i32 {
+        let input = ((input << PRECISION_BITS) * self.drive_gain) >> 8;
+        let feedback = (self.feedback * self.stages[3]) >> 12;
+        let mut stage_input = saturate(input - feedback);
+        let frequency = self.frequency >> 1;
+        for stage in self.stages.iter_mut() {
+            *stage += (frequency * (stage_input - *stage)) >> 15;
+            stage_input = *stage;
+        }
+        self.stages[3] >> PRECISION_BITS
+    }
+}
@@ -0,0 +1,332 @@
+//! FM voice engine, an alternative to the wavetable `MonoSynth`.
+//!
+//! Up to four sine operators, each with its own frequency ratio, level and envelope, phase
+//! modulate each other as arranged by the algorithm. Operator 1 is always a carrier, so two
+//! operator patches only need operators 1 and 2.
+use crate::adsr::{Adsr, MAX_LEVEL};
+use crate::intercore::FmAlgorithm;
+use crate::synth::{Param, Synth};
+use crate::wavetables::{pitch_ratio, MIDI_NOTE_TO_SAMPLE_INTERVAL_NS, SINE_WAVETABLE};
+
+pub const FM_OPERATORS: usize = 4;
+pub const MAX_OPERATOR_LEVEL: u8 = 127;
+/// Highest ratio, 16 times the note frequency
+pub const MAX_RATIO_HALVES: u8 = 32;
+/// A full level modulator moves the phase of the operator it modulates by up to a cycle either way
+const MODULATION_SHIFT: u32 = 25;
+const SAMPLE_CENTER: i32 = 128;
+/// Samples in a cycle of the note, the interval table is for one wavetable sample
+const CYCLE_SAMPLES_BITS: u32 = 7;
+
+/// Settings of an operator, kept by each part and copied to a voice when it starts a note.
+#[derive(Clone, Copy)]
+pub struct OperatorSettings {
+    /// Frequency as a multiple of the note frequency, in halves so 1 is an octave down
+    pub ratio_halves: u8,
+    pub level: u8,
+    pub attack_ms: u16,
+    pub decay_ms: u16,
+    pub sustain_level: u16,
+    pub release_ms: u16,
+}
+
+impl OperatorSettings {
+    pub const fn new() -> Self {
+        Self {
+            ratio_halves: 2,
+            level: 0,
+            attack_ms: crate::adsr::DEFAULT_ATTACK_MS as u16,
+            decay_ms: crate::adsr::DEFAULT_DECAY_MS as u16,
+            sustain_level: crate::adsr::DEFAULT_SUSTAIN_LEVEL as u16,
+            release_ms: crate::adsr::DEFAULT_RELEASE_MS as u16,
+        }
+    }
+
+    /// Operators 1 and 2 at the note frequency, 2 modulating 1 at half level
+    pub const fn defaults() -> [Self; FM_OPERATORS] {
+        let mut operators = [Self::new(); FM_OPERATORS];
+        operators[0].level = MAX_OPERATOR_LEVEL;
+        operators[1].level = MAX_OPERATOR_LEVEL / 2;
+        operators
+    }
+}
+
+struct Operator {
+    settings: OperatorSettings,
+    envelope: Adsr,
+    /// Position in the cycle, a full turn of the u32 is one period
+    phase: u32,
+    /// Envelope level of the last sample
+    level: i32,
+    /// Output of the last sample, centered on zero
+    output: i32,
+}
+
+impl Operator {
+    fn new(settings: OperatorSettings) -> Self {
+        let mut operator = Self {
+            settings,
+            envelope: Adsr::new(),
+            phase: 0,
+            level: 0,
+            output: 0,
+        };
+        operator.apply_envelope();
+        operator
+    }
+
+    fn apply_envelope(&mut self) {
+        self.envelope.set_attack(self.settings.attack_ms as u32);
+        self.envelope.set_decay(self.settings.decay_ms as u32);
+        self.envelope
+            .set_sustain(u32::min(self.settings.sustain_level as u32, MAX_LEVEL));
+        self.envelope.set_release(self.settings.release_ms as u32);
+    }
+
+    /// Advance by `phase_increment` and return the output, phase modulated by `modulation`
+    fn update(&mut self, elapsed_time_us: u32, phase_increment: u32, modulation: i32) -> i32 {
+        self.level = self.envelope.update(elapsed_time_us) as i32;
+        self.phase = self.phase.wrapping_add(phase_increment);
+        let phase = self
+            .phase
+            .wrapping_add((modulation as u32) << MODULATION_SHIFT);
+        self.output = sine(phase) * self.level / MAX_LEVEL as i32 * self.settings.level as i32
+            / MAX_OPERATOR_LEVEL as i32;
+        self.output
+    }
+}
+
+/// Sine of a phase, interpolated between the wavetable samples and centered on zero
+pub fn sine(phase: u32) -> i32 {
+    let index = (phase >> 25) as usize;
+    let next = (index + 1) % SINE_WAVETABLE.len();
+    let fraction = ((phase >> 17) & 0xFF) as i32;
+    let sample = SINE_WAVETABLE[index] as i32;
+    let next_sample = SINE_WAVETABLE[next] as i32;
+    sample + (((next_sample - sample) * fraction) >> 8) - SAMPLE_CENTER
+}
+
+/// Operators modulating each operator as bit masks, and the operators that are heard
+struct Routing {
+    modulators: [u8; FM_OPERATORS],
+    carriers: u8,
+}
+
+impl Routing {
+    fn of(algorithm: FmAlgorithm) -> Self {
+        let (modulators, carriers) = match algorithm {
+            FmAlgorithm::TwoOperator => ([0b0010, 0, 0, 0], 0b0001),
+            FmAlgorithm::Stack => ([0b0010, 0b0100, 0b1000, 0], 0b0001),
+            FmAlgorithm::TwoStacks => ([0b0010, 0, 0b1000, 0], 0b0101),
+            FmAlgorithm::Branch => ([0b1110, 0, 0, 0], 0b0001),
+            FmAlgorithm::Parallel => ([0, 0, 0, 0], 0b1111),
+        };
+        Self {
+            modulators,
+            carriers,
+        }
+    }
+
+    /// Operators that are heard or modulate another
+    fn used(&self) -> u8 {
+        self.modulators
+            .iter()
+            .fold(self.carriers, |used, mask| used | mask)
+    }
+}
+
+pub struct FmSynth {
+    operators: [Operator; FM_OPERATORS],
+    routing: Routing,
+    note: u8,
+    /// Phase increment of the note frequency per microsecond
+    phase_per_us: u32,
+    pitch_bend_cents: i16,
+    /// Pitch bend of just this note, from an MPE member channel
+    note_bend_cents: i16,
+}
+
+impl FmSynth {
+    pub fn note(&self) -> u8 {
+        self.note
+    }
+
+    /// True once every operator that is heard has finished its release
+    pub fn is_done(&self) -> bool {
+        self.operators
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.routing.carriers & (1 << *i) != 0)
+            .all(|(_, operator)| operator.envelope.is_done())
+    }
+
+    pub fn note_pitch_bend(&mut self, cents: i16) {
+        self.note_bend_cents = cents;
+        self.update_pitch();
+    }
+
+    fn update_pitch(&mut self) {
+        let interval_ns = MIDI_NOTE_TO_SAMPLE_INTERVAL_NS[self.note as usize] as u64;
+        if interval_ns == 0 {
+            self.phase_per_us = 0;
+            return;
+        }
+        let cents = self.pitch_bend_cents as i32 + self.note_bend_cents as i32;
+        let period_ns = (interval_ns * pitch_ratio(cents) as u64) << CYCLE_SAMPLES_BITS;
+        self.phase_per_us = ((1000u64 << (32 + 16)) / period_ns) as u32;
+    }
+
+    fn operator_mut(&mut self, op: u8) -> Option<&mut Operator> {
+        self.operators.get_mut(op as usize)
+    }
+}
+
+impl Synth for FmSynth {
+    fn new() -> Self {
+        let [op1, op2, op3, op4] = OperatorSettings::defaults();
+        let mut synth = Self {
+            operators: [
+                Operator::new(op1),
+                Operator::new(op2),
+                Operator::new(op3),
+                Operator::new(op4),
+            ],
+            routing: Routing::of(FmAlgorithm::default()),
+            note: 69,
+            phase_per_us: 0,
+            pitch_bend_cents: 0,
+            note_bend_cents: 0,
+        };
+        synth.update_pitch();
+        synth
+    }
+
+    fn update(&mut self, elapsed_time_us: u32) -> u8 {
+        let used = self.routing.used();
+        let mut mix = 0;
+        let mut peak = 0;
+        // Modulators always have a higher index than the operators they modulate
+        for i in (0..FM_OPERATORS).rev() {
+            if used & (1 << i) == 0 {
+                continue;
+            }
+            let modulation: i32 = (0..FM_OPERATORS)
+                .filter(|j| self.routing.modulators[i] & (1 << j) != 0)
+                .map(|j| self.operators[j].output)
+                .sum();
+            let operator = &mut self.operators[i];
+            let phase_increment =
+                ((self.phase_per_us as u64 * operator.settings.ratio_halves as u64) >> 1) as u32;
+            let output = operator.update(
+                elapsed_time_us,
+                phase_increment.wrapping_mul(elapsed_time_us),
+                modulation,
+            );
+            if self.routing.carriers & (1 << i) != 0 {
+                mix += output;
+                peak = i32::max(peak, operator.level);
+            }
+        }
+        let carrier_count = self.routing.carriers.count_ones() as i32;
+        // Like the wavetable voices, the output sits on a level that follows the envelope
+        let sample = SAMPLE_CENTER * peak / MAX_LEVEL as i32 + mix / carrier_count;
+        sample.clamp(0, u8::MAX as i32) as u8
+    }
+
+    fn note_on(&mut self, note: u8, velocity: u8) {
+        self.note = note;
+        self.update_pitch();
+        for operator in self.operators.iter_mut() {
+            operator.phase = 0;
+            operator.output = 0;
+            operator.envelope.trigger(velocity);
+        }
+    }
+
+    fn note_off(&mut self, _note: u8) {
+        for operator in self.operators.iter_mut() {
+            operator.envelope.release();
+        }
+    }
+
+    fn set_param(&mut self, param: Param) {
+        match param {
+            Param::FmAlgorithm(algorithm) => {
+                self.routing = Routing::of(algorithm);
+            }
+            Param::FmRatio { op, ratio_halves } => {
+                if let Some(operator) = self.operator_mut(op) {
+                    operator.settings.ratio_halves = ratio_halves.clamp(1, MAX_RATIO_HALVES);
+                }
+            }
+            Param::FmLevel { op, level } => {
+                if let Some(operator) = self.operator_mut(op) {
+                    operator.settings.level = u8::min(level, MAX_OPERATOR_LEVEL);
+                }
+            }
+            Param::FmAttack { op, attack_ms } => {
+                if let Some(operator) = self.operator_mut(op) {
+                    operator.settings.attack_ms = attack_ms;
+                    operator.apply_envelope();
+                }
+            }
+            Param::FmDecay { op, decay_ms } => {
+                if let Some(operator) = self.operator_mut(op) {
+                    operator.settings.decay_ms = decay_ms;
+                    operator.apply_envelope();
+                }
+            }
+            Param::FmSustain { op, sustain_level } => {
+                if let Some(operator) = self.operator_mut(op) {
+                    operator.settings.sustain_level = sustain_level;
+                    operator.apply_envelope();
+                }
+            }
+            Param::FmRelease { op, release_ms } => {
+                if let Some(operator) = self.operator_mut(op) {
+                    operator.settings.release_ms = release_ms;
+                    operator.apply_envelope();
+                }
+            }
+            Param::AttackCurve(curve) => {
+                for operator in self.operators.iter_mut() {
+                    operator.envelope.set_attack_curve(curve);
+                }
+            }
+            Param::DecayCurve(curve) => {
+                for operator in self.operators.iter_mut() {
+                    operator.envelope.set_decay_curve(curve);
+                }
+            }
+            Param::ReleaseCurve(curve) => {
+                for operator in self.operators.iter_mut() {
+                    operator.envelope.set_release_curve(curve);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn all_sound_off(&mut self) {
+        for operator in self.operators.iter_mut() {
+            operator.envelope.reset();
+        }
+    }
+
+    fn all_notes_off(&mut self) {
+        for operator in self.operators.iter_mut() {
+            operator.envelope.release();
+        }
+    }
+
+    fn reset_all_controllers(&mut self) {
+        self.pitch_bend_cents = 0;
+        self.note_bend_cents = 0;
+        self.update_pitch();
+    }
+
+    fn pitch_bend(&mut self, cents: i16) {
+        self.pitch_bend_cents = cents;
+        self.update_pitch();
+    }
+}
@@ -1,5 +1,9 @@
 use defmt::Format;
 
+/// Highest MIDI channel that can be addressed as an MPE member, the channel is sent plus one in a
+/// nibble where zero means no member.
+pub const MAX_MEMBER_CHANNEL: u8 = 14;
+
 pub enum Waveform {
     Sine,
     Sawtooth,
@@ -45,19 +49,1202 @@ impl Waveform {
     }
 }
 
+/// What key and channel pressure modulate.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PressureDestination {
+    Amplitude,
+    Brightness,
+    Vibrato,
+    FilterCutoff,
+}
+
+impl Default for PressureDestination {
+    fn default() -> Self {
+        Self::Amplitude
+    }
+}
+
+impl Format for PressureDestination {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Amplitude => defmt::write!(f, "Amplitude"),
+            Self::Brightness => defmt::write!(f, "Brightness"),
+            Self::Vibrato => defmt::write!(f, "Vibrato"),
+            Self::FilterCutoff => defmt::write!(f, "FilterCutoff"),
+        }
+    }
+}
+
+impl PressureDestination {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Amplitude => 0,
+            Self::Brightness => 64,
+            Self::Vibrato => 128,
+            Self::FilterCutoff => 192,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..64 => Self::Amplitude,
+            64..128 => Self::Brightness,
+            128..192 => Self::Vibrato,
+            192..=u8::MAX => Self::FilterCutoff,
+        }
+    }
+}
+
+/// Waveform of a low frequency oscillator.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    SampleAndHold,
+}
+
+impl Default for LfoShape {
+    fn default() -> Self {
+        Self::Sine
+    }
+}
+
+impl Format for LfoShape {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Sine => defmt::write!(f, "Sine"),
+            Self::Triangle => defmt::write!(f, "Triangle"),
+            Self::Saw => defmt::write!(f, "Saw"),
+            Self::Square => defmt::write!(f, "Square"),
+            Self::SampleAndHold => defmt::write!(f, "SampleAndHold"),
+        }
+    }
+}
+
+impl LfoShape {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Sine => 0,
+            Self::Triangle => 52,
+            Self::Saw => 103,
+            Self::Square => 154,
+            Self::SampleAndHold => 205,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..52 => Self::Sine,
+            52..103 => Self::Triangle,
+            103..154 => Self::Saw,
+            154..205 => Self::Square,
+            205..=u8::MAX => Self::SampleAndHold,
+        }
+    }
+}
+
+/// What an LFO modulates.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LfoTarget {
+    Pitch,
+    Amplitude,
+    /// The pulse width of the square wave
+    PulseWidth,
+    /// The blend towards the next brighter wavetable, as set by timbre
+    WavetablePosition,
+    /// Steps through the wavetables from dark to bright, around the selected one
+    Wavetable,
+}
+
+impl Format for LfoTarget {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Pitch => defmt::write!(f, "Pitch"),
+            Self::Amplitude => defmt::write!(f, "Amplitude"),
+            Self::PulseWidth => defmt::write!(f, "PulseWidth"),
+            Self::WavetablePosition => defmt::write!(f, "WavetablePosition"),
+            Self::Wavetable => defmt::write!(f, "Wavetable"),
+        }
+    }
+}
+
+impl LfoTarget {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Pitch => 0,
+            Self::Amplitude => 52,
+            Self::PulseWidth => 103,
+            Self::WavetablePosition => 154,
+            Self::Wavetable => 205,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..52 => Self::Pitch,
+            52..103 => Self::Amplitude,
+            103..154 => Self::PulseWidth,
+            154..205 => Self::WavetablePosition,
+            205..=u8::MAX => Self::Wavetable,
+        }
+    }
+}
+
+/// How an LFO's cycle is timed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LfoSync {
+    /// Runs at its own rate, independent of the notes
+    Free,
+    /// Restarts at every note
+    Key,
+    /// Follows the MIDI clock, `division` cycles every 4 beats
+    Clock { division: u8 },
+}
+
+impl Format for LfoSync {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Free => defmt::write!(f, "Free"),
+            Self::Key => defmt::write!(f, "Key"),
+            Self::Clock { division } => defmt::write!(f, "Clock 1/{}", division),
+        }
+    }
+}
+
+impl LfoSync {
+    /// The settings a knob steps through
+    pub const OPTIONS: [LfoSync; 7] = [
+        Self::Free,
+        Self::Key,
+        Self::Clock { division: 1 },
+        Self::Clock { division: 2 },
+        Self::Clock { division: 4 },
+        Self::Clock { division: 8 },
+        Self::Clock { division: 16 },
+    ];
+    const CLOCK_FLAG: u8 = 0x80;
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Free => 0,
+            Self::Key => 1,
+            Self::Clock { division } => Self::CLOCK_FLAG | division,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            1 => Self::Key,
+            _ if byte & Self::CLOCK_FLAG != 0 => Self::Clock {
+                division: u8::max(byte & !Self::CLOCK_FLAG, 1),
+            },
+            _ => Self::Free,
+        }
+    }
+}
+
+/// Response of the voice filter.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Notch,
+}
+
+impl Default for FilterMode {
+    fn default() -> Self {
+        Self::Lowpass
+    }
+}
+
+impl Format for FilterMode {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Lowpass => defmt::write!(f, "Lowpass"),
+            Self::Highpass => defmt::write!(f, "Highpass"),
+            Self::Bandpass => defmt::write!(f, "Bandpass"),
+            Self::Notch => defmt::write!(f, "Notch"),
+        }
+    }
+}
+
+impl FilterMode {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Lowpass => 0,
+            Self::Highpass => 64,
+            Self::Bandpass => 128,
+            Self::Notch => 192,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..64 => Self::Lowpass,
+            64..128 => Self::Highpass,
+            128..192 => Self::Bandpass,
+            192..=u8::MAX => Self::Notch,
+        }
+    }
+}
+
+/// Circuit the voice filter models.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    /// Clean 12dB/oct filter with every `FilterMode`
+    StateVariable,
+    /// Driven 24dB/oct lowpass that self-oscillates at high resonance
+    Ladder,
+}
+
+impl Default for FilterType {
+    fn default() -> Self {
+        Self::StateVariable
+    }
+}
+
+impl Format for FilterType {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::StateVariable => defmt::write!(f, "StateVariable"),
+            Self::Ladder => defmt::write!(f, "Ladder"),
+        }
+    }
+}
+
+impl FilterType {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::StateVariable => 0,
+            Self::Ladder => 128,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..128 => Self::StateVariable,
+            128..=u8::MAX => Self::Ladder,
+        }
+    }
+}
+
+/// Waveform of the sub-oscillator.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SubShape {
+    Square,
+    Sine,
+}
+
+impl Default for SubShape {
+    fn default() -> Self {
+        Self::Square
+    }
+}
+
+impl Format for SubShape {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Square => defmt::write!(f, "Square"),
+            Self::Sine => defmt::write!(f, "Sine"),
+        }
+    }
+}
+
+impl SubShape {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Square => 0,
+            Self::Sine => 128,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..128 => Self::Square,
+            128..=u8::MAX => Self::Sine,
+        }
+    }
+}
+
+/// Spectrum of the noise source.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NoiseColor {
+    White,
+    /// Falls 3dB per octave, for breath and wind
+    Pink,
+}
+
+impl Default for NoiseColor {
+    fn default() -> Self {
+        Self::White
+    }
+}
+
+impl Format for NoiseColor {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::White => defmt::write!(f, "White"),
+            Self::Pink => defmt::write!(f, "Pink"),
+        }
+    }
+}
+
+impl NoiseColor {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::White => 0,
+            Self::Pink => 128,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..128 => Self::White,
+            128..=u8::MAX => Self::Pink,
+        }
+    }
+}
+
+/// An input of the modulation matrix.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ModSource {
+    Velocity,
+    Aftertouch,
+    ModWheel,
+    Lfo1,
+    Lfo2,
+    /// The amplitude envelope
+    Envelope,
+    /// Distance of the note from middle C
+    KeyTrack,
+    /// A knob or CC assigned to the `ModKnob` parameter
+    Knob,
+    FilterEnvelope,
+    /// The envelope that only modulates
+    ModEnvelope,
+}
+
+impl Format for ModSource {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Velocity => defmt::write!(f, "Velocity"),
+            Self::Aftertouch => defmt::write!(f, "Aftertouch"),
+            Self::ModWheel => defmt::write!(f, "ModWheel"),
+            Self::Lfo1 => defmt::write!(f, "Lfo1"),
+            Self::Lfo2 => defmt::write!(f, "Lfo2"),
+            Self::Envelope => defmt::write!(f, "Envelope"),
+            Self::KeyTrack => defmt::write!(f, "KeyTrack"),
+            Self::Knob => defmt::write!(f, "Knob"),
+            Self::FilterEnvelope => defmt::write!(f, "FilterEnvelope"),
+            Self::ModEnvelope => defmt::write!(f, "ModEnvelope"),
+        }
+    }
+}
+
+impl ModSource {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Velocity => 0,
+            Self::Aftertouch => 1,
+            Self::ModWheel => 2,
+            Self::Lfo1 => 3,
+            Self::Lfo2 => 4,
+            Self::Envelope => 5,
+            Self::KeyTrack => 6,
+            Self::Knob => 7,
+            Self::FilterEnvelope => 8,
+            Self::ModEnvelope => 9,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Velocity),
+            1 => Some(Self::Aftertouch),
+            2 => Some(Self::ModWheel),
+            3 => Some(Self::Lfo1),
+            4 => Some(Self::Lfo2),
+            5 => Some(Self::Envelope),
+            6 => Some(Self::KeyTrack),
+            7 => Some(Self::Knob),
+            8 => Some(Self::FilterEnvelope),
+            9 => Some(Self::ModEnvelope),
+            _ => None,
+        }
+    }
+}
+
+/// An output of the modulation matrix.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ModTarget {
+    Pitch,
+    Amplitude,
+    WavetablePosition,
+    FilterCutoff,
+    PulseWidth,
+    PhaseDistortion,
+    ShaperDrive,
+}
+
+impl Format for ModTarget {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Pitch => defmt::write!(f, "Pitch"),
+            Self::Amplitude => defmt::write!(f, "Amplitude"),
+            Self::WavetablePosition => defmt::write!(f, "WavetablePosition"),
+            Self::FilterCutoff => defmt::write!(f, "FilterCutoff"),
+            Self::PulseWidth => defmt::write!(f, "PulseWidth"),
+            Self::PhaseDistortion => defmt::write!(f, "PhaseDistortion"),
+            Self::ShaperDrive => defmt::write!(f, "ShaperDrive"),
+        }
+    }
+}
+
+impl ModTarget {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Pitch => 0,
+            Self::Amplitude => 1,
+            Self::WavetablePosition => 2,
+            Self::FilterCutoff => 3,
+            Self::PulseWidth => 4,
+            Self::PhaseDistortion => 5,
+            Self::ShaperDrive => 6,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Pitch),
+            1 => Some(Self::Amplitude),
+            2 => Some(Self::WavetablePosition),
+            3 => Some(Self::FilterCutoff),
+            4 => Some(Self::PulseWidth),
+            5 => Some(Self::PhaseDistortion),
+            6 => Some(Self::ShaperDrive),
+            _ => None,
+        }
+    }
+}
+
+/// How the first oscillator reads its wavetable.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OscillatorMode {
+    /// The selected wavetable at an even rate
+    Wavetable,
+    /// The sine table with its read phase bent, from a sine towards a saw
+    PdSaw,
+    /// A faster sine table reset every cycle under a falling window, a swept resonance
+    PdResonant,
+}
+
+impl Default for OscillatorMode {
+    fn default() -> Self {
+        Self::Wavetable
+    }
+}
+
+impl Format for OscillatorMode {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Wavetable => defmt::write!(f, "Wavetable"),
+            Self::PdSaw => defmt::write!(f, "PdSaw"),
+            Self::PdResonant => defmt::write!(f, "PdResonant"),
+        }
+    }
+}
+
+impl OscillatorMode {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Wavetable => 0,
+            Self::PdSaw => 86,
+            Self::PdResonant => 171,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..86 => Self::Wavetable,
+            86..171 => Self::PdSaw,
+            171..=u8::MAX => Self::PdResonant,
+        }
+    }
+}
+
+/// Waveshaping of a voice between the oscillators and the filter.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ShaperMode {
+    Off,
+    /// Folds the peaks beyond full scale back on themselves
+    Fold,
+    /// Soft clips with the cubic curve of the ladder filter
+    Saturate,
+    /// Takes bits away from the sample
+    Crush,
+}
+
+impl Default for ShaperMode {
+    fn default() -> Self {
+        Self::Off
+    }
+}
+
+impl Format for ShaperMode {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Off => defmt::write!(f, "Off"),
+            Self::Fold => defmt::write!(f, "Fold"),
+            Self::Saturate => defmt::write!(f, "Saturate"),
+            Self::Crush => defmt::write!(f, "Crush"),
+        }
+    }
+}
+
+impl ShaperMode {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Off => 0,
+            Self::Fold => 64,
+            Self::Saturate => 128,
+            Self::Crush => 192,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..64 => Self::Off,
+            64..128 => Self::Fold,
+            128..192 => Self::Saturate,
+            192..=u8::MAX => Self::Crush,
+        }
+    }
+}
+
+/// Sound engine of the voices of a part.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Wavetable oscillators through the filter
+    Wavetable,
+    /// Phase modulation between operators
+    Fm,
+    /// Plucked string model
+    String,
+    /// Sine partials at the drawbar footages of an organ
+    Additive,
+    /// PCM samples from the bank in flash
+    Sample,
+    /// Tuned resonators of a struck bar, bell or glass
+    Modal,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::Wavetable
+    }
+}
+
+impl Format for Engine {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Wavetable => defmt::write!(f, "Wavetable"),
+            Self::Fm => defmt::write!(f, "Fm"),
+            Self::String => defmt::write!(f, "String"),
+            Self::Additive => defmt::write!(f, "Additive"),
+            Self::Sample => defmt::write!(f, "Sample"),
+            Self::Modal => defmt::write!(f, "Modal"),
+        }
+    }
+}
+
+impl Engine {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Wavetable => 0,
+            Self::Fm => 43,
+            Self::String => 86,
+            Self::Additive => 128,
+            Self::Sample => 171,
+            Self::Modal => 213,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..43 => Self::Wavetable,
+            43..86 => Self::Fm,
+            86..128 => Self::String,
+            128..171 => Self::Additive,
+            171..213 => Self::Sample,
+            213..=u8::MAX => Self::Modal,
+        }
+    }
+}
+
+/// How the operators of the FM engine modulate each other, operator 1 is always heard.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FmAlgorithm {
+    /// 2 modulates 1, operators 3 and 4 are off
+    TwoOperator,
+    /// 4 modulates 3, which modulates 2, which modulates 1
+    Stack,
+    /// 2 modulates 1 and 4 modulates 3, both 1 and 3 are heard
+    TwoStacks,
+    /// 2, 3 and 4 all modulate 1
+    Branch,
+    /// No modulation, all four operators are heard
+    Parallel,
+}
+
+impl Default for FmAlgorithm {
+    fn default() -> Self {
+        Self::TwoOperator
+    }
+}
+
+impl Format for FmAlgorithm {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::TwoOperator => defmt::write!(f, "TwoOperator"),
+            Self::Stack => defmt::write!(f, "Stack"),
+            Self::TwoStacks => defmt::write!(f, "TwoStacks"),
+            Self::Branch => defmt::write!(f, "Branch"),
+            Self::Parallel => defmt::write!(f, "Parallel"),
+        }
+    }
+}
+
+impl FmAlgorithm {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::TwoOperator => 0,
+            Self::Stack => 52,
+            Self::TwoStacks => 103,
+            Self::Branch => 154,
+            Self::Parallel => 205,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..52 => Self::TwoOperator,
+            52..103 => Self::Stack,
+            103..154 => Self::TwoStacks,
+            154..205 => Self::Branch,
+            205..=u8::MAX => Self::Parallel,
+        }
+    }
+}
+
+/// Drawbar registrations of the additive engine.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DrawbarPreset {
+    /// 8' alone
+    Sine,
+    /// 16', 5 1/3' and 8'
+    Jazz,
+    /// The four lowest drawbars
+    Gospel,
+    /// 8' with some 4'
+    Flute,
+    /// 16', 8' and 1'
+    Bright,
+    /// Every drawbar out
+    Full,
+}
+
+impl Default for DrawbarPreset {
+    fn default() -> Self {
+        Self::Sine
+    }
+}
+
+impl Format for DrawbarPreset {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Sine => defmt::write!(f, "Sine"),
+            Self::Jazz => defmt::write!(f, "Jazz"),
+            Self::Gospel => defmt::write!(f, "Gospel"),
+            Self::Flute => defmt::write!(f, "Flute"),
+            Self::Bright => defmt::write!(f, "Bright"),
+            Self::Full => defmt::write!(f, "Full"),
+        }
+    }
+}
+
+impl DrawbarPreset {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Sine => 0,
+            Self::Jazz => 43,
+            Self::Gospel => 86,
+            Self::Flute => 128,
+            Self::Bright => 171,
+            Self::Full => 213,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..43 => Self::Sine,
+            43..86 => Self::Jazz,
+            86..128 => Self::Gospel,
+            128..171 => Self::Flute,
+            171..213 => Self::Bright,
+            213..=u8::MAX => Self::Full,
+        }
+    }
+}
+
+/// What the resonators of the modal engine are made of, which sets how their modes are tuned.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ModalMaterial {
+    Wood,
+    Metal,
+    Glass,
+    Bell,
+}
+
+impl Default for ModalMaterial {
+    fn default() -> Self {
+        Self::Wood
+    }
+}
+
+impl Format for ModalMaterial {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Wood => defmt::write!(f, "Wood"),
+            Self::Metal => defmt::write!(f, "Metal"),
+            Self::Glass => defmt::write!(f, "Glass"),
+            Self::Bell => defmt::write!(f, "Bell"),
+        }
+    }
+}
+
+impl ModalMaterial {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Wood => 0,
+            Self::Metal => 64,
+            Self::Glass => 128,
+            Self::Bell => 192,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..64 => Self::Wood,
+            64..128 => Self::Metal,
+            128..192 => Self::Glass,
+            192..=u8::MAX => Self::Bell,
+        }
+    }
+}
+
+/// How the modal engine's resonators are set ringing.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ModalExciter {
+    /// An impulse, struck
+    Mallet,
+    /// A short burst of noise, scraped or brushed
+    Noise,
+}
+
+impl Default for ModalExciter {
+    fn default() -> Self {
+        Self::Mallet
+    }
+}
+
+impl Format for ModalExciter {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Mallet => defmt::write!(f, "Mallet"),
+            Self::Noise => defmt::write!(f, "Noise"),
+        }
+    }
+}
+
+impl ModalExciter {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Mallet => 0,
+            Self::Noise => 128,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0..128 => Self::Mallet,
+            128..=u8::MAX => Self::Noise,
+        }
+    }
+}
+
+/// A voice of the drum kit.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Drum {
+    Kick,
+    Snare,
+    ClosedHat,
+    OpenHat,
+    Clap,
+}
+
+impl Format for Drum {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::Kick => defmt::write!(f, "Kick"),
+            Self::Snare => defmt::write!(f, "Snare"),
+            Self::ClosedHat => defmt::write!(f, "ClosedHat"),
+            Self::OpenHat => defmt::write!(f, "OpenHat"),
+            Self::Clap => defmt::write!(f, "Clap"),
+        }
+    }
+}
+
+impl Drum {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Kick => 0,
+            Self::Snare => 1,
+            Self::ClosedHat => 2,
+            Self::OpenHat => 3,
+            Self::Clap => 4,
+        }
+    }
+
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Kick),
+            1 => Some(Self::Snare),
+            2 => Some(Self::ClosedHat),
+            3 => Some(Self::OpenHat),
+            4 => Some(Self::Clap),
+            _ => None,
+        }
+    }
+}
+
 pub enum IntercoreMessage {
-    NoteOn { note: u8, velocity: u8 },
-    NoteOff { note: u8 },
-    AttackControl { attack_ms: u16 },
-    DecayControl { decay_ms: u16 },
-    SustainControl { sustain_level: u16 },
-    ReleaseControl { release_ms: u16 },
-    WaveformControl { waveform: Waveform },
-    PortamentoControl { portamento_time_ms: u16 },
-    ChannelAftertouch { aftertouch: u8 },
+    NoteOn {
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        note: u8,
+    },
+    AttackControl {
+        attack_ms: u16,
+    },
+    DecayControl {
+        decay_ms: u16,
+    },
+    SustainControl {
+        sustain_level: u16,
+    },
+    ReleaseControl {
+        release_ms: u16,
+    },
+    WaveformControl {
+        waveform: Waveform,
+    },
+    PortamentoControl {
+        portamento_time_ms: u16,
+    },
+    ChannelAftertouch {
+        aftertouch: u8,
+    },
+    PolyAftertouch {
+        note: u8,
+        pressure: u8,
+    },
+    PressureDestination {
+        destination: PressureDestination,
+    },
+    PressureDepth {
+        depth: u8,
+    },
+    ModWheel {
+        value: u8,
+    },
+    VibratoRate {
+        rate_centihertz: u16,
+    },
+    VibratoShape {
+        shape: LfoShape,
+    },
+    VibratoDelay {
+        delay_ms: u16,
+    },
+    LfoRate {
+        lfo: u8,
+        rate_centihertz: u16,
+    },
+    LfoShape {
+        lfo: u8,
+        shape: LfoShape,
+    },
+    LfoDepth {
+        lfo: u8,
+        depth: u8,
+    },
+    LfoTarget {
+        lfo: u8,
+        target: LfoTarget,
+    },
+    LfoSync {
+        lfo: u8,
+        sync: LfoSync,
+    },
+    Tempo {
+        bpm_tenths: u16,
+    },
+    ModSource {
+        slot: u8,
+        source: ModSource,
+    },
+    ModTarget {
+        slot: u8,
+        target: ModTarget,
+    },
+    ModDepth {
+        slot: u8,
+        depth: i8,
+    },
+    ModKnob {
+        value: u8,
+    },
+    FilterMode {
+        mode: FilterMode,
+    },
+    FilterCutoff {
+        cutoff: u8,
+    },
+    FilterResonance {
+        resonance: u8,
+    },
+    FilterKeyTrack {
+        amount: u8,
+    },
+    FilterAttack {
+        attack_ms: u16,
+    },
+    FilterDecay {
+        decay_ms: u16,
+    },
+    FilterSustain {
+        sustain_level: u16,
+    },
+    FilterRelease {
+        release_ms: u16,
+    },
+    FilterEnvDepth {
+        depth: u8,
+    },
+    FilterType {
+        filter_type: FilterType,
+    },
+    FilterDrive {
+        drive: u8,
+    },
+    Osc2Waveform {
+        waveform: Waveform,
+    },
+    Osc2Coarse {
+        semitones: i8,
+    },
+    Osc2Fine {
+        cents: i8,
+    },
+    Osc2Level {
+        level: u8,
+    },
+    Osc2Sync {
+        on: bool,
+    },
+    Osc2Ring {
+        on: bool,
+    },
+    SubShape {
+        shape: SubShape,
+    },
+    SubOctave {
+        octaves: u8,
+    },
+    SubLevel {
+        level: u8,
+    },
+    NoiseColor {
+        color: NoiseColor,
+    },
+    NoiseLevel {
+        level: u8,
+    },
+    PulseWidth {
+        width: u8,
+    },
+    Engine {
+        engine: Engine,
+    },
+    FmAlgorithm {
+        algorithm: FmAlgorithm,
+    },
+    FmRatio {
+        op: u8,
+        ratio_halves: u8,
+    },
+    FmLevel {
+        op: u8,
+        level: u8,
+    },
+    FmAttack {
+        op: u8,
+        attack_ms: u16,
+    },
+    FmDecay {
+        op: u8,
+        decay_ms: u16,
+    },
+    FmSustain {
+        op: u8,
+        sustain_level: u16,
+    },
+    FmRelease {
+        op: u8,
+        release_ms: u16,
+    },
+    StringDamping {
+        damping: u8,
+    },
+    StringDecay {
+        decay: u8,
+    },
+    StringPickPosition {
+        position: u8,
+    },
+    OscillatorMode {
+        mode: OscillatorMode,
+    },
+    PhaseDistortion {
+        amount: u8,
+    },
+    ShaperMode {
+        mode: ShaperMode,
+    },
+    ShaperDrive {
+        drive: u8,
+    },
+    Drawbar {
+        drawbar: u8,
+        level: u8,
+    },
+    DrawbarPreset {
+        preset: DrawbarPreset,
+    },
+    DrawbarTilt {
+        tilt: i8,
+    },
+    DrumHit {
+        drum: Drum,
+        velocity: u8,
+    },
+    DrumMode {
+        on: bool,
+    },
+    /// Stop every drum that is still sounding
+    DrumSilence,
+    ModalMaterial {
+        material: ModalMaterial,
+    },
+    ModalExciter {
+        exciter: ModalExciter,
+    },
+    ModalBrightness {
+        brightness: u8,
+    },
+    ModalDecay {
+        decay: u8,
+    },
+    AttackCurve {
+        curve: i8,
+    },
+    DecayCurve {
+        curve: i8,
+    },
+    ReleaseCurve {
+        curve: i8,
+    },
+    /// The envelope that is only a modulation source
+    ModAttack {
+        attack_ms: u16,
+    },
+    ModDecay {
+        decay_ms: u16,
+    },
+    ModSustain {
+        sustain_level: u16,
+    },
+    ModRelease {
+        release_ms: u16,
+    },
+    SustainPedal {
+        on: bool,
+    },
+    SostenutoPedal {
+        on: bool,
+    },
+    AllSoundOff,
+    ResetAllControllers,
+    AllNotesOff,
+    PartReserve {
+        voices: u8,
+    },
+    PitchBend {
+        cents: i16,
+    },
+    Timbre {
+        timbre: u8,
+    },
+    FlashLock,
 }
 
 impl IntercoreMessage {
+    /// The last byte of every message is left free to address it: the low nibble is the index of
+    /// the part it is for, the high nibble the MPE member channel plus one, or 0 for none
+    pub fn part_from_u32(bytes: u32) -> u8 {
+        bytes.to_ne_bytes()[3] & 0x0F
+    }
+
+    pub fn member_from_u32(bytes: u32) -> Option<u8> {
+        match bytes.to_ne_bytes()[3] >> 4 {
+            0 => None,
+            member => Some(member - 1),
+        }
+    }
+
+    pub fn to_u32_for_part(&self, part: u8) -> u32 {
+        let mut bytes = self.to_u32().to_ne_bytes();
+        bytes[3] = part & 0x0F;
+        u32::from_ne_bytes(bytes)
+    }
+
+    /// Address the message to the voice playing on an MPE member channel of a part.
+    ///
+    /// Returns `None` for channels above `MAX_MEMBER_CHANNEL` as they don't fit in the nibble.
+    pub fn to_u32_for_member(&self, part: u8, channel: u8) -> Option<u32> {
+        if channel > MAX_MEMBER_CHANNEL {
+            return None;
+        }
+        let mut bytes = self.to_u32().to_ne_bytes();
+        bytes[3] = (part & 0x0F) | ((channel + 1) << 4);
+        Some(u32::from_ne_bytes(bytes))
+    }
+
     pub fn from_u32(bytes: u32) -> Option<Self> {
         let bytes = bytes.to_ne_bytes();
         match bytes[0] {
@@ -72,21 +1259,236 @@ impl IntercoreMessage {
             0x02 => Some(Self::DecayControl {
                 decay_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
             }),
-            0x03 => Some(Self::SustainControl {
+            0x03 => Some(Self::SustainControl {
+                sustain_level: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x04 => Some(Self::ReleaseControl {
+                release_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x05 => Some(Self::WaveformControl {
+                waveform: Waveform::from_u8(bytes[1])?,
+            }),
+            0x06 => Some(Self::PortamentoControl {
+                portamento_time_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x0A => Some(Self::ChannelAftertouch {
+                aftertouch: bytes[1],
+            }),
+            0xA0 => Some(Self::PolyAftertouch {
+                note: bytes[1],
+                pressure: bytes[2],
+            }),
+            0x11 => Some(Self::PressureDestination {
+                destination: PressureDestination::from_u8(bytes[1]),
+            }),
+            0x12 => Some(Self::PressureDepth { depth: bytes[1] }),
+            0x13 => Some(Self::ModWheel { value: bytes[1] }),
+            0x14 => Some(Self::VibratoRate {
+                rate_centihertz: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x15 => Some(Self::VibratoShape {
+                shape: LfoShape::from_u8(bytes[1]),
+            }),
+            0x16 => Some(Self::VibratoDelay {
+                delay_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x17 => {
+                let packed = u16::from_ne_bytes([bytes[1], bytes[2]]);
+                Some(Self::LfoRate {
+                    lfo: (packed >> 14) as u8,
+                    rate_centihertz: packed & 0x3FFF,
+                })
+            }
+            0x18 => Some(Self::LfoShape {
+                lfo: bytes[1],
+                shape: LfoShape::from_u8(bytes[2]),
+            }),
+            0x19 => Some(Self::LfoDepth {
+                lfo: bytes[1],
+                depth: bytes[2],
+            }),
+            0x1A => Some(Self::LfoTarget {
+                lfo: bytes[1],
+                target: LfoTarget::from_u8(bytes[2]),
+            }),
+            0x1B => Some(Self::LfoSync {
+                lfo: bytes[1],
+                sync: LfoSync::from_u8(bytes[2]),
+            }),
+            0x1C => Some(Self::Tempo {
+                bpm_tenths: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x1D => Some(Self::ModSource {
+                slot: bytes[1],
+                source: ModSource::from_u8(bytes[2])?,
+            }),
+            0x1E => Some(Self::ModTarget {
+                slot: bytes[1],
+                target: ModTarget::from_u8(bytes[2])?,
+            }),
+            0x1F => Some(Self::ModDepth {
+                slot: bytes[1],
+                depth: bytes[2] as i8,
+            }),
+            0x20 => Some(Self::ModKnob { value: bytes[1] }),
+            0x21 => Some(Self::FilterMode {
+                mode: FilterMode::from_u8(bytes[1]),
+            }),
+            0x22 => Some(Self::FilterCutoff { cutoff: bytes[1] }),
+            0x23 => Some(Self::FilterResonance {
+                resonance: bytes[1],
+            }),
+            0x24 => Some(Self::FilterKeyTrack { amount: bytes[1] }),
+            0x25 => Some(Self::FilterAttack {
+                attack_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x26 => Some(Self::FilterDecay {
+                decay_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x27 => Some(Self::FilterSustain {
+                sustain_level: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x28 => Some(Self::FilterRelease {
+                release_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x29 => Some(Self::FilterEnvDepth { depth: bytes[1] }),
+            0x2A => Some(Self::FilterType {
+                filter_type: FilterType::from_u8(bytes[1]),
+            }),
+            0x2B => Some(Self::FilterDrive { drive: bytes[1] }),
+            0x2C => Some(Self::Osc2Waveform {
+                waveform: Waveform::from_u8(bytes[1])?,
+            }),
+            0x2D => Some(Self::Osc2Coarse {
+                semitones: bytes[1] as i8,
+            }),
+            0x2E => Some(Self::Osc2Fine {
+                cents: bytes[1] as i8,
+            }),
+            0x2F => Some(Self::Osc2Level { level: bytes[1] }),
+            0x30 => Some(Self::Osc2Sync { on: bytes[1] != 0 }),
+            0x31 => Some(Self::Osc2Ring { on: bytes[1] != 0 }),
+            0x32 => Some(Self::SubShape {
+                shape: SubShape::from_u8(bytes[1]),
+            }),
+            0x33 => Some(Self::SubOctave { octaves: bytes[1] }),
+            0x34 => Some(Self::SubLevel { level: bytes[1] }),
+            0x35 => Some(Self::NoiseColor {
+                color: NoiseColor::from_u8(bytes[1]),
+            }),
+            0x36 => Some(Self::NoiseLevel { level: bytes[1] }),
+            0x37 => Some(Self::PulseWidth { width: bytes[1] }),
+            0x38 => Some(Self::Engine {
+                engine: Engine::from_u8(bytes[1]),
+            }),
+            0x39 => Some(Self::FmAlgorithm {
+                algorithm: FmAlgorithm::from_u8(bytes[1]),
+            }),
+            0x3A => Some(Self::FmRatio {
+                op: bytes[1],
+                ratio_halves: bytes[2],
+            }),
+            0x3B => Some(Self::FmLevel {
+                op: bytes[1],
+                level: bytes[2],
+            }),
+            0x3C => {
+                let packed = u16::from_ne_bytes([bytes[1], bytes[2]]);
+                Some(Self::FmAttack {
+                    op: (packed >> 14) as u8,
+                    attack_ms: packed & 0x3FFF,
+                })
+            }
+            0x3D => {
+                let packed = u16::from_ne_bytes([bytes[1], bytes[2]]);
+                Some(Self::FmDecay {
+                    op: (packed >> 14) as u8,
+                    decay_ms: packed & 0x3FFF,
+                })
+            }
+            0x3E => {
+                let packed = u16::from_ne_bytes([bytes[1], bytes[2]]);
+                Some(Self::FmSustain {
+                    op: (packed >> 14) as u8,
+                    sustain_level: packed & 0x3FFF,
+                })
+            }
+            0x3F => {
+                let packed = u16::from_ne_bytes([bytes[1], bytes[2]]);
+                Some(Self::FmRelease {
+                    op: (packed >> 14) as u8,
+                    release_ms: packed & 0x3FFF,
+                })
+            }
+            0x40 => Some(Self::StringDamping { damping: bytes[1] }),
+            0x41 => Some(Self::StringDecay { decay: bytes[1] }),
+            0x42 => Some(Self::StringPickPosition { position: bytes[1] }),
+            0x43 => Some(Self::OscillatorMode {
+                mode: OscillatorMode::from_u8(bytes[1]),
+            }),
+            0x44 => Some(Self::PhaseDistortion { amount: bytes[1] }),
+            0x45 => Some(Self::ShaperMode {
+                mode: ShaperMode::from_u8(bytes[1]),
+            }),
+            0x46 => Some(Self::ShaperDrive { drive: bytes[1] }),
+            0x47 => Some(Self::Drawbar {
+                drawbar: bytes[1],
+                level: bytes[2],
+            }),
+            0x48 => Some(Self::DrawbarPreset {
+                preset: DrawbarPreset::from_u8(bytes[1]),
+            }),
+            0x49 => Some(Self::DrawbarTilt {
+                tilt: bytes[1] as i8,
+            }),
+            0x4A => Some(Self::DrumHit {
+                drum: Drum::from_u8(bytes[1])?,
+                velocity: bytes[2],
+            }),
+            0x52 => Some(Self::DrumMode { on: bytes[1] != 0 }),
+            0x57 => Some(Self::DrumSilence),
+            0x4B => Some(Self::ModalMaterial {
+                material: ModalMaterial::from_u8(bytes[1]),
+            }),
+            0x4C => Some(Self::ModalExciter {
+                exciter: ModalExciter::from_u8(bytes[1]),
+            }),
+            0x4D => Some(Self::ModalBrightness {
+                brightness: bytes[1],
+            }),
+            0x4E => Some(Self::ModalDecay { decay: bytes[1] }),
+            0x4F => Some(Self::AttackCurve {
+                curve: bytes[1] as i8,
+            }),
+            0x50 => Some(Self::DecayCurve {
+                curve: bytes[1] as i8,
+            }),
+            0x51 => Some(Self::ReleaseCurve {
+                curve: bytes[1] as i8,
+            }),
+            0x53 => Some(Self::ModAttack {
+                attack_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x54 => Some(Self::ModDecay {
+                decay_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
+            }),
+            0x55 => Some(Self::ModSustain {
                 sustain_level: u16::from_ne_bytes([bytes[1], bytes[2]]),
             }),
-            0x04 => Some(Self::ReleaseControl {
+            0x56 => Some(Self::ModRelease {
                 release_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
             }),
-            0x05 => Some(Self::WaveformControl {
-                waveform: Waveform::from_u8(bytes[1])?,
-            }),
-            0x06 => Some(Self::PortamentoControl {
-                portamento_time_ms: u16::from_ne_bytes([bytes[1], bytes[2]]),
-            }),
-            0x0A => Some(Self::ChannelAftertouch {
-                aftertouch: bytes[1],
+            0x07 => Some(Self::SustainPedal { on: bytes[1] != 0 }),
+            0x08 => Some(Self::SostenutoPedal { on: bytes[1] != 0 }),
+            0x0B => Some(Self::AllSoundOff),
+            0x0C => Some(Self::ResetAllControllers),
+            0x0D => Some(Self::AllNotesOff),
+            0x0E => Some(Self::PartReserve { voices: bytes[1] }),
+            0x0F => Some(Self::PitchBend {
+                cents: i16::from_ne_bytes([bytes[1], bytes[2]]),
             }),
+            0x10 => Some(Self::Timbre { timbre: bytes[1] }),
+            0x7F => Some(Self::FlashLock),
             _ => None,
         }
     }
@@ -181,6 +1583,549 @@ impl IntercoreMessage {
                 bytes[3] = 0x00;
                 u32::from_ne_bytes(bytes)
             }
+            Self::PolyAftertouch { note, pressure } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0xA0;
+                bytes[1] = *note;
+                bytes[2] = *pressure;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::PressureDestination { destination } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x11;
+                bytes[1] = destination.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::PressureDepth { depth } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x12;
+                bytes[1] = *depth;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModWheel { value } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x13;
+                bytes[1] = *value;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::VibratoRate { rate_centihertz } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x14;
+                // Split rate_centihertz into 2 bytes
+                let rate_bytes = rate_centihertz.to_ne_bytes();
+                bytes[1] = rate_bytes[0];
+                bytes[2] = rate_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::VibratoShape { shape } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x15;
+                bytes[1] = shape.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::VibratoDelay { delay_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x16;
+                // Split delay_ms into 2 bytes
+                let delay_bytes = delay_ms.to_ne_bytes();
+                bytes[1] = delay_bytes[0];
+                bytes[2] = delay_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::LfoRate {
+                lfo,
+                rate_centihertz,
+            } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x17;
+                // The LFO index takes the top 2 bits, leaving 14 for the rate
+                let packed = ((*lfo as u16) << 14) | (rate_centihertz & 0x3FFF);
+                let packed_bytes = packed.to_ne_bytes();
+                bytes[1] = packed_bytes[0];
+                bytes[2] = packed_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::LfoShape { lfo, shape } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x18;
+                bytes[1] = *lfo;
+                bytes[2] = shape.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::LfoDepth { lfo, depth } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x19;
+                bytes[1] = *lfo;
+                bytes[2] = *depth;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::LfoTarget { lfo, target } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x1A;
+                bytes[1] = *lfo;
+                bytes[2] = target.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::LfoSync { lfo, sync } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x1B;
+                bytes[1] = *lfo;
+                bytes[2] = sync.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Tempo { bpm_tenths } => {
+                // Tempo of the incoming MIDI clock, for clock synced LFOs
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x1C;
+                let tempo_bytes = bpm_tenths.to_ne_bytes();
+                bytes[1] = tempo_bytes[0];
+                bytes[2] = tempo_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModSource { slot, source } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x1D;
+                bytes[1] = *slot;
+                bytes[2] = source.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModTarget { slot, target } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x1E;
+                bytes[1] = *slot;
+                bytes[2] = target.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModDepth { slot, depth } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x1F;
+                bytes[1] = *slot;
+                bytes[2] = *depth as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModKnob { value } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x20;
+                bytes[1] = *value;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterMode { mode } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x21;
+                bytes[1] = mode.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterCutoff { cutoff } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x22;
+                bytes[1] = *cutoff;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterResonance { resonance } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x23;
+                bytes[1] = *resonance;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterKeyTrack { amount } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x24;
+                bytes[1] = *amount;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterAttack { attack_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x25;
+                let value_bytes = attack_ms.to_ne_bytes();
+                bytes[1] = value_bytes[0];
+                bytes[2] = value_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterDecay { decay_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x26;
+                let value_bytes = decay_ms.to_ne_bytes();
+                bytes[1] = value_bytes[0];
+                bytes[2] = value_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterSustain { sustain_level } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x27;
+                let value_bytes = sustain_level.to_ne_bytes();
+                bytes[1] = value_bytes[0];
+                bytes[2] = value_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterRelease { release_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x28;
+                let value_bytes = release_ms.to_ne_bytes();
+                bytes[1] = value_bytes[0];
+                bytes[2] = value_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterEnvDepth { depth } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x29;
+                bytes[1] = *depth;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterType { filter_type } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x2A;
+                bytes[1] = filter_type.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FilterDrive { drive } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x2B;
+                bytes[1] = *drive;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Osc2Waveform { waveform } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x2C;
+                bytes[1] = waveform.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Osc2Coarse { semitones } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x2D;
+                bytes[1] = *semitones as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Osc2Fine { cents } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x2E;
+                bytes[1] = *cents as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Osc2Level { level } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x2F;
+                bytes[1] = *level;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Osc2Sync { on } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x30;
+                bytes[1] = *on as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Osc2Ring { on } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x31;
+                bytes[1] = *on as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::SubShape { shape } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x32;
+                bytes[1] = shape.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::SubOctave { octaves } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x33;
+                bytes[1] = *octaves;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::SubLevel { level } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x34;
+                bytes[1] = *level;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::NoiseColor { color } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x35;
+                bytes[1] = color.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::NoiseLevel { level } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x36;
+                bytes[1] = *level;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::PulseWidth { width } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x37;
+                bytes[1] = *width;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Engine { engine } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x38;
+                bytes[1] = engine.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FmAlgorithm { algorithm } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x39;
+                bytes[1] = algorithm.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FmRatio { op, ratio_halves } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x3A;
+                bytes[1] = *op;
+                bytes[2] = *ratio_halves;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FmLevel { op, level } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x3B;
+                bytes[1] = *op;
+                bytes[2] = *level;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FmAttack { op, attack_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x3C;
+                // The operator index takes the top 2 bits, leaving 14 for the value
+                let packed = ((*op as u16) << 14) | (attack_ms & 0x3FFF);
+                let packed_bytes = packed.to_ne_bytes();
+                bytes[1] = packed_bytes[0];
+                bytes[2] = packed_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FmDecay { op, decay_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x3D;
+                let packed = ((*op as u16) << 14) | (decay_ms & 0x3FFF);
+                let packed_bytes = packed.to_ne_bytes();
+                bytes[1] = packed_bytes[0];
+                bytes[2] = packed_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FmSustain { op, sustain_level } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x3E;
+                let packed = ((*op as u16) << 14) | (sustain_level & 0x3FFF);
+                let packed_bytes = packed.to_ne_bytes();
+                bytes[1] = packed_bytes[0];
+                bytes[2] = packed_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FmRelease { op, release_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x3F;
+                let packed = ((*op as u16) << 14) | (release_ms & 0x3FFF);
+                let packed_bytes = packed.to_ne_bytes();
+                bytes[1] = packed_bytes[0];
+                bytes[2] = packed_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::StringDamping { damping } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x40;
+                bytes[1] = *damping;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::StringDecay { decay } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x41;
+                bytes[1] = *decay;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::StringPickPosition { position } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x42;
+                bytes[1] = *position;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::OscillatorMode { mode } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x43;
+                bytes[1] = mode.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::PhaseDistortion { amount } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x44;
+                bytes[1] = *amount;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ShaperMode { mode } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x45;
+                bytes[1] = mode.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ShaperDrive { drive } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x46;
+                bytes[1] = *drive;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Drawbar { drawbar, level } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x47;
+                bytes[1] = *drawbar;
+                bytes[2] = *level;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::DrawbarPreset { preset } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x48;
+                bytes[1] = preset.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::DrawbarTilt { tilt } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x49;
+                bytes[1] = *tilt as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::DrumHit { drum, velocity } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x4A;
+                bytes[1] = drum.to_u8();
+                bytes[2] = *velocity;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::DrumMode { on } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x52;
+                bytes[1] = *on as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::DrumSilence => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x57;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModalMaterial { material } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x4B;
+                bytes[1] = material.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModalExciter { exciter } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x4C;
+                bytes[1] = exciter.to_u8();
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModalBrightness { brightness } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x4D;
+                bytes[1] = *brightness;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModalDecay { decay } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x4E;
+                bytes[1] = *decay;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::AttackCurve { curve } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x4F;
+                bytes[1] = *curve as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::DecayCurve { curve } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x50;
+                bytes[1] = *curve as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ReleaseCurve { curve } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x51;
+                bytes[1] = *curve as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModAttack { attack_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x53;
+                let value_bytes = attack_ms.to_ne_bytes();
+                bytes[1] = value_bytes[0];
+                bytes[2] = value_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModDecay { decay_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x54;
+                let value_bytes = decay_ms.to_ne_bytes();
+                bytes[1] = value_bytes[0];
+                bytes[2] = value_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModSustain { sustain_level } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x55;
+                let value_bytes = sustain_level.to_ne_bytes();
+                bytes[1] = value_bytes[0];
+                bytes[2] = value_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ModRelease { release_ms } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x56;
+                let value_bytes = release_ms.to_ne_bytes();
+                bytes[1] = value_bytes[0];
+                bytes[2] = value_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::SustainPedal { on } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x07;
+                bytes[1] = *on as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::SostenutoPedal { on } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x08;
+                bytes[1] = *on as u8;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::AllSoundOff => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x0B;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::ResetAllControllers => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x0C;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::AllNotesOff => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x0D;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::PartReserve { voices } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x0E;
+                bytes[1] = *voices;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::PitchBend { cents } => {
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x0F;
+                // Split cents into 2 bytes
+                let cents_bytes = cents.to_ne_bytes();
+                bytes[1] = cents_bytes[0];
+                bytes[2] = cents_bytes[1];
+                u32::from_ne_bytes(bytes)
+            }
+            Self::Timbre { timbre } => {
+                // MPE slide, CC74
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x10;
+                bytes[1] = *timbre;
+                u32::from_ne_bytes(bytes)
+            }
+            Self::FlashLock => {
+                // Core 1 parks itself in RAM while core 0 writes to flash
+                let mut bytes = [0u8; 4];
+                bytes[0] = 0x7F;
+                u32::from_ne_bytes(bytes)
+            }
         }
     }
 }
@@ -0,0 +1,121 @@
+//! Low frequency oscillators for modulation.
+use crate::intercore::{LfoShape, LfoSync, LfoTarget};
+use crate::wavetables::SINE_WAVETABLE;
+
+/// Peak of the LFO output, which swings between -LFO_MAX and LFO_MAX
+pub const LFO_MAX: i32 = 1 << 15;
+/// Number of general purpose LFOs of each voice
+pub const LFO_COUNT: usize = 2;
+pub const MAX_LFO_DEPTH: u8 = 127;
+const DEFAULT_RATE_CENTIHERTZ: u16 = 200;
+
+/// Settings of an LFO, kept by each part and copied to a voice when it starts a note.
+#[derive(Clone, Copy)]
+pub struct LfoSettings {
+    pub shape: LfoShape,
+    pub rate_centihertz: u16,
+    pub depth: u8,
+    pub target: LfoTarget,
+    pub sync: LfoSync,
+}
+
+impl LfoSettings {
+    pub const fn new() -> Self {
+        Self {
+            shape: LfoShape::Sine,
+            rate_centihertz: DEFAULT_RATE_CENTIHERTZ,
+            depth: 0,
+            target: LfoTarget::Pitch,
+            sync: LfoSync::Free,
+        }
+    }
+}
+
+pub struct Lfo {
+    pub settings: LfoSettings,
+    /// Position in the cycle, a full turn of the u32 is one period
+    phase: u32,
+    /// Output of the sample and hold shape, picked at the start of each cycle
+    held: i32,
+    /// xorshift state for the sample and hold
+    noise: u32,
+}
+
+impl Lfo {
+    pub fn new(rate_centihertz: u16) -> Self {
+        let mut settings = LfoSettings::new();
+        settings.rate_centihertz = rate_centihertz;
+        settings.depth = MAX_LFO_DEPTH;
+        Self {
+            settings,
+            phase: 0,
+            held: 0,
+            noise: 0x2545_F491,
+        }
+    }
+
+    pub fn set_rate(&mut self, rate_centihertz: u16) {
+        self.settings.rate_centihertz = rate_centihertz;
+    }
+
+    pub fn set_shape(&mut self, shape: LfoShape) {
+        self.settings.shape = shape;
+    }
+
+    /// Restart the cycle if the LFO is synced to notes
+    pub fn key_on(&mut self) {
+        if self.settings.sync == LfoSync::Key {
+            self.phase = 0;
+            self.hold_next();
+        }
+    }
+
+    /// Rate of the LFO, following the MIDI clock tempo when synced to it and one is known
+    fn rate_centihertz(&self, tempo_bpm_tenths: u16) -> u32 {
+        match self.settings.sync {
+            // Division is in cycles per 4 beats
+            LfoSync::Clock { division } if tempo_bpm_tenths > 0 => {
+                tempo_bpm_tenths as u32 * division as u32 / 24
+            }
+            _ => self.settings.rate_centihertz as u32,
+        }
+    }
+
+    fn hold_next(&mut self) {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.held = (self.noise >> 16) as i32 - LFO_MAX;
+    }
+
+    /// Advance by the elapsed time and return the output
+    pub fn update(&mut self, elapsed_time_us: u32, tempo_bpm_tenths: u16) -> i32 {
+        let increment =
+            (self.rate_centihertz(tempo_bpm_tenths) as u64 * elapsed_time_us as u64 * (1 << 32))
+                / 100_000_000;
+        let (phase, wrapped) = self.phase.overflowing_add(increment as u32);
+        self.phase = phase;
+        if wrapped {
+            self.hold_next();
+        }
+
+        match self.settings.shape {
+            LfoShape::Sine => {
+                let sample = SINE_WAVETABLE[(self.phase >> 25) as usize] as i32;
+                (sample - 128) * LFO_MAX / 128
+            }
+            LfoShape::Triangle => {
+                let position = (self.phase >> 15) as i32;
+                if position < 2 * LFO_MAX {
+                    position - LFO_MAX
+                } else {
+                    3 * LFO_MAX - position
+                }
+            }
+            LfoShape::Saw => (self.phase >> 16) as i32 - LFO_MAX,
+            LfoShape::Square if self.phase < 1 << 31 => LFO_MAX,
+            LfoShape::Square => -LFO_MAX,
+            LfoShape::SampleAndHold => self.held,
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! Voice engines, settings and MIDI decoding of the synth, kept apart from the board so they can
+//! be tested on the host with `cargo test --lib --target <host triple>`.
+#![cfg_attr(not(test), no_std)]
+// Everything is built with `new` like in the firmware, being public here does not call for `Default`
+#![allow(clippy::new_without_default)]
+
+pub mod additive;
+pub mod adsr;
+pub mod clock;
+pub mod controls;
+pub mod drums;
+pub mod filter;
+pub mod fm;
+pub mod intercore;
+pub mod lfo;
+pub mod modal;
+pub mod modmatrix;
+pub mod noise;
+pub mod sampler;
+pub mod shaper;
+pub mod storage;
+pub mod string;
+pub mod synth;
+pub mod sysex;
+pub mod wavetables;
@@ -4,20 +4,33 @@
 #![no_std]
 #![no_main]
 
-mod adsr;
 mod errors;
 mod i2c;
-mod intercore;
 mod metrics;
-mod synth;
-mod wavetables;
+mod mpe;
 
+use slunk_synth::{
+    clock, controls, drums, intercore, modmatrix, storage, synth, sysex, wavetables,
+};
+
+use crate::clock::MidiClock;
+use crate::controls::{
+    Button, ControlSource, MidiLearn, ReceiveChannel, ALL_NOTES_OFF_CC, ALL_SOUND_OFF_CC,
+    MOD_WHEEL_CC, RESET_ALL_CONTROLLERS_CC, SOSTENUTO_PEDAL_CC, SUSTAIN_PEDAL_CC, TIMBRE_CC,
+};
+use crate::drums::DRUM_CHANNEL;
 use crate::i2c::refcelldevice::RefCellDevice;
 use crate::metrics::{MetricName, Metrics};
+use crate::modmatrix::ModSlot;
+use crate::mpe::{ChannelRole, Mpe, MPE_PART};
+use crate::storage::Settings;
+use crate::synth::MAX_PARTS;
+use crate::sysex::{SysexCommand, SysexReader};
 use bsp::entry;
 use core::cell::RefCell;
 use defmt::*;
 use defmt_rtt as _;
+use embedded_hal::digital::InputPin;
 use embedded_hal::pwm::SetDutyCycle;
 use intercore::IntercoreMessage;
 use knobz::Knobz;
@@ -25,7 +38,6 @@ use panic_probe as _;
 use rp_pico::hal::Clock;
 use usb_device::class_prelude::UsbBusAllocator;
 use usb_device::prelude::{StringDescriptors, UsbDeviceBuilder, UsbVidPid};
-use usbd_midi::data::midi::channel::Channel::Channel1;
 use usbd_midi::data::midi::message::Message;
 use usbd_midi::data::usb_midi::midi_packet_reader::MidiPacketBufferReader;
 use usbd_midi::midi_device::MidiClass;
@@ -41,14 +53,14 @@ use bsp::hal::{
     gpio::{FunctionI2C, Pin, PullUp},
     multicore::{Multicore, Stack},
     pac,
-    sio::Sio,
+    sio::{Sio, SioFifo},
     timer::Timer,
     usb::UsbBus,
     watchdog::Watchdog,
     I2C,
 };
 
-use crate::synth::Synth;
+use crate::synth::{Param, Synth};
 
 const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
@@ -83,6 +95,7 @@ fn core1_task(loop_timer: &bsp::hal::timer::Timer) -> ! {
 
     // Setup the polyphonic synthesizer
     let mut poly_synth = synth::PolySynth::new();
+    let mut drum_kit = drums::DrumKit::new();
     let mut previous_time_us: u32 = loop_timer.get_counter_low();
 
     let mut metrics = Metrics::new();
@@ -97,6 +110,11 @@ fn core1_task(loop_timer: &bsp::hal::timer::Timer) -> ! {
         metrics.update(elapsed_time_us);
 
         let sample = poly_synth.update(elapsed_time_us);
+        let sample = if drum_kit.is_enabled() {
+            drums::mix(sample, drum_kit.update(elapsed_time_us))
+        } else {
+            sample
+        };
 
         channel.set_duty_cycle(sample as u16).unwrap();
 
@@ -104,6 +122,8 @@ fn core1_task(loop_timer: &bsp::hal::timer::Timer) -> ! {
         let msg = sio.fifo.read();
         if let Some(word) = msg {
             let message = IntercoreMessage::from_u32(word);
+            poly_synth.select_part(IntercoreMessage::part_from_u32(word) as usize);
+            poly_synth.select_member(IntercoreMessage::member_from_u32(word));
             match message {
                 Some(IntercoreMessage::NoteOn { note, velocity }) => {
                     info!("NoteOn: note: {}, velocity: {}", note, velocity);
@@ -115,40 +135,368 @@ fn core1_task(loop_timer: &bsp::hal::timer::Timer) -> ! {
                 }
                 Some(IntercoreMessage::AttackControl { attack_ms }) => {
                     info!("AttackControl: attack_ms: {}", attack_ms);
-                    poly_synth.attack_control(attack_ms);
+                    poly_synth.set_param(Param::Attack(attack_ms));
                 }
                 Some(IntercoreMessage::DecayControl { decay_ms }) => {
                     info!("DecayControl: decay_ms: {}", decay_ms);
-                    poly_synth.decay_control(decay_ms);
+                    poly_synth.set_param(Param::Decay(decay_ms));
                 }
                 Some(IntercoreMessage::SustainControl { sustain_level }) => {
                     info!("SustainControl: sustain_level: {}", sustain_level);
-                    poly_synth.sustain_control(sustain_level as u16);
+                    poly_synth.set_param(Param::Sustain(sustain_level as u16));
                 }
                 Some(IntercoreMessage::ReleaseControl { release_ms }) => {
                     info!("ReleaseControl: release_ms: {}", release_ms);
-                    poly_synth.release_control(release_ms);
+                    poly_synth.set_param(Param::Release(release_ms));
                 }
                 Some(IntercoreMessage::WaveformControl { waveform }) => {
-                    let wavetable = match waveform {
-                        intercore::Waveform::Sine => &wavetables::SINE_WAVETABLE,
-                        intercore::Waveform::Square => &wavetables::SQUARE_WAVETABLE,
-                        intercore::Waveform::Triangle => &wavetables::TRIANGLE_WAVETABLE,
-                        intercore::Waveform::Sawtooth => &wavetables::SAWTOOTH_WAVETABLE,
-                    };
                     info!("WaveformControl: waveform: {:?}", waveform);
-                    poly_synth.set_wavetable(&wavetable);
+                    poly_synth.set_param(Param::Wavetable(wavetable(waveform)));
                 }
                 Some(IntercoreMessage::PortamentoControl { portamento_time_ms }) => {
                     info!(
                         "PortamentoControl: portamento_time_ms: {}",
                         portamento_time_ms
                     );
-                    poly_synth.portamento_control(portamento_time_ms);
+                    poly_synth.set_param(Param::Portamento(portamento_time_ms));
                 }
                 Some(IntercoreMessage::ChannelAftertouch { aftertouch }) => {
                     info!("ChannelAftertouch: aftertouch: {}", aftertouch);
-                    poly_synth.channel_aftertouch(aftertouch);
+                    poly_synth.set_param(Param::ChannelAftertouch(aftertouch));
+                }
+                Some(IntercoreMessage::PolyAftertouch { note, pressure }) => {
+                    info!("PolyAftertouch: note: {}, pressure: {}", note, pressure);
+                    poly_synth.set_param(Param::KeyPressure { note, pressure });
+                }
+                Some(IntercoreMessage::PressureDestination { destination }) => {
+                    info!("PressureDestination: destination: {:?}", destination);
+                    poly_synth.set_param(Param::PressureDestination(destination));
+                }
+                Some(IntercoreMessage::PressureDepth { depth }) => {
+                    info!("PressureDepth: depth: {}", depth);
+                    poly_synth.set_param(Param::PressureDepth(depth));
+                }
+                Some(IntercoreMessage::ModWheel { value }) => {
+                    info!("ModWheel: value: {}", value);
+                    poly_synth.set_param(Param::ModWheel(value));
+                }
+                Some(IntercoreMessage::VibratoRate { rate_centihertz }) => {
+                    info!("VibratoRate: rate_centihertz: {}", rate_centihertz);
+                    poly_synth.set_param(Param::VibratoRate(rate_centihertz));
+                }
+                Some(IntercoreMessage::VibratoShape { shape }) => {
+                    info!("VibratoShape: shape: {:?}", shape);
+                    poly_synth.set_param(Param::VibratoShape(shape));
+                }
+                Some(IntercoreMessage::VibratoDelay { delay_ms }) => {
+                    info!("VibratoDelay: delay_ms: {}", delay_ms);
+                    poly_synth.set_param(Param::VibratoDelay(delay_ms));
+                }
+                Some(IntercoreMessage::LfoRate {
+                    lfo,
+                    rate_centihertz,
+                }) => {
+                    info!(
+                        "LfoRate: lfo: {}, rate_centihertz: {}",
+                        lfo, rate_centihertz
+                    );
+                    poly_synth.set_param(Param::LfoRate {
+                        lfo,
+                        rate_centihertz,
+                    });
+                }
+                Some(IntercoreMessage::LfoShape { lfo, shape }) => {
+                    info!("LfoShape: lfo: {}, shape: {:?}", lfo, shape);
+                    poly_synth.set_param(Param::LfoShape { lfo, shape });
+                }
+                Some(IntercoreMessage::LfoDepth { lfo, depth }) => {
+                    info!("LfoDepth: lfo: {}, depth: {}", lfo, depth);
+                    poly_synth.set_param(Param::LfoDepth { lfo, depth });
+                }
+                Some(IntercoreMessage::LfoTarget { lfo, target }) => {
+                    info!("LfoTarget: lfo: {}, target: {:?}", lfo, target);
+                    poly_synth.set_param(Param::LfoTarget { lfo, target });
+                }
+                Some(IntercoreMessage::LfoSync { lfo, sync }) => {
+                    info!("LfoSync: lfo: {}, sync: {:?}", lfo, sync);
+                    poly_synth.set_param(Param::LfoSync { lfo, sync });
+                }
+                Some(IntercoreMessage::Tempo { bpm_tenths }) => {
+                    info!("Tempo: bpm_tenths: {}", bpm_tenths);
+                    poly_synth.set_param(Param::Tempo(bpm_tenths));
+                }
+                Some(IntercoreMessage::ModSource { slot, source }) => {
+                    info!("ModSource: slot: {}, source: {:?}", slot, source);
+                    poly_synth.set_param(Param::ModSource { slot, source });
+                }
+                Some(IntercoreMessage::ModTarget { slot, target }) => {
+                    info!("ModTarget: slot: {}, target: {:?}", slot, target);
+                    poly_synth.set_param(Param::ModTarget { slot, target });
+                }
+                Some(IntercoreMessage::ModDepth { slot, depth }) => {
+                    info!("ModDepth: slot: {}, depth: {}", slot, depth);
+                    poly_synth.set_param(Param::ModDepth { slot, depth });
+                }
+                Some(IntercoreMessage::ModKnob { value }) => {
+                    info!("ModKnob: value: {}", value);
+                    poly_synth.set_param(Param::ModKnob(value));
+                }
+                Some(IntercoreMessage::FilterMode { mode }) => {
+                    info!("FilterMode: mode: {:?}", mode);
+                    poly_synth.set_param(Param::FilterMode(mode));
+                }
+                Some(IntercoreMessage::FilterCutoff { cutoff }) => {
+                    info!("FilterCutoff: cutoff: {}", cutoff);
+                    poly_synth.set_param(Param::FilterCutoff(cutoff));
+                }
+                Some(IntercoreMessage::FilterResonance { resonance }) => {
+                    info!("FilterResonance: resonance: {}", resonance);
+                    poly_synth.set_param(Param::FilterResonance(resonance));
+                }
+                Some(IntercoreMessage::FilterKeyTrack { amount }) => {
+                    info!("FilterKeyTrack: amount: {}", amount);
+                    poly_synth.set_param(Param::FilterKeyTrack(amount));
+                }
+                Some(IntercoreMessage::FilterAttack { attack_ms }) => {
+                    info!("FilterAttack: attack_ms: {}", attack_ms);
+                    poly_synth.set_param(Param::FilterAttack(attack_ms));
+                }
+                Some(IntercoreMessage::FilterDecay { decay_ms }) => {
+                    info!("FilterDecay: decay_ms: {}", decay_ms);
+                    poly_synth.set_param(Param::FilterDecay(decay_ms));
+                }
+                Some(IntercoreMessage::FilterSustain { sustain_level }) => {
+                    info!("FilterSustain: sustain_level: {}", sustain_level);
+                    poly_synth.set_param(Param::FilterSustain(sustain_level));
+                }
+                Some(IntercoreMessage::FilterRelease { release_ms }) => {
+                    info!("FilterRelease: release_ms: {}", release_ms);
+                    poly_synth.set_param(Param::FilterRelease(release_ms));
+                }
+                Some(IntercoreMessage::FilterEnvDepth { depth }) => {
+                    info!("FilterEnvDepth: depth: {}", depth);
+                    poly_synth.set_param(Param::FilterEnvDepth(depth));
+                }
+                Some(IntercoreMessage::FilterType { filter_type }) => {
+                    info!("FilterType: filter_type: {:?}", filter_type);
+                    poly_synth.set_param(Param::FilterType(filter_type));
+                }
+                Some(IntercoreMessage::FilterDrive { drive }) => {
+                    info!("FilterDrive: drive: {}", drive);
+                    poly_synth.set_param(Param::FilterDrive(drive));
+                }
+                Some(IntercoreMessage::ModAttack { attack_ms }) => {
+                    info!("ModAttack: attack_ms: {}", attack_ms);
+                    poly_synth.set_param(Param::ModAttack(attack_ms));
+                }
+                Some(IntercoreMessage::ModDecay { decay_ms }) => {
+                    info!("ModDecay: decay_ms: {}", decay_ms);
+                    poly_synth.set_param(Param::ModDecay(decay_ms));
+                }
+                Some(IntercoreMessage::ModSustain { sustain_level }) => {
+                    info!("ModSustain: sustain_level: {}", sustain_level);
+                    poly_synth.set_param(Param::ModSustain(sustain_level));
+                }
+                Some(IntercoreMessage::ModRelease { release_ms }) => {
+                    info!("ModRelease: release_ms: {}", release_ms);
+                    poly_synth.set_param(Param::ModRelease(release_ms));
+                }
+                Some(IntercoreMessage::Osc2Waveform { waveform }) => {
+                    info!("Osc2Waveform: waveform: {:?}", waveform);
+                    poly_synth.set_param(Param::Osc2Wavetable(wavetable(waveform)));
+                }
+                Some(IntercoreMessage::Osc2Coarse { semitones }) => {
+                    info!("Osc2Coarse: semitones: {}", semitones);
+                    poly_synth.set_param(Param::Osc2Coarse(semitones));
+                }
+                Some(IntercoreMessage::Osc2Fine { cents }) => {
+                    info!("Osc2Fine: cents: {}", cents);
+                    poly_synth.set_param(Param::Osc2Fine(cents));
+                }
+                Some(IntercoreMessage::Osc2Level { level }) => {
+                    info!("Osc2Level: level: {}", level);
+                    poly_synth.set_param(Param::Osc2Level(level));
+                }
+                Some(IntercoreMessage::Osc2Sync { on }) => {
+                    info!("Osc2Sync: on: {}", on);
+                    poly_synth.set_param(Param::Osc2Sync(on));
+                }
+                Some(IntercoreMessage::Osc2Ring { on }) => {
+                    info!("Osc2Ring: on: {}", on);
+                    poly_synth.set_param(Param::Osc2Ring(on));
+                }
+                Some(IntercoreMessage::SubShape { shape }) => {
+                    info!("SubShape: shape: {:?}", shape);
+                    poly_synth.set_param(Param::SubShape(shape));
+                }
+                Some(IntercoreMessage::SubOctave { octaves }) => {
+                    info!("SubOctave: octaves: {}", octaves);
+                    poly_synth.set_param(Param::SubOctave(octaves));
+                }
+                Some(IntercoreMessage::SubLevel { level }) => {
+                    info!("SubLevel: level: {}", level);
+                    poly_synth.set_param(Param::SubLevel(level));
+                }
+                Some(IntercoreMessage::NoiseColor { color }) => {
+                    info!("NoiseColor: color: {:?}", color);
+                    poly_synth.set_param(Param::NoiseColor(color));
+                }
+                Some(IntercoreMessage::NoiseLevel { level }) => {
+                    info!("NoiseLevel: level: {}", level);
+                    poly_synth.set_param(Param::NoiseLevel(level));
+                }
+                Some(IntercoreMessage::PulseWidth { width }) => {
+                    info!("PulseWidth: width: {}", width);
+                    poly_synth.set_param(Param::PulseWidth(width));
+                }
+                Some(IntercoreMessage::Engine { engine }) => {
+                    info!("Engine: engine: {:?}", engine);
+                    poly_synth.set_engine(engine);
+                }
+                Some(IntercoreMessage::FmAlgorithm { algorithm }) => {
+                    info!("FmAlgorithm: algorithm: {:?}", algorithm);
+                    poly_synth.set_param(Param::FmAlgorithm(algorithm));
+                }
+                Some(IntercoreMessage::FmRatio { op, ratio_halves }) => {
+                    info!("FmRatio: op: {}, ratio_halves: {}", op, ratio_halves);
+                    poly_synth.set_param(Param::FmRatio { op, ratio_halves });
+                }
+                Some(IntercoreMessage::FmLevel { op, level }) => {
+                    info!("FmLevel: op: {}, level: {}", op, level);
+                    poly_synth.set_param(Param::FmLevel { op, level });
+                }
+                Some(IntercoreMessage::FmAttack { op, attack_ms }) => {
+                    info!("FmAttack: op: {}, attack_ms: {}", op, attack_ms);
+                    poly_synth.set_param(Param::FmAttack { op, attack_ms });
+                }
+                Some(IntercoreMessage::FmDecay { op, decay_ms }) => {
+                    info!("FmDecay: op: {}, decay_ms: {}", op, decay_ms);
+                    poly_synth.set_param(Param::FmDecay { op, decay_ms });
+                }
+                Some(IntercoreMessage::FmSustain { op, sustain_level }) => {
+                    info!("FmSustain: op: {}, sustain_level: {}", op, sustain_level);
+                    poly_synth.set_param(Param::FmSustain { op, sustain_level });
+                }
+                Some(IntercoreMessage::FmRelease { op, release_ms }) => {
+                    info!("FmRelease: op: {}, release_ms: {}", op, release_ms);
+                    poly_synth.set_param(Param::FmRelease { op, release_ms });
+                }
+                Some(IntercoreMessage::StringDamping { damping }) => {
+                    info!("StringDamping: damping: {}", damping);
+                    poly_synth.set_param(Param::StringDamping(damping));
+                }
+                Some(IntercoreMessage::StringDecay { decay }) => {
+                    info!("StringDecay: decay: {}", decay);
+                    poly_synth.set_param(Param::StringDecay(decay));
+                }
+                Some(IntercoreMessage::StringPickPosition { position }) => {
+                    info!("StringPickPosition: position: {}", position);
+                    poly_synth.set_param(Param::StringPickPosition(position));
+                }
+                Some(IntercoreMessage::OscillatorMode { mode }) => {
+                    info!("OscillatorMode: mode: {:?}", mode);
+                    poly_synth.set_param(Param::OscillatorMode(mode));
+                }
+                Some(IntercoreMessage::PhaseDistortion { amount }) => {
+                    info!("PhaseDistortion: amount: {}", amount);
+                    poly_synth.set_param(Param::PhaseDistortion(amount));
+                }
+                Some(IntercoreMessage::ShaperMode { mode }) => {
+                    info!("ShaperMode: mode: {:?}", mode);
+                    poly_synth.set_param(Param::ShaperMode(mode));
+                }
+                Some(IntercoreMessage::ShaperDrive { drive }) => {
+                    info!("ShaperDrive: drive: {}", drive);
+                    poly_synth.set_param(Param::ShaperDrive(drive));
+                }
+                Some(IntercoreMessage::Drawbar { drawbar, level }) => {
+                    info!("Drawbar: drawbar: {}, level: {}", drawbar, level);
+                    poly_synth.set_param(Param::Drawbar { drawbar, level });
+                }
+                Some(IntercoreMessage::DrawbarPreset { preset }) => {
+                    info!("DrawbarPreset: preset: {:?}", preset);
+                    poly_synth.set_param(Param::DrawbarPreset(preset));
+                }
+                Some(IntercoreMessage::DrawbarTilt { tilt }) => {
+                    info!("DrawbarTilt: tilt: {}", tilt);
+                    poly_synth.set_param(Param::DrawbarTilt(tilt));
+                }
+                Some(IntercoreMessage::DrumHit { drum, velocity }) => {
+                    info!("DrumHit: drum: {:?}, velocity: {}", drum, velocity);
+                    drum_kit.hit(drum, velocity);
+                }
+                Some(IntercoreMessage::DrumMode { on }) => {
+                    info!("DrumMode: on: {}", on);
+                    drum_kit.set_enabled(on);
+                }
+                Some(IntercoreMessage::DrumSilence) => {
+                    info!("DrumSilence");
+                    drum_kit.silence();
+                }
+                Some(IntercoreMessage::ModalMaterial { material }) => {
+                    info!("ModalMaterial: material: {:?}", material);
+                    poly_synth.set_param(Param::ModalMaterial(material));
+                }
+                Some(IntercoreMessage::ModalExciter { exciter }) => {
+                    info!("ModalExciter: exciter: {:?}", exciter);
+                    poly_synth.set_param(Param::ModalExciter(exciter));
+                }
+                Some(IntercoreMessage::ModalBrightness { brightness }) => {
+                    info!("ModalBrightness: brightness: {}", brightness);
+                    poly_synth.set_param(Param::ModalBrightness(brightness));
+                }
+                Some(IntercoreMessage::ModalDecay { decay }) => {
+                    info!("ModalDecay: decay: {}", decay);
+                    poly_synth.set_param(Param::ModalDecay(decay));
+                }
+                Some(IntercoreMessage::AttackCurve { curve }) => {
+                    info!("AttackCurve: curve: {}", curve);
+                    poly_synth.set_param(Param::AttackCurve(curve));
+                }
+                Some(IntercoreMessage::DecayCurve { curve }) => {
+                    info!("DecayCurve: curve: {}", curve);
+                    poly_synth.set_param(Param::DecayCurve(curve));
+                }
+                Some(IntercoreMessage::ReleaseCurve { curve }) => {
+                    info!("ReleaseCurve: curve: {}", curve);
+                    poly_synth.set_param(Param::ReleaseCurve(curve));
+                }
+                Some(IntercoreMessage::SustainPedal { on }) => {
+                    info!("SustainPedal: on: {}", on);
+                    poly_synth.sustain_pedal(on);
+                }
+                Some(IntercoreMessage::SostenutoPedal { on }) => {
+                    info!("SostenutoPedal: on: {}", on);
+                    poly_synth.sostenuto_pedal(on);
+                }
+                Some(IntercoreMessage::AllSoundOff) => {
+                    info!("AllSoundOff");
+                    poly_synth.all_sound_off();
+                }
+                Some(IntercoreMessage::ResetAllControllers) => {
+                    info!("ResetAllControllers");
+                    poly_synth.reset_all_controllers();
+                }
+                Some(IntercoreMessage::AllNotesOff) => {
+                    info!("AllNotesOff");
+                    poly_synth.all_notes_off();
+                }
+                Some(IntercoreMessage::PartReserve { voices }) => {
+                    info!("PartReserve: voices: {}", voices);
+                    poly_synth.set_part_reserve(voices);
+                }
+                Some(IntercoreMessage::PitchBend { cents }) => {
+                    info!("PitchBend: cents: {}", cents);
+                    poly_synth.pitch_bend(cents);
+                }
+                Some(IntercoreMessage::Timbre { timbre }) => {
+                    info!("Timbre: timbre: {}", timbre);
+                    poly_synth.set_param(Param::Timbre(timbre));
+                }
+                Some(IntercoreMessage::FlashLock) => {
+                    storage::park();
+                    // Don't count the flash write as time the voices have to catch up on
+                    previous_time_us = loop_timer.get_counter_low();
                 }
                 None => {
                     info!("Unknown message: {}", word);
@@ -158,6 +506,216 @@ fn core1_task(loop_timer: &bsp::hal::timer::Timer) -> ! {
     }
 }
 
+fn wavetable(waveform: intercore::Waveform) -> &'static [u8; wavetables::WAVETABLE_SIZE] {
+    match waveform {
+        intercore::Waveform::Sine => &wavetables::SINE_WAVETABLE,
+        intercore::Waveform::Square => &wavetables::SQUARE_WAVETABLE,
+        intercore::Waveform::Triangle => &wavetables::TRIANGLE_WAVETABLE,
+        intercore::Waveform::Sawtooth => &wavetables::SAWTOOTH_WAVETABLE,
+    }
+}
+
+/// Route a knob or CC change to its assigned parameter, or assign it when MIDI learn is active
+fn handle_control(
+    source: ControlSource,
+    value: u16,
+    part: u8,
+    settings: &mut Settings,
+    midi_learn: &mut MidiLearn,
+    fifo: &mut SioFifo,
+) {
+    if let Some(parameter) = midi_learn.capture(&mut settings.control_map, source) {
+        info!("MIDI learn: {:?} assigned to {:?}", source, parameter);
+        settings.save(fifo);
+    }
+
+    if let Some(parameter) = settings.control_map.parameter(source) {
+        let msg = parameter.message(source.scale(value));
+        fifo.write_blocking(msg.to_u32_for_part(part));
+    }
+}
+
+/// Handle a MIDI CC received for a part
+fn handle_control_change(
+    control: u8,
+    value: u8,
+    part: u8,
+    settings: &mut Settings,
+    midi_learn: &mut MidiLearn,
+    fifo: &mut SioFifo,
+) {
+    let msg = match control {
+        MOD_WHEEL_CC => IntercoreMessage::ModWheel { value },
+        SUSTAIN_PEDAL_CC => IntercoreMessage::SustainPedal {
+            on: controls::pedal_pressed(value),
+        },
+        SOSTENUTO_PEDAL_CC => IntercoreMessage::SostenutoPedal {
+            on: controls::pedal_pressed(value),
+        },
+        ALL_SOUND_OFF_CC => IntercoreMessage::AllSoundOff,
+        RESET_ALL_CONTROLLERS_CC => IntercoreMessage::ResetAllControllers,
+        ALL_NOTES_OFF_CC => IntercoreMessage::AllNotesOff,
+        // The other channel mode messages are not supported
+        _ if controls::is_channel_mode(control) => return,
+        _ => {
+            let source = ControlSource::ControlChange(control);
+            handle_control(source, value as u16, part, settings, midi_learn, fifo);
+            return;
+        }
+    };
+    fifo.write_blocking(msg.to_u32_for_part(part));
+}
+
+/// Handle a message on an MPE member channel, its expression only applies to the channel's note
+/// Send a message to the voice of a member channel, channels that can't be addressed are dropped
+fn write_member_message(fifo: &mut SioFifo, msg: IntercoreMessage, channel: u8) {
+    if let Some(bytes) = msg.to_u32_for_member(MPE_PART, channel) {
+        fifo.write_blocking(bytes);
+    }
+}
+
+fn handle_member_message(message: Message, channel: u8, mpe: &mut Mpe, fifo: &mut SioFifo) {
+    let msg = match message {
+        Message::NoteOn(_, note, velocity) => {
+            let msg = IntercoreMessage::NoteOn {
+                note: note.into(),
+                velocity: velocity.into(),
+            };
+            write_member_message(fifo, msg, channel);
+            // Start the note with the expression sent ahead of it
+            let expression = mpe.expression(channel);
+            let msg = IntercoreMessage::PitchBend {
+                cents: expression.pitch_bend_cents,
+            };
+            write_member_message(fifo, msg, channel);
+            let msg = IntercoreMessage::ChannelAftertouch {
+                aftertouch: expression.pressure,
+            };
+            write_member_message(fifo, msg, channel);
+            IntercoreMessage::Timbre {
+                timbre: expression.timbre,
+            }
+        }
+        Message::NoteOff(_, note, ..) => IntercoreMessage::NoteOff { note: note.into() },
+        Message::PitchWheelChange(_, lsb, msb) => IntercoreMessage::PitchBend {
+            cents: mpe.pitch_bend(channel, lsb.into(), msb.into()),
+        },
+        Message::ChannelAftertouch(_, aftertouch) => {
+            let aftertouch = u8::from(aftertouch);
+            mpe.pressure(channel, aftertouch);
+            IntercoreMessage::ChannelAftertouch { aftertouch }
+        }
+        Message::ControlChange(_, control, value) if u8::from(control.0) == TIMBRE_CC => {
+            let timbre = u8::from(value);
+            mpe.timbre(channel, timbre);
+            IntercoreMessage::Timbre { timbre }
+        }
+        // Controllers for the whole zone are sent on the master channel
+        _ => return,
+    };
+    write_member_message(fifo, msg, channel);
+}
+
+/// Apply a SysEx command to the settings and save them
+fn handle_sysex(command: SysexCommand, settings: &mut Settings, fifo: &mut SioFifo) {
+    match command {
+        SysexCommand::SetReceiveChannel(receive_channel) => {
+            set_receive_channel(receive_channel, settings, fifo);
+            return;
+        }
+        SysexCommand::SetPartCount(count) => {
+            info!("Part count: {}", count);
+            settings.part_map.set_count(count);
+            for part in settings.part_map.count()..MAX_PARTS as u8 {
+                fifo.write_blocking(IntercoreMessage::AllNotesOff.to_u32_for_part(part));
+            }
+        }
+        SysexCommand::SetPartChannel { part, channel } => {
+            info!("Part {} channel: {}", part, channel + 1);
+            settings.part_map.set_channel(part, channel);
+            fifo.write_blocking(IntercoreMessage::AllNotesOff.to_u32_for_part(part));
+        }
+        SysexCommand::SetPartReserve { part, voices } => {
+            info!("Part {} reserve: {}", part, voices);
+            settings.part_map.set_reserve(part, voices);
+            let msg = IntercoreMessage::PartReserve { voices };
+            fifo.write_blocking(msg.to_u32_for_part(part));
+        }
+        SysexCommand::SetModSlot {
+            part,
+            slot,
+            mod_slot,
+        } => {
+            info!(
+                "Part {} mod slot {}: {:?} to {:?}, depth {}",
+                part, slot, mod_slot.source, mod_slot.target, mod_slot.depth
+            );
+            settings.mod_matrix.set_slot(part, slot, mod_slot);
+            send_mod_slot(part, slot, mod_slot, fifo);
+        }
+        SysexCommand::SetDrumMode(on) => {
+            info!("Drum mode: {}", on);
+            settings.drum_mode = on;
+            // Notes the channel played on a part would be left hanging
+            all_notes_off(fifo);
+            fifo.write_blocking(IntercoreMessage::DrumMode { on }.to_u32());
+        }
+    }
+    settings.save(fifo);
+}
+
+fn send_mod_slot(part: u8, slot: u8, mod_slot: ModSlot, fifo: &mut SioFifo) {
+    let messages = [
+        IntercoreMessage::ModSource {
+            slot,
+            source: mod_slot.source,
+        },
+        IntercoreMessage::ModTarget {
+            slot,
+            target: mod_slot.target,
+        },
+        IntercoreMessage::ModDepth {
+            slot,
+            depth: mod_slot.depth,
+        },
+    ];
+    for msg in messages {
+        fifo.write_blocking(msg.to_u32_for_part(part));
+    }
+}
+
+/// The channel of the channel voice messages the synth responds to
+fn message_channel(message: &Message) -> Option<u8> {
+    match message {
+        Message::NoteOn(channel, ..)
+        | Message::NoteOff(channel, ..)
+        | Message::ChannelAftertouch(channel, ..)
+        | Message::PolyphonicAftertouch(channel, ..)
+        | Message::PitchWheelChange(channel, ..)
+        | Message::ControlChange(channel, ..) => Some(*channel as u8),
+        _ => None,
+    }
+}
+
+/// Release the notes of every part, e.g. before the channels they were played on change
+fn all_notes_off(fifo: &mut SioFifo) {
+    for part in 0..MAX_PARTS as u8 {
+        fifo.write_blocking(IntercoreMessage::AllNotesOff.to_u32_for_part(part));
+    }
+}
+
+/// Change the receive channel, releasing any notes started on the old one
+fn set_receive_channel(
+    receive_channel: ReceiveChannel,
+    settings: &mut Settings,
+    fifo: &mut SioFifo,
+) {
+    info!("Receive channel: {:?}", receive_channel);
+    all_notes_off(fifo);
+    settings.receive_channel = receive_channel;
+    settings.save(fifo);
+}
+
 #[entry]
 fn main() -> ! {
     info!("Program start");
@@ -245,78 +803,97 @@ fn main() -> ! {
 
     info!("Entering main loop");
 
+    let mut settings = Settings::load();
+    let mut midi_learn = MidiLearn::new();
+
+    for part in 0..MAX_PARTS as u8 {
+        let msg = IntercoreMessage::PartReserve {
+            voices: settings.part_map.reserve(part),
+        };
+        sio.fifo.write_blocking(msg.to_u32_for_part(part));
+        for slot in 0..modmatrix::MOD_SLOTS as u8 {
+            if let Some(mod_slot) = settings.mod_matrix.slot(part, slot) {
+                send_mod_slot(part, slot, mod_slot, &mut sio.fifo);
+            }
+        }
+    }
+    let msg = IntercoreMessage::DrumMode {
+        on: settings.drum_mode,
+    };
+    sio.fifo.write_blocking(msg.to_u32());
+    // Knobs edit the part that last received a note
+    let mut edit_part = 0;
+
+    // Every knob reads 0..=1023, the assigned parameter scales it to its own range
     let mut adsr_dials = Knobz::new(i2c_device_adsr, knobz::Address::X48).unwrap();
-    adsr_dials.set_channel_range(knobz::Channel::A0, knobz::Range::Within255); // Attack
-    adsr_dials.set_channel_range(knobz::Channel::A1, knobz::Range::Within255); // Decay
-    adsr_dials.set_channel_range(knobz::Channel::A2, knobz::Range::Within1023); // Sustain
-    adsr_dials.set_channel_range(knobz::Channel::A3, knobz::Range::Within255); // Release
+    adsr_dials.set_channel_range(knobz::Channel::A0, knobz::Range::Within1023);
+    adsr_dials.set_channel_range(knobz::Channel::A1, knobz::Range::Within1023);
+    adsr_dials.set_channel_range(knobz::Channel::A2, knobz::Range::Within1023);
+    adsr_dials.set_channel_range(knobz::Channel::A3, knobz::Range::Within1023);
 
     let mut portamento_dials = Knobz::new(i2c_device_portamento, knobz::Address::X4B).unwrap();
-    portamento_dials.set_channel_range(knobz::Channel::A3, knobz::Range::Within1023); // Portamento ms
-    portamento_dials.set_channel_range(knobz::Channel::A2, knobz::Range::Within255); // Waveform Select
+    portamento_dials.set_channel_range(knobz::Channel::A0, knobz::Range::Within1023);
+    portamento_dials.set_channel_range(knobz::Channel::A1, knobz::Range::Within1023);
+    portamento_dials.set_channel_range(knobz::Channel::A2, knobz::Range::Within1023);
+    portamento_dials.set_channel_range(knobz::Channel::A3, knobz::Range::Within1023);
 
     // let mut misc_dials = Knobz::new(i2c_device_misc, knobz::Address::X4B).unwrap();
 
+    // Each press of the learn button selects the next parameter to assign
+    let mut learn_pin = pins.gpio15.into_pull_up_input();
+    let mut learn_button = Button::new(learn_pin.is_low().unwrap());
+
+    // Holding the learn button at power up selects the receive channel: the channel of the next
+    // note played is used, or pressing the button again selects omni.
+    let mut channel_select = learn_button.is_pressed();
+    if channel_select {
+        info!("Receive channel select: play a note, or press learn for omni");
+    }
+    let mut sysex_reader = SysexReader::new();
+    let mut mpe = Mpe::new();
+    let mut midi_clock = MidiClock::new();
+
     let mut previous_time_us = loop_timer.get_counter_low();
     loop {
         let current_time_us = loop_timer.get_counter_low();
         let elapsed_time_us = current_time_us.wrapping_sub(previous_time_us);
         previous_time_us = current_time_us;
 
-        adsr_dials.update(elapsed_time_us).and_then(|dial_change| {
-            match dial_change.channel {
-                knobz::Channel::A0 => {
-                    info!("DIAL CHANGE: {}", adsr_dials.channel_0);
-
-                    let msg = IntercoreMessage::AttackControl {
-                        attack_ms: dial_change.value,
-                    };
-                    sio.fifo.write_blocking(msg.to_u32());
-                }
-                knobz::Channel::A1 => {
-                    let msg = IntercoreMessage::DecayControl {
-                        decay_ms: dial_change.value,
-                    };
-                    sio.fifo.write_blocking(msg.to_u32());
-                }
-                knobz::Channel::A2 => {
-                    let msg = IntercoreMessage::SustainControl {
-                        sustain_level: dial_change.value,
-                    };
-                    sio.fifo.write_blocking(msg.to_u32());
-                }
-                knobz::Channel::A3 => {
-                    let msg = IntercoreMessage::ReleaseControl {
-                        release_ms: dial_change.value,
-                    };
-                    sio.fifo.write_blocking(msg.to_u32());
+        if learn_button.update(learn_pin.is_low().unwrap(), elapsed_time_us) {
+            if channel_select {
+                channel_select = false;
+                set_receive_channel(ReceiveChannel::Omni, &mut settings, &mut sio.fifo);
+            } else {
+                match midi_learn.select_next() {
+                    Some(parameter) => info!("MIDI learn: {:?}", parameter),
+                    None => info!("MIDI learn: off"),
                 }
             }
-            Some(())
-        });
-
-        portamento_dials
-            .update(elapsed_time_us)
-            .and_then(|dial_change| {
-                match dial_change.channel {
-                    knobz::Channel::A2 => {
-                        info!("WaveformControl: waveform: {:?}", dial_change.value);
-                        let msg = IntercoreMessage::WaveformControl {
-                            waveform: intercore::Waveform::from_u8(dial_change.value as u8)
-                                .unwrap(),
-                        };
-                        sio.fifo.write_blocking(msg.to_u32());
-                    }
-                    knobz::Channel::A3 => {
-                        let msg = IntercoreMessage::PortamentoControl {
-                            portamento_time_ms: dial_change.value as u16,
-                        };
-                        sio.fifo.write_blocking(msg.to_u32());
-                    }
-                    _ => {}
-                }
-                Some(())
-            });
+        }
+
+        if let Some(dial_change) = adsr_dials.update(elapsed_time_us) {
+            let source = ControlSource::knob(0, dial_change.channel);
+            handle_control(
+                source,
+                dial_change.value,
+                edit_part,
+                &mut settings,
+                &mut midi_learn,
+                &mut sio.fifo,
+            );
+        }
+
+        if let Some(dial_change) = portamento_dials.update(elapsed_time_us) {
+            let source = ControlSource::knob(1, dial_change.channel);
+            handle_control(
+                source,
+                dial_change.value,
+                edit_part,
+                &mut settings,
+                &mut midi_learn,
+                &mut sio.fifo,
+            );
+        }
 
         if !usb_dev.poll(&mut [&mut midi]) {
             continue;
@@ -324,26 +901,131 @@ fn main() -> ! {
         let mut buffer = [0; 64];
 
         if let Ok(size) = midi.read(&mut buffer) {
+            // usbd-midi does not decode SysEx or the MIDI clock, so read them from the raw packets
+            for packet in buffer[..size].chunks_exact(4) {
+                if let Some(command) = sysex_reader.read_packet(packet) {
+                    handle_sysex(command, &mut settings, &mut sio.fifo);
+                }
+                if let Some(bpm_tenths) = midi_clock.read_packet(packet, current_time_us) {
+                    let msg = IntercoreMessage::Tempo { bpm_tenths };
+                    sio.fifo.write_blocking(msg.to_u32());
+                }
+            }
+
             let buffer_reader = MidiPacketBufferReader::new(&buffer, size);
 
             for packet in buffer_reader.into_iter() {
                 if let Ok(packet) = packet {
+                    if channel_select {
+                        if let Message::NoteOn(channel, ..) = &packet.message {
+                            channel_select = false;
+                            let receive_channel = ReceiveChannel::Channel(*channel as u8);
+                            set_receive_channel(receive_channel, &mut settings, &mut sio.fifo);
+                            continue;
+                        }
+                    }
+
+                    let channel = match message_channel(&packet.message) {
+                        Some(channel) => channel,
+                        None => continue,
+                    };
+
+                    if let Message::ControlChange(_, control, value) = &packet.message {
+                        let control = u8::from(control.0);
+                        if mpe::is_rpn_control(control) {
+                            if mpe.rpn_control(channel, control, u8::from(*value)) {
+                                let msg = IntercoreMessage::AllNotesOff;
+                                sio.fifo.write_blocking(msg.to_u32_for_part(MPE_PART));
+                            }
+                            continue;
+                        }
+                    }
+
+                    // The drum kit takes the GM drum channel over from the MPE zones and parts
+                    if settings.drum_mode && channel == DRUM_CHANNEL {
+                        match packet.message {
+                            Message::NoteOn(_, note, velocity) => {
+                                let velocity = u8::from(velocity);
+                                if let Some(drum) =
+                                    drums::gm_drum(note.into()).filter(|_| velocity > 0)
+                                {
+                                    let msg = IntercoreMessage::DrumHit { drum, velocity };
+                                    sio.fifo.write_blocking(msg.to_u32());
+                                }
+                            }
+                            // The drums are one-shots with no controllers, so each of these
+                            // resets just cuts them off
+                            Message::ControlChange(_, control, _)
+                                if matches!(
+                                    u8::from(control.0),
+                                    ALL_SOUND_OFF_CC | RESET_ALL_CONTROLLERS_CC | ALL_NOTES_OFF_CC
+                                ) =>
+                            {
+                                sio.fifo
+                                    .write_blocking(IntercoreMessage::DrumSilence.to_u32());
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+
+                    // MPE zones take their channels over from the parts
+                    let part = match mpe.role(channel) {
+                        ChannelRole::Member => {
+                            if let Message::NoteOn(..) = packet.message {
+                                edit_part = MPE_PART;
+                            }
+                            handle_member_message(packet.message, channel, &mut mpe, &mut sio.fifo);
+                            continue;
+                        }
+                        ChannelRole::Master => MPE_PART,
+                        ChannelRole::Normal => {
+                            match settings.part_map.part(settings.receive_channel, channel) {
+                                Some(part) => part,
+                                None => continue,
+                            }
+                        }
+                    };
+
                     match packet.message {
-                        Message::NoteOn(Channel1, note, velocity) => {
+                        Message::NoteOn(_, note, velocity) => {
                             let velocity = u8::from(velocity);
                             let note: u8 = note.into();
                             let msg = IntercoreMessage::NoteOn { note, velocity };
-                            sio.fifo.write_blocking(msg.to_u32());
+                            sio.fifo.write_blocking(msg.to_u32_for_part(part));
+                            edit_part = part;
                         }
-                        Message::NoteOff(Channel1, note, ..) => {
+                        Message::NoteOff(_, note, ..) => {
                             let note: u8 = note.into();
                             let msg = IntercoreMessage::NoteOff { note };
-                            sio.fifo.write_blocking(msg.to_u32());
+                            sio.fifo.write_blocking(msg.to_u32_for_part(part));
                         }
-                        Message::ChannelAftertouch(Channel1, aftertouch) => {
+                        Message::ChannelAftertouch(_, aftertouch) => {
                             let aftertouch = u8::from(aftertouch);
                             let msg = IntercoreMessage::ChannelAftertouch { aftertouch };
-                            sio.fifo.write_blocking(msg.to_u32());
+                            sio.fifo.write_blocking(msg.to_u32_for_part(part));
+                        }
+                        Message::PolyphonicAftertouch(_, note, pressure) => {
+                            let msg = IntercoreMessage::PolyAftertouch {
+                                note: note.into(),
+                                pressure: pressure.into(),
+                            };
+                            sio.fifo.write_blocking(msg.to_u32_for_part(part));
+                        }
+                        Message::PitchWheelChange(_, lsb, msb) => {
+                            let cents = mpe.pitch_bend(channel, lsb.into(), msb.into());
+                            let msg = IntercoreMessage::PitchBend { cents };
+                            sio.fifo.write_blocking(msg.to_u32_for_part(part));
+                        }
+                        Message::ControlChange(_, control, value) => {
+                            handle_control_change(
+                                u8::from(control.0),
+                                u8::from(value),
+                                part,
+                                &mut settings,
+                                &mut midi_learn,
+                                &mut sio.fifo,
+                            );
                         }
                         _ => {}
                     }
@@ -0,0 +1,357 @@
+//! Modal voice engine, a bank of tuned resonators for bars, bells and glass.
+//!
+//! Each mode of the struck object is a resonator, the "magic circle" pair of integrators, which
+//! rings at its frequency and dies away by its own decay. The material sets the frequencies of the
+//! modes against the note and how much faster the higher ones decay. The resonators are stepped
+//! at a fixed rate, like the string's delay line, so their tuning does not depend on the update
+//! rate.
+use crate::intercore::{ModalExciter, ModalMaterial};
+use crate::noise::Noise;
+use crate::synth::{Param, Synth};
+use crate::wavetables::{pitch_ratio, MIDI_NOTE_TO_SAMPLE_INTERVAL_NS};
+
+pub const MAX_BRIGHTNESS: u8 = 127;
+pub const MAX_DECAY: u8 = 127;
+pub const DEFAULT_BRIGHTNESS: u8 = 64;
+pub const DEFAULT_DECAY: u8 = 80;
+const MODES: usize = 6;
+/// Time between steps of the resonators, 25kHz
+const STEP_US: u32 = 40;
+/// Steps of the resonators per update are capped, a very late update drops the time it missed
+/// rather than making the next one late too
+const MAX_STEPS: u32 = 4;
+/// Modes above this part of the step rate are left out, the resonators detune near the top
+const MAX_MODE_HZ: u64 = 1_000_000 / STEP_US as u64 * 2 / 5;
+/// Bits of extra precision the resonators are computed with
+const PRECISION_BITS: u32 = 8;
+const FULL_SCALE: i32 = 127 << PRECISION_BITS;
+/// Keeps the resonators in range however hard the noise drives them
+const MAX_STATE: i32 = 4 * FULL_SCALE;
+/// Decay time of the fundamental at the shortest and longest decay
+const MIN_DECAY_US: u64 = 20_000;
+const MAX_DECAY_US: u64 = 4_000_000;
+/// Decay time once the key is released, which damps the object
+const RELEASE_DECAY_US: u64 = 120_000;
+/// Length of the noise burst that excites the resonators with `ModalExciter::Noise`
+const NOISE_BURST_US: u32 = 5_000;
+/// The noise is summed over the burst, so it is fed in at a fraction of the strike level
+const NOISE_SHIFT: u32 = 3;
+/// Samples at or below this level count as silent
+const SILENCE: i32 = 1;
+/// The level the output sits on follows the peaks, and falls by 1 / 2^n of itself each update
+const FOLLOWER_SHIFT: u32 = 8;
+/// pi as 16.16 fixed point
+const PI: u64 = 205_887;
+
+/// Frequencies of the modes as multiples of the note in 8.8 fixed point, and how much faster each
+/// mode decays for each multiple of the note above the fundamental, also 8.8
+struct Material {
+    ratios: [u32; MODES],
+    damping: u64,
+}
+
+impl Material {
+    const fn of(material: ModalMaterial) -> Self {
+        match material {
+            // A marimba bar, tuned so its first overtone is two octaves above
+            ModalMaterial::Wood => Self {
+                ratios: [256, 1_021, 2_355, 4_070, 6_246, 8_858],
+                damping: 128,
+            },
+            // A free bar, like a glockenspiel
+            ModalMaterial::Metal => Self {
+                ratios: [256, 706, 1_383, 2_287, 3_416, 4_771],
+                damping: 8,
+            },
+            // A wine glass
+            ModalMaterial::Glass => Self {
+                ratios: [256, 594, 1_088, 1_697, 2_401, 3_200],
+                damping: 24,
+            },
+            // A bell's hum, prime, minor third, fifth, nominal and the third above it
+            ModalMaterial::Bell => Self {
+                ratios: [128, 256, 305, 386, 512, 644],
+                damping: 4,
+            },
+        }
+    }
+}
+
+/// 2 sin(x) as 16.16 fixed point for an angle up to about pi / 2, from its series
+fn two_sin(x: i64) -> i64 {
+    let x2 = (x * x) >> 16;
+    let x3 = (x2 * x) >> 16;
+    let x5 = (x3 * x2) >> 16;
+    let x7 = (x5 * x2) >> 16;
+    2 * (x - x3 / 6 + x5 / 120 - x7 / 5_040)
+}
+
+#[derive(Clone, Copy)]
+struct Mode {
+    /// Frequency coefficient, 2 sin(pi f T) as 16.16 fixed point, 0 for a mode that is left out
+    coefficient: i32,
+    /// Loss of each step while the key is held, and once released, 8.24 fixed point
+    loss: i32,
+    release_loss: i32,
+    /// Share of the strike before it is normalized, 16.16 fixed point
+    weight: i32,
+    /// Gain of the noise burst into the mode, 16.16 fixed point
+    noise_gain: i32,
+    x: i32,
+    y: i32,
+}
+
+impl Mode {
+    const fn new() -> Self {
+        Self {
+            coefficient: 0,
+            loss: 0,
+            release_loss: 0,
+            weight: 0,
+            noise_gain: 0,
+            x: 0,
+            y: 0,
+        }
+    }
+
+    fn step(&mut self, input: i32, released: bool) {
+        let loss = if released {
+            i32::max(self.loss, self.release_loss)
+        } else {
+            self.loss
+        };
+        self.x += ((self.coefficient as i64 * self.y as i64) >> 16) as i32;
+        self.y -= ((self.coefficient as i64 * self.x as i64) >> 16) as i32;
+        self.y += input;
+        let keep = ((1 << 24) - loss) as i64;
+        self.x = (((self.x as i64 * keep) >> 24) as i32).clamp(-MAX_STATE, MAX_STATE);
+        self.y = (((self.y as i64 * keep) >> 24) as i32).clamp(-MAX_STATE, MAX_STATE);
+    }
+}
+
+pub struct ModalSynth {
+    modes: [Mode; MODES],
+    material: ModalMaterial,
+    exciter: ModalExciter,
+    brightness: u8,
+    decay: u8,
+    noise: Noise,
+    note: u8,
+    velocity: u8,
+    time_us: u32,
+    /// Time left of the noise burst
+    burst_us: u32,
+    pitch_bend_cents: i16,
+    /// Pitch bend of just this note, from an MPE member channel
+    note_bend_cents: i16,
+    released: bool,
+    /// Steps in a row that were silent
+    silent_steps: u32,
+    /// Sum of the resonators at the last step
+    output: i32,
+    /// Level the output sits on, following its peaks, with the extra precision
+    level: i32,
+}
+
+impl ModalSynth {
+    pub fn note(&self) -> u8 {
+        self.note
+    }
+
+    /// True once the resonators have been silent for the length of a noise burst
+    pub fn is_done(&self) -> bool {
+        self.silent_steps >= NOISE_BURST_US / STEP_US
+    }
+
+    pub fn seed_noise(&mut self, voice: usize) {
+        self.noise.seed(voice);
+    }
+
+    pub fn note_pitch_bend(&mut self, cents: i16) {
+        self.note_bend_cents = cents;
+        self.update_modes();
+    }
+
+    /// Set the frequency, decay and strike weight of each mode from the note and the controls
+    fn update_modes(&mut self) {
+        let interval_ns = MIDI_NOTE_TO_SAMPLE_INTERVAL_NS[self.note as usize] as u64;
+        let cents = self.pitch_bend_cents as i32 + self.note_bend_cents as i32;
+        // The interval table is for a 128 sample cycle, this is the period as 48.16 fixed point
+        let period_ns = (interval_ns * pitch_ratio(cents) as u64) << 7;
+        let material = Material::of(self.material);
+
+        let shortness = (MAX_DECAY - u8::min(self.decay, MAX_DECAY)) as u64;
+        let decay_us = MAX_DECAY_US
+            - (MAX_DECAY_US - MIN_DECAY_US) * shortness * shortness
+                / (MAX_DECAY as u64 * MAX_DECAY as u64);
+        let brightness =
+            ((u8::min(self.brightness, MAX_BRIGHTNESS) as i64) << 16) / MAX_BRIGHTNESS as i64;
+
+        let mut weight = 1i64 << 16;
+        for (mode, ratio) in self.modes.iter_mut().zip(material.ratios.iter()) {
+            // Frequency in Hz as 16.16 fixed point
+            let hz = (1_000_000_000u64 << 32)
+                .checked_div(period_ns)
+                .map_or(0, |hz| (hz * *ratio as u64) >> 8);
+            if hz == 0 || hz >> 16 > MAX_MODE_HZ {
+                mode.coefficient = 0;
+                mode.weight = 0;
+                continue;
+            }
+            let angle = (hz * STEP_US as u64 * PI / 1_000_000) >> 16;
+            mode.coefficient = two_sin(angle as i64) as i32;
+            // Modes above the fundamental decay faster, more so for softer materials
+            let above = (*ratio as u64).saturating_sub(256);
+            let mode_decay_us = (decay_us << 8) / (256 + ((above * material.damping) >> 8));
+            mode.loss = ((STEP_US as u64) << 24).div_ceil(mode_decay_us) as i32;
+            mode.release_loss = (((STEP_US as u64) << 24) / RELEASE_DECAY_US) as i32;
+            mode.weight = weight as i32;
+            weight = (weight * brightness) >> 16;
+        }
+    }
+
+    /// Start the resonators ringing, a mallet strikes them all at once.
+    ///
+    /// The shares of the strike are normalized here, so the noise burst only has to multiply.
+    fn excite(&mut self) {
+        let total: i64 = self.modes.iter().map(|mode| mode.weight as i64).sum();
+        let strike = FULL_SCALE as i64 * self.velocity as i64 / 127;
+        for mode in self.modes.iter_mut() {
+            let share = ((mode.weight as i64) << 16).checked_div(total).unwrap_or(0);
+            mode.x = 0;
+            mode.y = match self.exciter {
+                ModalExciter::Mallet => ((strike * share) >> 16) as i32,
+                ModalExciter::Noise => 0,
+            };
+            mode.noise_gain = ((share * self.velocity as i64 / 127) >> NOISE_SHIFT) as i32;
+        }
+        self.burst_us = match self.exciter {
+            ModalExciter::Mallet => 0,
+            ModalExciter::Noise => NOISE_BURST_US,
+        };
+    }
+
+    /// Advance the resonators by one step and return their sum
+    fn step(&mut self) -> i32 {
+        let noise = if self.burst_us > 0 {
+            self.burst_us = self.burst_us.saturating_sub(STEP_US);
+            self.noise.next_sample() << PRECISION_BITS
+        } else {
+            0
+        };
+        let mut sum = 0;
+        for mode in self.modes.iter_mut().filter(|mode| mode.coefficient != 0) {
+            mode.step((noise * mode.noise_gain) >> 16, self.released);
+            sum += mode.x;
+        }
+        if sum.abs() >> PRECISION_BITS <= SILENCE && self.burst_us == 0 {
+            self.silent_steps = self.silent_steps.saturating_add(1);
+        } else {
+            self.silent_steps = 0;
+        }
+        sum
+    }
+}
+
+impl Synth for ModalSynth {
+    fn new() -> Self {
+        let mut synth = Self {
+            modes: [Mode::new(); MODES],
+            material: ModalMaterial::default(),
+            exciter: ModalExciter::default(),
+            brightness: DEFAULT_BRIGHTNESS,
+            decay: DEFAULT_DECAY,
+            noise: Noise::new(),
+            note: 69,
+            velocity: 0,
+            time_us: 0,
+            burst_us: 0,
+            pitch_bend_cents: 0,
+            note_bend_cents: 0,
+            released: true,
+            silent_steps: NOISE_BURST_US / STEP_US,
+            output: 0,
+            level: 0,
+        };
+        synth.update_modes();
+        synth
+    }
+
+    fn update(&mut self, elapsed_time_us: u32) -> u8 {
+        if self.is_done() {
+            self.level = 0;
+            return 0;
+        }
+        self.time_us += elapsed_time_us;
+        let steps = u32::min(self.time_us / STEP_US, MAX_STEPS);
+        self.time_us %= STEP_US;
+        for _ in 0..steps {
+            self.output = self.step();
+        }
+        let sample = self.output;
+        // Like the other engines, the output sits on a level that follows the sound
+        self.level = i32::max(sample.abs(), self.level - (self.level >> FOLLOWER_SHIFT));
+        let level = i32::min(self.level, FULL_SCALE);
+        ((level + sample) >> PRECISION_BITS).clamp(0, u8::MAX as i32) as u8
+    }
+
+    fn note_on(&mut self, note: u8, velocity: u8) {
+        self.note = note;
+        self.velocity = velocity;
+        self.released = false;
+        self.silent_steps = 0;
+        self.time_us = 0;
+        self.update_modes();
+        self.excite();
+    }
+
+    fn note_off(&mut self, _note: u8) {
+        self.released = true;
+    }
+
+    fn set_param(&mut self, param: Param) {
+        match param {
+            Param::ModalMaterial(material) => {
+                self.material = material;
+                self.update_modes();
+            }
+            Param::ModalExciter(exciter) => {
+                self.exciter = exciter;
+            }
+            Param::ModalBrightness(brightness) => {
+                self.brightness = u8::min(brightness, MAX_BRIGHTNESS);
+                self.update_modes();
+            }
+            Param::ModalDecay(decay) => {
+                self.decay = u8::min(decay, MAX_DECAY);
+                self.update_modes();
+            }
+            _ => {}
+        }
+    }
+
+    fn all_sound_off(&mut self) {
+        for mode in self.modes.iter_mut() {
+            mode.x = 0;
+            mode.y = 0;
+        }
+        self.burst_us = 0;
+        self.released = true;
+        self.silent_steps = NOISE_BURST_US / STEP_US;
+    }
+
+    fn all_notes_off(&mut self) {
+        self.released = true;
+    }
+
+    fn reset_all_controllers(&mut self) {
+        self.pitch_bend_cents = 0;
+        self.note_bend_cents = 0;
+        self.update_modes();
+    }
+
+    fn pitch_bend(&mut self, cents: i16) {
+        self.pitch_bend_cents = cents;
+        self.update_modes();
+    }
+}
@@ -0,0 +1,248 @@
+//! Modulation matrix, routing per voice sources to synth parameters.
+//!
+//! Each slot scales one source by a signed depth and adds it to one target. Slots with a depth
+//! of zero are off, so a blank slot does nothing.
+use crate::intercore::{ModSource, ModTarget};
+use crate::lfo::{LFO_COUNT, LFO_MAX};
+use crate::synth::MAX_PARTS;
+
+/// Number of slots of each part
+pub const MOD_SLOTS: usize = 4;
+/// Full scale of a source, bipolar sources swing between -MOD_MAX and MOD_MAX
+pub const MOD_MAX: i32 = LFO_MAX;
+pub const MAX_MOD_DEPTH: i32 = 127;
+/// Notes this far from middle C give a full scale key track
+const KEY_TRACK_RANGE: i32 = 64;
+const MIDDLE_C: i32 = 60;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ModSlot {
+    pub source: ModSource,
+    pub target: ModTarget,
+    pub depth: i8,
+}
+
+impl ModSlot {
+    pub const SIZE: usize = 3;
+
+    pub const fn new() -> Self {
+        Self {
+            source: ModSource::Velocity,
+            target: ModTarget::Pitch,
+            depth: 0,
+        }
+    }
+
+    pub fn to_bytes(&self, bytes: &mut [u8]) {
+        bytes[0] = self.source.to_u8();
+        bytes[1] = self.target.to_u8();
+        bytes[2] = self.depth as u8;
+    }
+
+    /// A blank slot reads as velocity to pitch with no depth, which is off
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            source: ModSource::from_u8(bytes[0]).unwrap_or(ModSource::Velocity),
+            target: ModTarget::from_u8(bytes[1]).unwrap_or(ModTarget::Pitch),
+            depth: bytes[2] as i8,
+        }
+    }
+}
+
+/// Modulation matrix slots of every part.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ModMatrix {
+    slots: [[ModSlot; MOD_SLOTS]; MAX_PARTS],
+}
+
+impl ModMatrix {
+    pub const SIZE: usize = MAX_PARTS * MOD_SLOTS * ModSlot::SIZE;
+
+    pub fn new() -> Self {
+        Self {
+            slots: [[ModSlot::new(); MOD_SLOTS]; MAX_PARTS],
+        }
+    }
+
+    pub fn slot(&self, part: u8, slot: u8) -> Option<ModSlot> {
+        self.slots.get(part as usize)?.get(slot as usize).copied()
+    }
+
+    pub fn set_slot(&mut self, part: u8, slot: u8, mod_slot: ModSlot) {
+        if let Some(slots) = self.slots.get_mut(part as usize) {
+            if let Some(existing) = slots.get_mut(slot as usize) {
+                *existing = mod_slot;
+            }
+        }
+    }
+
+    pub fn to_bytes(&self, bytes: &mut [u8]) {
+        let chunks = bytes.chunks_exact_mut(ModSlot::SIZE);
+        for (slot, chunk) in self.slots.iter().flatten().zip(chunks) {
+            slot.to_bytes(chunk);
+        }
+    }
+
+    /// Settings saved before the matrix existed are all zero, which leaves every slot off
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut mod_matrix = Self::new();
+        let chunks = bytes.chunks_exact(ModSlot::SIZE);
+        for (slot, chunk) in mod_matrix.slots.iter_mut().flatten().zip(chunks) {
+            *slot = ModSlot::from_bytes(chunk);
+        }
+        mod_matrix
+    }
+}
+
+/// Current value of every source for one voice, scaled to `MOD_MAX`
+pub struct ModSources {
+    pub velocity: i32,
+    pub aftertouch: i32,
+    pub mod_wheel: i32,
+    pub lfos: [i32; LFO_COUNT],
+    pub envelope: i32,
+    pub filter_envelope: i32,
+    pub mod_envelope: i32,
+    pub key_track: i32,
+    pub knob: i32,
+}
+
+impl ModSources {
+    /// Scale a 7 bit controller value
+    pub fn from_7bit(value: u8) -> i32 {
+        value as i32 * MOD_MAX / 127
+    }
+
+    /// Scale an envelope level
+    pub fn from_envelope(level: u16) -> i32 {
+        level as i32 * MOD_MAX / crate::adsr::MAX_LEVEL as i32
+    }
+
+    /// Distance of a note from middle C, negative below it
+    pub fn key_track(note: u8) -> i32 {
+        ((note as i32 - MIDDLE_C) * MOD_MAX / KEY_TRACK_RANGE).clamp(-MOD_MAX, MOD_MAX)
+    }
+
+    fn value(&self, source: ModSource) -> i32 {
+        match source {
+            ModSource::Velocity => self.velocity,
+            ModSource::Aftertouch => self.aftertouch,
+            ModSource::ModWheel => self.mod_wheel,
+            ModSource::Lfo1 => self.lfos[0],
+            ModSource::Lfo2 => self.lfos[1],
+            ModSource::Envelope => self.envelope,
+            ModSource::FilterEnvelope => self.filter_envelope,
+            ModSource::ModEnvelope => self.mod_envelope,
+            ModSource::KeyTrack => self.key_track,
+            ModSource::Knob => self.knob,
+        }
+    }
+}
+
+/// Sum of the slots for each target, a single slot at full depth reaches `MOD_MAX`
+#[derive(Clone, Copy)]
+pub struct ModOutputs {
+    pub pitch: i32,
+    pub amplitude: i32,
+    pub wavetable_position: i32,
+    pub filter_cutoff: i32,
+    pub pulse_width: i32,
+    pub phase_distortion: i32,
+    pub shaper_drive: i32,
+}
+
+impl ModOutputs {
+    pub const fn new() -> Self {
+        Self {
+            pitch: 0,
+            amplitude: 0,
+            wavetable_position: 0,
+            filter_cutoff: 0,
+            pulse_width: 0,
+            phase_distortion: 0,
+            shaper_drive: 0,
+        }
+    }
+}
+
+pub fn evaluate(slots: &[ModSlot; MOD_SLOTS], sources: &ModSources) -> ModOutputs {
+    let mut outputs = ModOutputs::new();
+    for slot in slots.iter().filter(|slot| slot.depth != 0) {
+        let value = sources.value(slot.source) * slot.depth as i32 / MAX_MOD_DEPTH;
+        match slot.target {
+            ModTarget::Pitch => outputs.pitch += value,
+            ModTarget::Amplitude => outputs.amplitude += value,
+            ModTarget::WavetablePosition => outputs.wavetable_position += value,
+            ModTarget::FilterCutoff => outputs.filter_cutoff += value,
+            ModTarget::PulseWidth => outputs.pulse_width += value,
+            ModTarget::PhaseDistortion => outputs.phase_distortion += value,
+            ModTarget::ShaperDrive => outputs.shaper_drive += value,
+        }
+    }
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources() -> ModSources {
+        ModSources {
+            velocity: MOD_MAX,
+            aftertouch: 0,
+            mod_wheel: MOD_MAX / 2,
+            lfos: [-MOD_MAX, 0],
+            envelope: MOD_MAX / 4,
+            filter_envelope: 0,
+            mod_envelope: 0,
+            key_track: 0,
+            knob: 0,
+        }
+    }
+
+    fn slot(source: ModSource, target: ModTarget, depth: i8) -> ModSlot {
+        ModSlot {
+            source,
+            target,
+            depth,
+        }
+    }
+
+    #[test]
+    fn blank_slots_do_nothing() {
+        let outputs = evaluate(&[ModSlot::new(); MOD_SLOTS], &sources());
+        assert_eq!(outputs.pitch, 0);
+        assert_eq!(outputs.amplitude, 0);
+    }
+
+    #[test]
+    fn full_depth_reaches_full_scale() {
+        let mut slots = [ModSlot::new(); MOD_SLOTS];
+        slots[0] = slot(ModSource::Velocity, ModTarget::WavetablePosition, 127);
+        slots[1] = slot(ModSource::Lfo1, ModTarget::Amplitude, -127);
+        let outputs = evaluate(&slots, &sources());
+        assert_eq!(outputs.wavetable_position, MOD_MAX);
+        assert_eq!(outputs.amplitude, MOD_MAX);
+    }
+
+    #[test]
+    fn slots_on_one_target_add_up() {
+        let mut slots = [ModSlot::new(); MOD_SLOTS];
+        slots[0] = slot(ModSource::ModWheel, ModTarget::Pitch, 127);
+        slots[1] = slot(ModSource::Envelope, ModTarget::Pitch, 127);
+        slots[2] = slot(ModSource::Velocity, ModTarget::Pitch, -127);
+        let outputs = evaluate(&slots, &sources());
+        assert_eq!(outputs.pitch, MOD_MAX / 2 + MOD_MAX / 4 - MOD_MAX);
+    }
+
+    #[test]
+    fn matrix_round_trip() {
+        let mut mod_matrix = ModMatrix::new();
+        mod_matrix.set_slot(0, 0, slot(ModSource::ModEnvelope, ModTarget::Pitch, 127));
+        mod_matrix.set_slot(3, 3, slot(ModSource::Knob, ModTarget::Amplitude, -1));
+        let mut bytes = [0; ModMatrix::SIZE];
+        mod_matrix.to_bytes(&mut bytes);
+        assert!(ModMatrix::from_bytes(&bytes) == mod_matrix);
+        assert!(ModMatrix::from_bytes(&[0; ModMatrix::SIZE]) == ModMatrix::new());
+    }
+}
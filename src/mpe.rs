@@ -0,0 +1,167 @@
+//! MPE (MIDI Polyphonic Expression) zones and the RPNs that configure them.
+//!
+//! A zone is a master channel and a range of member channels. Controllers play every note on its
+//! own member channel, so pitch bend, channel pressure and CC74 on a member channel apply to that
+//! note alone. The master channel behaves like a normal channel for the whole zone. Zones are set
+//! up by the controller with the MPE Configuration Message, RPN 6 on the master channel.
+use defmt::info;
+
+use crate::intercore::MAX_MEMBER_CHANNEL;
+
+/// Part the MPE zones play
+pub const MPE_PART: u8 = 0;
+
+const DATA_ENTRY_CC: u8 = 6;
+const RPN_LSB_CC: u8 = 100;
+const RPN_MSB_CC: u8 = 101;
+const PITCH_BEND_SENSITIVITY_RPN: u16 = 0;
+const MPE_CONFIGURATION_RPN: u16 = 6;
+/// Sent after data entry to stop further CC6 changing the parameter
+const NULL_RPN: u16 = 0x3FFF;
+
+const LOWER_ZONE_MASTER: u8 = 0;
+const UPPER_ZONE_MASTER: u8 = 15;
+/// Pitch bend ranges in semitones the MPE specification defaults to
+const DEFAULT_BEND_RANGE: u8 = 2;
+const DEFAULT_MEMBER_BEND_RANGE: u8 = 48;
+const MAX_BEND_RANGE: u8 = 96;
+/// Both zones together can have at most 14 members, leaving room for the master channels.
+/// Channel 15 is therefore never a member, which `IntercoreMessage::to_u32_for_member` relies on.
+const MAX_MEMBERS: u8 = 14;
+const _: () = assert!(UPPER_ZONE_MASTER - 1 <= MAX_MEMBER_CHANNEL);
+const PITCH_BEND_CENTER: i32 = 8192;
+
+pub fn is_rpn_control(control: u8) -> bool {
+    matches!(control, DATA_ENTRY_CC | RPN_LSB_CC | RPN_MSB_CC)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ChannelRole {
+    /// Not part of an MPE zone
+    Normal,
+    Master,
+    Member,
+}
+
+/// Expression of the note on a member channel, kept so a new note starts with the values sent
+/// before its note on.
+#[derive(Clone, Copy)]
+pub struct Expression {
+    pub pitch_bend_cents: i16,
+    pub pressure: u8,
+    pub timbre: u8,
+}
+
+impl Expression {
+    const fn new() -> Self {
+        Self {
+            pitch_bend_cents: 0,
+            pressure: 0,
+            timbre: 0,
+        }
+    }
+}
+
+/// RPN state of all 16 channels and the MPE zones they make up.
+pub struct Mpe {
+    /// Number of member channels of each zone, 0 when the zone is off
+    lower_members: u8,
+    upper_members: u8,
+    rpns: [u16; 16],
+    bend_ranges: [u8; 16],
+    expressions: [Expression; 16],
+}
+
+impl Mpe {
+    pub fn new() -> Self {
+        Self {
+            lower_members: 0,
+            upper_members: 0,
+            rpns: [NULL_RPN; 16],
+            bend_ranges: [DEFAULT_BEND_RANGE; 16],
+            expressions: [Expression::new(); 16],
+        }
+    }
+
+    pub fn role(&self, channel: u8) -> ChannelRole {
+        debug_assert!(self.lower_members + self.upper_members <= MAX_MEMBERS);
+        let upper_start = UPPER_ZONE_MASTER - self.upper_members;
+        match channel {
+            LOWER_ZONE_MASTER if self.lower_members > 0 => ChannelRole::Master,
+            UPPER_ZONE_MASTER if self.upper_members > 0 => ChannelRole::Master,
+            _ if (1..=self.lower_members).contains(&channel) => ChannelRole::Member,
+            _ if (upper_start..UPPER_ZONE_MASTER).contains(&channel) => ChannelRole::Member,
+            _ => ChannelRole::Normal,
+        }
+    }
+
+    /// Track RPN selection and data entry, returns true when an MPE zone was configured
+    pub fn rpn_control(&mut self, channel: u8, control: u8, value: u8) -> bool {
+        let channel = channel as usize & 0x0F;
+        let rpn = self.rpns[channel];
+        match control {
+            RPN_MSB_CC => self.rpns[channel] = (rpn & 0x7F) | ((value as u16) << 7),
+            RPN_LSB_CC => self.rpns[channel] = (rpn & !0x7F) | value as u16,
+            DATA_ENTRY_CC if rpn == PITCH_BEND_SENSITIVITY_RPN => {
+                self.bend_ranges[channel] = u8::min(value, MAX_BEND_RANGE);
+            }
+            DATA_ENTRY_CC if rpn == MPE_CONFIGURATION_RPN => {
+                return self.configure_zone(channel as u8, value);
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Apply an MPE Configuration Message, the zones can not overlap so the other zone shrinks
+    /// to make room
+    fn configure_zone(&mut self, master: u8, members: u8) -> bool {
+        let members = u8::min(members, MAX_MEMBERS);
+        match master {
+            LOWER_ZONE_MASTER => {
+                self.lower_members = members;
+                self.upper_members = u8::min(self.upper_members, MAX_MEMBERS - members);
+            }
+            UPPER_ZONE_MASTER => {
+                self.upper_members = members;
+                self.lower_members = u8::min(self.lower_members, MAX_MEMBERS - members);
+            }
+            _ => return false,
+        }
+        info!(
+            "MPE zones: lower {} members, upper {} members",
+            self.lower_members, self.upper_members
+        );
+
+        for channel in 0..16u8 {
+            self.bend_ranges[channel as usize] = match self.role(channel) {
+                ChannelRole::Member => DEFAULT_MEMBER_BEND_RANGE,
+                _ => DEFAULT_BEND_RANGE,
+            };
+        }
+        self.expressions = [Expression::new(); 16];
+        true
+    }
+
+    /// Convert a 14 bit pitch bend to cents using the channel's bend range, and keep it for the
+    /// channel's next note
+    pub fn pitch_bend(&mut self, channel: u8, lsb: u8, msb: u8) -> i16 {
+        let channel = channel as usize & 0x0F;
+        let bend = (((msb as i32) << 7) | lsb as i32) - PITCH_BEND_CENTER;
+        let cents = bend * self.bend_ranges[channel] as i32 * 100 / PITCH_BEND_CENTER;
+        self.expressions[channel].pitch_bend_cents = cents as i16;
+        cents as i16
+    }
+
+    pub fn pressure(&mut self, channel: u8, pressure: u8) {
+        self.expressions[channel as usize & 0x0F].pressure = pressure;
+    }
+
+    pub fn timbre(&mut self, channel: u8, timbre: u8) {
+        self.expressions[channel as usize & 0x0F].timbre = timbre;
+    }
+
+    pub fn expression(&self, channel: u8) -> Expression {
+        self.expressions[channel as usize & 0x0F]
+    }
+}
@@ -0,0 +1,63 @@
+//! Noise source of the voices.
+use crate::intercore::NoiseColor;
+
+/// Coefficients of Paul Kellet's economy pink noise filter, as 16.16 fixed point pairs of pole
+/// and gain
+const PINK_POLES: [(i32, i32); 3] = [(65_382, 6_491), (63_111, 19_433), (37_356, 68_989)];
+const PINK_DIRECT_GAIN: i32 = 12_111;
+/// Scales the pink filter's output down so it rarely needs clipping
+const PINK_SHIFT: u32 = 2;
+const MAX_SAMPLE: i32 = 127;
+const SEED: u32 = 0x9E37_79B9;
+
+pub struct Noise {
+    color: NoiseColor,
+    /// xorshift state
+    state: u32,
+    pink: [i32; 3],
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            color: NoiseColor::White,
+            state: SEED,
+            pink: [0; 3],
+        }
+    }
+
+    /// Start the sequence somewhere of its own for each voice, so voices sounding together do not
+    /// play the same noise
+    pub fn seed(&mut self, voice: usize) {
+        // xorshift never leaves 0, so that seed is skipped
+        let state = SEED ^ (voice as u32).wrapping_mul(0x6D2B_79F5);
+        self.state = if state == 0 { SEED } else { state };
+    }
+
+    pub fn set_color(&mut self, color: NoiseColor) {
+        self.color = color;
+    }
+
+    fn white(&mut self) -> i32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 24) as i32 - 128
+    }
+
+    /// The next sample, centered on zero
+    pub fn next_sample(&mut self) -> i32 {
+        let white = self.white();
+        match self.color {
+            NoiseColor::White => white,
+            NoiseColor::Pink => {
+                let mut pink = (white * PINK_DIRECT_GAIN) >> 16;
+                for (state, (pole, gain)) in self.pink.iter_mut().zip(PINK_POLES.iter()) {
+                    *state = ((*state as i64 * *pole as i64) >> 16) as i32 + white * gain;
+                    pink += *state >> 16;
+                }
+                (pink >> PINK_SHIFT).clamp(-MAX_SAMPLE, MAX_SAMPLE)
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Sample playback voice engine, reading PCM samples from a bank in flash.
+//!
+//! The bank sits in the 1M of flash before the settings sector, left out of the `FLASH` region in
+//! `memory.x`, and is flashed separately from the firmware. All values are little endian:
+//!
+//! | Offset | Size          | Contents                                         |
+//! |--------|---------------|--------------------------------------------------|
+//! | 0      | 4             | Magic `SMPL`                                     |
+//! | 4      | 1             | Version, 1                                       |
+//! | 5      | 1             | Number of zones, up to `MAX_ZONES`               |
+//! | 6      | 2             | Reserved                                         |
+//! | 8      | 24 per zone   | Zones, see `Zone::from_bytes`                    |
+//! | ...    |               | Mono signed 8 bit samples, anywhere in the bank  |
+//!
+//! A note plays the first zone whose key and velocity ranges it falls in. A bank that is missing
+//! or does not check out has no zones, and the engine stays silent.
+use crate::adsr::{Adsr, MAX_LEVEL};
+use crate::storage::{FLASH_BASE, SETTINGS_OFFSET};
+use crate::synth::{Param, Synth};
+use crate::wavetables::pitch_ratio;
+
+pub const SAMPLE_BANK_SIZE: usize = 1024 * 1024;
+/// Offset of the sample bank from the start of flash
+const SAMPLE_BANK_OFFSET: u32 = SETTINGS_OFFSET - SAMPLE_BANK_SIZE as u32;
+const MAGIC: [u8; 4] = *b"SMPL";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 8;
+const ZONE_SIZE: usize = 24;
+const MAX_ZONES: usize = 64;
+const SAMPLE_CENTER: i32 = 128;
+/// Bits of the fraction of the play position
+const FRACTION_BITS: u32 = 32;
+
+/// A sample and the notes it plays for.
+#[derive(Clone, Copy)]
+struct Zone {
+    /// Offset of the first sample from the start of the bank
+    start: u32,
+    /// Samples in the zone
+    length: u32,
+    /// Samples from `loop_start` up to `loop_end` repeat while the note sounds, a one shot
+    /// sample has them equal
+    loop_start: u32,
+    loop_end: u32,
+    sample_rate_hz: u16,
+    /// Note the sample plays at its own pitch
+    root_note: u8,
+    low_key: u8,
+    high_key: u8,
+    low_velocity: u8,
+    high_velocity: u8,
+}
+
+impl Zone {
+    /// Start, length, loop start and loop end as u32, the sample rate as u16, then the root note,
+    /// the lowest and highest key, the lowest and highest velocity and a reserved byte
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let word = |index: usize| {
+            u32::from_le_bytes([
+                bytes[index],
+                bytes[index + 1],
+                bytes[index + 2],
+                bytes[index + 3],
+            ])
+        };
+        let zone = Self {
+            start: word(0),
+            length: word(4),
+            loop_start: word(8),
+            loop_end: word(12),
+            sample_rate_hz: u16::from_le_bytes([bytes[16], bytes[17]]),
+            root_note: bytes[18],
+            low_key: bytes[19],
+            high_key: bytes[20],
+            low_velocity: bytes[21],
+            high_velocity: bytes[22],
+        };
+        let in_bank = (zone.start as usize)
+            .checked_add(zone.length as usize)
+            .is_some_and(|end| end <= SAMPLE_BANK_SIZE);
+        let valid = in_bank
+            && zone.length > 0
+            && zone.sample_rate_hz > 0
+            && zone.loop_start <= zone.loop_end
+            && zone.loop_end <= zone.length;
+        valid.then_some(zone)
+    }
+
+    fn plays(&self, note: u8, velocity: u8) -> bool {
+        (self.low_key..=self.high_key).contains(&note)
+            && (self.low_velocity..=self.high_velocity).contains(&velocity)
+    }
+
+    fn is_looped(&self) -> bool {
+        self.loop_end > self.loop_start
+    }
+}
+
+/// The sample bank, mapped into memory by XIP
+fn bank() -> &'static [u8; SAMPLE_BANK_SIZE] {
+    unsafe { &*((FLASH_BASE + SAMPLE_BANK_OFFSET) as *const [u8; SAMPLE_BANK_SIZE]) }
+}
+
+/// The zone to play for a note, if the bank has one
+fn find_zone(bank: &[u8; SAMPLE_BANK_SIZE], note: u8, velocity: u8) -> Option<Zone> {
+    if bank[..MAGIC.len()] != MAGIC || bank[MAGIC.len()] != VERSION {
+        return None;
+    }
+    let zone_count = usize::min(bank[MAGIC.len() + 1] as usize, MAX_ZONES);
+    bank[HEADER_SIZE..HEADER_SIZE + zone_count * ZONE_SIZE]
+        .chunks_exact(ZONE_SIZE)
+        .filter_map(Zone::from_bytes)
+        .find(|zone| zone.plays(note, velocity))
+}
+
+pub struct SampleSynth {
+    envelope: Adsr,
+    zone: Option<Zone>,
+    /// Play position in samples from the start of the zone, 32.32 fixed point
+    position: u64,
+    /// Position increment per microsecond, 32.32 fixed point
+    step_per_us: u64,
+    /// Set once a one shot sample has played to the end
+    finished: bool,
+    note: u8,
+    pitch_bend_cents: i16,
+    /// Pitch bend of just this note, from an MPE member channel
+    note_bend_cents: i16,
+}
+
+impl SampleSynth {
+    pub fn note(&self) -> u8 {
+        self.note
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.finished || self.envelope.is_done()
+    }
+
+    pub fn note_pitch_bend(&mut self, cents: i16) {
+        self.note_bend_cents = cents;
+        self.update_pitch();
+    }
+
+    fn update_pitch(&mut self) {
+        let Some(zone) = self.zone else {
+            self.step_per_us = 0;
+            return;
+        };
+        let cents = (self.note as i32 - zone.root_note as i32) * 100
+            + self.pitch_bend_cents as i32
+            + self.note_bend_cents as i32;
+        // pitch_ratio is 2^(-cents/1200) as 16.16 fixed point, raising the pitch lowers it
+        let step = ((zone.sample_rate_hz as u64) << FRACTION_BITS) / 1_000_000;
+        self.step_per_us = (step << 16) / pitch_ratio(cents) as u64;
+    }
+
+    /// The sample at the play position, interpolated and centered on zero
+    fn sample(&self, zone: &Zone, samples: &[u8]) -> i32 {
+        let index = (self.position >> FRACTION_BITS) as u32;
+        let next = if zone.is_looped() && index + 1 >= zone.loop_end {
+            zone.loop_start
+        } else {
+            u32::min(index + 1, zone.length - 1)
+        };
+        let sample = samples[index as usize] as i8 as i32;
+        let next_sample = samples[next as usize] as i8 as i32;
+        let fraction = ((self.position >> (FRACTION_BITS - 8)) & 0xFF) as i32;
+        sample + (((next_sample - sample) * fraction) >> 8)
+    }
+}
+
+impl Synth for SampleSynth {
+    fn new() -> Self {
+        Self {
+            envelope: Adsr::new(),
+            zone: None,
+            position: 0,
+            step_per_us: 0,
+            finished: true,
+            note: 69,
+            pitch_bend_cents: 0,
+            note_bend_cents: 0,
+        }
+    }
+
+    fn update(&mut self, elapsed_time_us: u32) -> u8 {
+        let level = self.envelope.update(elapsed_time_us) as i32;
+        let Some(zone) = self.zone else {
+            return 0;
+        };
+        if self.finished {
+            return 0;
+        }
+        self.position += self.step_per_us * elapsed_time_us as u64;
+        let loop_start = (zone.loop_start as u64) << FRACTION_BITS;
+        let loop_end = (zone.loop_end as u64) << FRACTION_BITS;
+        if zone.is_looped() && self.position >= loop_end {
+            self.position = loop_start + (self.position - loop_end) % (loop_end - loop_start);
+        } else if self.position >> FRACTION_BITS >= zone.length as u64 {
+            self.finished = true;
+            return 0;
+        }
+        let start = zone.start as usize;
+        let samples = &bank()[start..start + zone.length as usize];
+        // Like the wavetable voices, the output sits on a level that follows the envelope
+        let sample = (SAMPLE_CENTER + self.sample(&zone, samples)) * level / MAX_LEVEL as i32;
+        sample.clamp(0, u8::MAX as i32) as u8
+    }
+
+    fn note_on(&mut self, note: u8, velocity: u8) {
+        self.note = note;
+        self.zone = find_zone(bank(), note, velocity);
+        self.position = 0;
+        self.finished = self.zone.is_none();
+        self.update_pitch();
+        self.envelope.trigger(velocity);
+    }
+
+    fn note_off(&mut self, _note: u8) {
+        self.envelope.release();
+    }
+
+    fn set_param(&mut self, param: Param) {
+        match param {
+            Param::Attack(attack_ms) => {
+                self.envelope.set_attack(attack_ms as u32);
+            }
+            Param::Decay(decay_ms) => {
+                self.envelope.set_decay(decay_ms as u32);
+            }
+            Param::Sustain(sustain_level) => {
+                self.envelope
+                    .set_sustain(u32::min(sustain_level as u32, MAX_LEVEL));
+            }
+            Param::Release(release_ms) => {
+                self.envelope.set_release(release_ms as u32);
+            }
+            Param::AttackCurve(curve) => {
+                self.envelope.set_attack_curve(curve);
+            }
+            Param::DecayCurve(curve) => {
+                self.envelope.set_decay_curve(curve);
+            }
+            Param::ReleaseCurve(curve) => {
+                self.envelope.set_release_curve(curve);
+            }
+            _ => {}
+        }
+    }
+
+    fn all_sound_off(&mut self) {
+        self.envelope.reset();
+        self.finished = true;
+    }
+
+    fn all_notes_off(&mut self) {
+        self.envelope.release();
+    }
+
+    fn reset_all_controllers(&mut self) {
+        self.pitch_bend_cents = 0;
+        self.note_bend_cents = 0;
+        self.update_pitch();
+    }
+
+    fn pitch_bend(&mut self, cents: i16) {
+        self.pitch_bend_cents = cents;
+        self.update_pitch();
+    }
+}
@@ -0,0 +1,69 @@
+//! Waveshaper, one for each voice, between the oscillators and the filter.
+//!
+//! The drive raises the gain into a wavefolder or a saturator, or takes bits away from the sample,
+//! so one wavetable gives a range of timbres as the drive is swept.
+use crate::filter;
+use crate::intercore::ShaperMode;
+
+pub const MAX_SHAPER_DRIVE: u8 = 127;
+/// Full scale of the samples, which are centered on zero
+const FULL_SCALE: i32 = 128;
+/// Gain at full drive, 8 times as 8.8 fixed point
+const MAX_GAIN: i32 = 8 << 8;
+/// Bits taken from the sample at full drive, which leaves 2
+const MAX_CRUSH_BITS: i32 = 6;
+
+pub struct Shaper {
+    mode: ShaperMode,
+    drive: u8,
+}
+
+impl Shaper {
+    pub fn new() -> Self {
+        Self {
+            mode: ShaperMode::Off,
+            drive: 0,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: ShaperMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_drive(&mut self, drive: u8) {
+        self.drive = u8::min(drive, MAX_SHAPER_DRIVE);
+    }
+
+    /// Shape a sample centered on zero
+    pub fn process(&self, input: i32) -> i32 {
+        match self.mode {
+            ShaperMode::Off => input,
+            ShaperMode::Fold => fold(self.gain(input)),
+            ShaperMode::Saturate => saturate(self.gain(input)),
+            ShaperMode::Crush => {
+                let bits = self.drive as i32 * MAX_CRUSH_BITS / MAX_SHAPER_DRIVE as i32;
+                (input >> bits) << bits
+            }
+        }
+    }
+
+    fn gain(&self, input: i32) -> i32 {
+        let gain = (1 << 8) + (MAX_GAIN - (1 << 8)) * self.drive as i32 / MAX_SHAPER_DRIVE as i32;
+        (input * gain) >> 8
+    }
+}
+
+/// Reflect the parts of a sample beyond full scale back into range, as often as it takes
+fn fold(sample: i32) -> i32 {
+    let position = (sample + FULL_SCALE).rem_euclid(4 * FULL_SCALE);
+    if position < 2 * FULL_SCALE {
+        position - FULL_SCALE
+    } else {
+        3 * FULL_SCALE - position
+    }
+}
+
+/// The filter's cubic soft clipper, which reaches full scale at 1.5 times full scale
+fn saturate(sample: i32) -> i32 {
+    filter::saturate(sample << filter::PRECISION_BITS) >> filter::PRECISION_BITS
+}
@@ -0,0 +1,306 @@
+//! Persistent settings kept in the last sector of flash.
+//!
+//! The sector is left out of the `FLASH` region in `memory.x` so flashing new firmware does not
+//! overwrite it. Settings are stored at the start of the sector with a magic number and checksum,
+//! anything else found in the sector (e.g. a blank chip) loads the defaults. New fields must be
+//! appended after the existing ones and treat a zero byte as their default, so older pages stay
+//! valid.
+//!
+//! The first page filled up, so a second page follows it as an extension. A flag in the header
+//! marks settings saved with it, and the checksum then covers both pages. Settings saved before
+//! the extension have the flag clear, their checksum covers only the first page and the page
+//! after them is erased. They load as before, with everything stored in the extension left at
+//! its default, and the next save rewrites them in the extended format. The version is unchanged,
+//! so firmware from before the extension still loads the first page of newer settings.
+use crate::controls::{ControlMap, PartMap, ReceiveChannel};
+use crate::intercore::IntercoreMessage;
+use crate::modmatrix::ModMatrix;
+use rp_pico::hal::sio::SioFifo;
+
+pub const FLASH_BASE: u32 = 0x1000_0000;
+const FLASH_SIZE: u32 = 2048 * 1024;
+const SECTOR_SIZE: u32 = 4096;
+const PAGE_SIZE: usize = 256;
+const SETTINGS_SIZE: usize = 2 * PAGE_SIZE;
+/// Offset of the settings sector from the start of flash
+pub const SETTINGS_OFFSET: u32 = FLASH_SIZE - SECTOR_SIZE;
+
+const MAGIC: [u8; 4] = *b"SLNK";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 8;
+const CHECKSUM_INDEX: usize = 5;
+/// Non-zero when the extension page was saved
+const EXTENSION_INDEX: usize = 6;
+const CONTROL_MAP_OFFSET: usize = HEADER_SIZE;
+const RECEIVE_CHANNEL_INDEX: usize = CONTROL_MAP_OFFSET + ControlMap::SIZE;
+const PART_MAP_OFFSET: usize = RECEIVE_CHANNEL_INDEX + 1;
+const MOD_MATRIX_OFFSET: usize = PART_MAP_OFFSET + PartMap::SIZE;
+const EXTENSION_OFFSET: usize = PAGE_SIZE;
+/// Offsets within the extension page
+const CONTROL_MAP_EXTENSION_OFFSET: usize = 0;
+const DRUM_MODE_EXTENSION_INDEX: usize = CONTROL_MAP_EXTENSION_OFFSET + ControlMap::EXTENSION_SIZE;
+
+/// Sent by core 1 once it is running from RAM and flash can be written
+const PARK_ACK: u32 = 0x5AFE_0001;
+/// Sent by core 0 once flash is readable again
+const PARK_RELEASE: u32 = 0x5AFE_0002;
+
+/// Registers of the SIO inter-core FIFO
+const SIO_FIFO_ST: *const u32 = 0xD000_0050 as *const u32;
+const SIO_FIFO_WR: *mut u32 = 0xD000_0054 as *mut u32;
+const SIO_FIFO_RD: *const u32 = 0xD000_0058 as *const u32;
+const SIO_FIFO_ST_VLD: u32 = 1 << 0;
+const SIO_FIFO_ST_RDY: u32 = 1 << 1;
+
+/// Copy of the second stage bootloader, run after programming to restore fast XIP
+static mut BOOT2_RAM: [u32; 64] = [0; 64];
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub control_map: ControlMap,
+    pub receive_channel: ReceiveChannel,
+    pub part_map: PartMap,
+    pub mod_matrix: ModMatrix,
+    /// Play the drum kit from the GM drum channel instead of a part
+    pub drum_mode: bool,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self {
+            control_map: ControlMap::new(),
+            receive_channel: ReceiveChannel::Channel(0),
+            part_map: PartMap::new(),
+            mod_matrix: ModMatrix::new(),
+            drum_mode: false,
+        }
+    }
+
+    /// Read the settings from flash, falling back to the defaults if none have been saved
+    pub fn load() -> Self {
+        let address = (FLASH_BASE + SETTINGS_OFFSET) as *const [u8; SETTINGS_SIZE];
+        let bytes = unsafe { core::ptr::read_volatile(address) };
+        Self::from_bytes(&bytes).unwrap_or_else(Self::new)
+    }
+
+    fn to_bytes(&self) -> [u8; SETTINGS_SIZE] {
+        let mut bytes = [0u8; SETTINGS_SIZE];
+        bytes[..MAGIC.len()].copy_from_slice(&MAGIC);
+        bytes[MAGIC.len()] = VERSION;
+        bytes[EXTENSION_INDEX] = 1;
+        let (page, extension) = bytes.split_at_mut(EXTENSION_OFFSET);
+        self.control_map.to_bytes(
+            &mut page[CONTROL_MAP_OFFSET..CONTROL_MAP_OFFSET + ControlMap::SIZE],
+            &mut extension[CONTROL_MAP_EXTENSION_OFFSET
+                ..CONTROL_MAP_EXTENSION_OFFSET + ControlMap::EXTENSION_SIZE],
+        );
+        bytes[RECEIVE_CHANNEL_INDEX] = self.receive_channel.to_u8();
+        self.part_map
+            .to_bytes(&mut bytes[PART_MAP_OFFSET..PART_MAP_OFFSET + PartMap::SIZE]);
+        self.mod_matrix
+            .to_bytes(&mut bytes[MOD_MATRIX_OFFSET..MOD_MATRIX_OFFSET + ModMatrix::SIZE]);
+        bytes[EXTENSION_OFFSET + DRUM_MODE_EXTENSION_INDEX] = self.drum_mode as u8;
+        bytes[CHECKSUM_INDEX] = checksum(&bytes[HEADER_SIZE..]);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; SETTINGS_SIZE]) -> Option<Self> {
+        // Older settings are a single page, and the page after them is left erased
+        let extended = bytes[EXTENSION_INDEX] != 0;
+        let size = if extended { SETTINGS_SIZE } else { PAGE_SIZE };
+        if bytes[..MAGIC.len()] != MAGIC
+            || bytes[MAGIC.len()] != VERSION
+            || bytes[CHECKSUM_INDEX] != checksum(&bytes[HEADER_SIZE..size])
+        {
+            return None;
+        }
+        let extension = &bytes[EXTENSION_OFFSET..];
+        Some(Self {
+            control_map: ControlMap::from_bytes(
+                &bytes[CONTROL_MAP_OFFSET..CONTROL_MAP_OFFSET + ControlMap::SIZE],
+                extended.then(|| {
+                    &extension[CONTROL_MAP_EXTENSION_OFFSET
+                        ..CONTROL_MAP_EXTENSION_OFFSET + ControlMap::EXTENSION_SIZE]
+                }),
+            ),
+            receive_channel: ReceiveChannel::from_u8(bytes[RECEIVE_CHANNEL_INDEX]),
+            part_map: PartMap::from_bytes(&bytes[PART_MAP_OFFSET..PART_MAP_OFFSET + PartMap::SIZE]),
+            mod_matrix: ModMatrix::from_bytes(
+                &bytes[MOD_MATRIX_OFFSET..MOD_MATRIX_OFFSET + ModMatrix::SIZE],
+            ),
+            drum_mode: extended && extension[DRUM_MODE_EXTENSION_INDEX] != 0,
+        })
+    }
+
+    /// Write the settings to flash.
+    ///
+    /// Core 1 executes from flash, so it is parked in RAM for the duration of the erase and
+    /// program. This stalls audio for the few tens of milliseconds a sector erase takes.
+    pub fn save(&self, fifo: &mut SioFifo) {
+        let bytes = self.to_bytes();
+
+        fifo.write_blocking(IntercoreMessage::FlashLock.to_u32());
+        while fifo.read_blocking() != PARK_ACK {}
+
+        cortex_m::interrupt::free(|_| unsafe {
+            let boot2 = core::ptr::addr_of_mut!(BOOT2_RAM);
+            core::ptr::copy_nonoverlapping(FLASH_BASE as *const u32, boot2 as *mut u32, 64);
+            let functions = RomFunctions::lookup();
+            write_page(&functions, SETTINGS_OFFSET, &bytes, boot2 as *const u32);
+        });
+
+        fifo.write_blocking(PARK_RELEASE);
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Park core 1 in RAM while core 0 writes to flash.
+///
+/// Called by core 1 on `IntercoreMessage::FlashLock`, it returns once core 0 releases it.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+pub fn park() {
+    unsafe {
+        while core::ptr::read_volatile(SIO_FIFO_ST) & SIO_FIFO_ST_RDY == 0 {}
+        core::ptr::write_volatile(SIO_FIFO_WR, PARK_ACK);
+        loop {
+            if core::ptr::read_volatile(SIO_FIFO_ST) & SIO_FIFO_ST_VLD != 0
+                && core::ptr::read_volatile(SIO_FIFO_RD) == PARK_RELEASE
+            {
+                break;
+            }
+        }
+    }
+}
+
+type RomFn = unsafe extern "C" fn();
+type RangeEraseFn = unsafe extern "C" fn(u32, usize, u32, u8);
+type RangeProgramFn = unsafe extern "C" fn(u32, *const u8, usize);
+
+/// Pointers to the bootrom flash functions, looked up before flash becomes unavailable
+struct RomFunctions {
+    connect_internal_flash: RomFn,
+    flash_exit_xip: RomFn,
+    flash_range_erase: RangeEraseFn,
+    flash_range_program: RangeProgramFn,
+    flash_flush_cache: RomFn,
+}
+
+impl RomFunctions {
+    unsafe fn lookup() -> Self {
+        Self {
+            connect_internal_flash: core::mem::transmute::<usize, RomFn>(rom_function(*b"IF")),
+            flash_exit_xip: core::mem::transmute::<usize, RomFn>(rom_function(*b"EX")),
+            flash_range_erase: core::mem::transmute::<usize, RangeEraseFn>(rom_function(*b"RE")),
+            flash_range_program: core::mem::transmute::<usize, RangeProgramFn>(rom_function(
+                *b"RP",
+            )),
+            flash_flush_cache: core::mem::transmute::<usize, RomFn>(rom_function(*b"FC")),
+        }
+    }
+}
+
+unsafe fn rom_function(tag: [u8; 2]) -> usize {
+    let lookup: unsafe extern "C" fn(*const u16, u32) -> usize =
+        core::mem::transmute(core::ptr::read(0x18 as *const u16) as usize);
+    let table = core::ptr::read(0x14 as *const u16) as *const u16;
+    lookup(table, u16::from_le_bytes(tag) as u32)
+}
+
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn write_page(
+    functions: &RomFunctions,
+    offset: u32,
+    bytes: &[u8; SETTINGS_SIZE],
+    boot2: *const u32,
+) {
+    // 4k sector erase command
+    const SECTOR_ERASE: u8 = 0x20;
+
+    (functions.connect_internal_flash)();
+    (functions.flash_exit_xip)();
+    (functions.flash_range_erase)(offset, SECTOR_SIZE as usize, SECTOR_SIZE, SECTOR_ERASE);
+    (functions.flash_range_program)(offset, bytes.as_ptr(), SETTINGS_SIZE);
+    (functions.flash_flush_cache)();
+    let boot2: unsafe extern "C" fn() = core::mem::transmute(boot2 as usize + 1);
+    boot2();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controls::{ControlSource, Parameter};
+    use crate::intercore::{ModSource, ModTarget};
+    use crate::modmatrix::ModSlot;
+
+    fn changed_settings() -> Settings {
+        let mut settings = Settings::new();
+        settings
+            .control_map
+            .assign(Parameter::Portamento, ControlSource::ControlChange(90));
+        settings
+            .control_map
+            .assign(Parameter::FilterDrive, ControlSource::ControlChange(91));
+        settings.receive_channel = ReceiveChannel::Omni;
+        settings.part_map.set_count(3);
+        settings.part_map.set_channel(2, 5);
+        settings.part_map.set_reserve(1, 2);
+        settings.mod_matrix.set_slot(
+            1,
+            3,
+            ModSlot {
+                source: ModSource::Lfo2,
+                target: ModTarget::WavetablePosition,
+                depth: -64,
+            },
+        );
+        settings.drum_mode = true;
+        settings
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let settings = changed_settings();
+        let loaded = Settings::from_bytes(&settings.to_bytes());
+        assert!(loaded.is_some_and(|loaded| loaded == settings));
+        assert!(Settings::from_bytes(&Settings::new().to_bytes())
+            .is_some_and(|loaded| loaded == Settings::new()));
+    }
+
+    #[test]
+    fn erased_or_corrupt_flash_is_rejected() {
+        assert!(Settings::from_bytes(&[0xFF; SETTINGS_SIZE]).is_none());
+        let mut bytes = changed_settings().to_bytes();
+        bytes[SETTINGS_SIZE - 1] ^= 1;
+        assert!(Settings::from_bytes(&bytes).is_none());
+        let mut bytes = changed_settings().to_bytes();
+        bytes[EXTENSION_OFFSET + DRUM_MODE_EXTENSION_INDEX] = 0;
+        assert!(Settings::from_bytes(&bytes).is_none());
+    }
+
+    #[test]
+    fn single_page_settings_load_with_extension_defaults() {
+        let settings = changed_settings();
+        let mut bytes = settings.to_bytes();
+        bytes[EXTENSION_INDEX] = 0;
+        bytes[EXTENSION_OFFSET..].fill(0xFF);
+        bytes[CHECKSUM_INDEX] = checksum(&bytes[HEADER_SIZE..PAGE_SIZE]);
+        let loaded = Settings::from_bytes(&bytes).expect("first page is valid");
+        assert!(loaded.receive_channel == settings.receive_channel);
+        assert!(loaded.part_map == settings.part_map);
+        assert!(loaded.mod_matrix == settings.mod_matrix);
+        assert!(!loaded.drum_mode);
+        let control_map = loaded.control_map;
+        assert!(matches!(
+            control_map.parameter(ControlSource::ControlChange(90)),
+            Some(Parameter::Portamento)
+        ));
+        assert!(control_map
+            .parameter(ControlSource::ControlChange(91))
+            .is_none());
+    }
+}
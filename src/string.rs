@@ -0,0 +1,218 @@
+//! Plucked string voice engine, a Karplus-Strong waveguide.
+//!
+//! A note fills a delay line of one period with a burst of noise, shaped by where the string is
+//! picked, and then circulates it through a lowpass and a loss. The delay line is stepped at the
+//! same sample interval as a wavetable of the note, so it is always a cycle long and in tune.
+use crate::noise::Noise;
+use crate::synth::{Param, Synth};
+use crate::wavetables::{pitch_ratio, MIDI_NOTE_TO_SAMPLE_INTERVAL_NS, WAVETABLE_SIZE};
+
+pub const MAX_DAMPING: u8 = 127;
+pub const MAX_DECAY: u8 = 127;
+pub const MAX_PICK_POSITION: u8 = 127;
+pub const DEFAULT_DAMPING: u8 = 64;
+pub const DEFAULT_DECAY: u8 = 96;
+/// Bits of extra precision the string is computed with
+const PRECISION_BITS: u32 = 8;
+/// Loss of each step as 16.16 fixed point, at the longest and shortest decay. There are 128
+/// steps to a period, so the longest rings for about 8 seconds at A3.
+const MIN_LOSS: i32 = 2;
+const MAX_LOSS: i32 = 400;
+/// Loss of each step once the key is released, which mutes the string in about a dozen periods
+const RELEASE_LOSS: i32 = 400;
+/// Steps of the string per update are capped, in case an update is very late
+const MAX_STEPS: u32 = WAVETABLE_SIZE as u32;
+/// Samples at or below this level count as silent
+const SILENCE: i32 = 1;
+/// The level the output sits on follows the peaks, and falls by 1 / 2^n of itself each update
+const FOLLOWER_SHIFT: u32 = 8;
+
+pub struct StringSynth {
+    delay_line: [i32; WAVETABLE_SIZE],
+    index: usize,
+    /// Delayed sample of the previous step, for the lowpass
+    previous: i32,
+    noise: Noise,
+    note: u8,
+    time_ns: u32,
+    pitch_bend_cents: i16,
+    /// Pitch bend of just this note, from an MPE member channel
+    note_bend_cents: i16,
+    /// Scales the sample interval to apply pitch bend, see `pitch_ratio`
+    pitch_ratio: u32,
+    /// Weight of the previous sample in the lowpass, 16.16 fixed point up to a half
+    damping: i32,
+    /// Loss of each step while the key is held, 16.16 fixed point
+    loss: i32,
+    pick_position: u8,
+    released: bool,
+    /// Steps in a row that were silent
+    silent_steps: u32,
+    /// Level the output sits on, following its peaks, with the extra precision
+    level: i32,
+}
+
+impl StringSynth {
+    pub fn note(&self) -> u8 {
+        self.note
+    }
+
+    /// True once the string has been silent for a whole period
+    pub fn is_done(&self) -> bool {
+        self.silent_steps >= WAVETABLE_SIZE as u32
+    }
+
+    pub fn seed_noise(&mut self, voice: usize) {
+        self.noise.seed(voice);
+    }
+
+    pub fn note_pitch_bend(&mut self, cents: i16) {
+        self.note_bend_cents = cents;
+        self.update_pitch();
+    }
+
+    fn update_pitch(&mut self) {
+        self.pitch_ratio = pitch_ratio(self.pitch_bend_cents as i32 + self.note_bend_cents as i32);
+    }
+
+    /// Fill the delay line with a noise burst. Picking away from the end of the string cancels
+    /// the harmonics that have a node at the pick, like a comb filter.
+    fn excite(&mut self, velocity: u8) {
+        for sample in self.delay_line.iter_mut() {
+            *sample = (self.noise.next_sample() * velocity as i32 / 127) << PRECISION_BITS;
+        }
+        let offset =
+            1 + self.pick_position as usize * (WAVETABLE_SIZE / 2 - 1) / MAX_PICK_POSITION as usize;
+        // Going backwards leaves the earlier samples untouched until they have been used
+        for i in (offset..WAVETABLE_SIZE).rev() {
+            self.delay_line[i] = (self.delay_line[i] - self.delay_line[i - offset]) / 2;
+        }
+        for i in 0..offset {
+            self.delay_line[i] /= 2;
+        }
+        self.index = 0;
+        self.previous = 0;
+    }
+
+    /// Advance the string by one step of the delay line
+    fn step(&mut self) {
+        let delayed = self.delay_line[self.index];
+        let filtered =
+            delayed + (((self.previous - delayed) as i64 * self.damping as i64) >> 16) as i32;
+        self.previous = delayed;
+        let loss = if self.released {
+            RELEASE_LOSS
+        } else {
+            self.loss
+        };
+        let sample = ((filtered as i64 * (65_536 - loss) as i64) >> 16) as i32;
+        self.delay_line[self.index] = sample;
+        self.index = (self.index + 1) % WAVETABLE_SIZE;
+
+        if sample.abs() >> PRECISION_BITS <= SILENCE {
+            self.silent_steps = self.silent_steps.saturating_add(1);
+        } else {
+            self.silent_steps = 0;
+        }
+    }
+}
+
+impl Synth for StringSynth {
+    fn new() -> Self {
+        let mut synth = Self {
+            delay_line: [0; WAVETABLE_SIZE],
+            index: 0,
+            previous: 0,
+            noise: Noise::new(),
+            note: 69,
+            time_ns: 0,
+            pitch_bend_cents: 0,
+            note_bend_cents: 0,
+            pitch_ratio: pitch_ratio(0),
+            damping: 0,
+            loss: 0,
+            pick_position: 0,
+            released: true,
+            silent_steps: WAVETABLE_SIZE as u32,
+            level: 0,
+        };
+        synth.set_param(Param::StringDamping(DEFAULT_DAMPING));
+        synth.set_param(Param::StringDecay(DEFAULT_DECAY));
+        synth
+    }
+
+    fn update(&mut self, elapsed_time_us: u32) -> u8 {
+        let interval_ns = MIDI_NOTE_TO_SAMPLE_INTERVAL_NS[self.note as usize];
+        let interval_ns = ((interval_ns as u64 * self.pitch_ratio as u64) >> 16) as u32;
+        if interval_ns == 0 || self.is_done() {
+            self.level = 0;
+            return 0;
+        }
+
+        self.time_ns += elapsed_time_us * 1_000;
+        let steps = u32::min(self.time_ns / interval_ns, MAX_STEPS);
+        self.time_ns %= interval_ns;
+        for _ in 0..steps {
+            self.step();
+        }
+
+        let previous_index = (self.index + WAVETABLE_SIZE - 1) % WAVETABLE_SIZE;
+        let sample = self.delay_line[previous_index];
+        // Like the other engines, the output sits on a level that follows the sound
+        self.level = i32::max(sample.abs(), self.level - (self.level >> FOLLOWER_SHIFT));
+        ((self.level + sample) >> PRECISION_BITS).clamp(0, u8::MAX as i32) as u8
+    }
+
+    fn note_on(&mut self, note: u8, velocity: u8) {
+        self.note = note;
+        self.released = false;
+        self.silent_steps = 0;
+        self.time_ns = 0;
+        self.excite(velocity);
+    }
+
+    fn note_off(&mut self, _note: u8) {
+        self.released = true;
+    }
+
+    fn set_param(&mut self, param: Param) {
+        match param {
+            Param::StringDamping(damping) => {
+                let damping = u8::min(damping, MAX_DAMPING) as i32;
+                self.damping = (1 << 15) * damping / MAX_DAMPING as i32;
+            }
+            // Longer decays lose less on each step, on a square law so the long end is finer
+            Param::StringDecay(decay) => {
+                let shortness = (MAX_DECAY - u8::min(decay, MAX_DECAY)) as i32;
+                self.loss = MIN_LOSS
+                    + (MAX_LOSS - MIN_LOSS) * shortness * shortness
+                        / (MAX_DECAY as i32 * MAX_DECAY as i32);
+            }
+            Param::StringPickPosition(position) => {
+                self.pick_position = u8::min(position, MAX_PICK_POSITION);
+            }
+            _ => {}
+        }
+    }
+
+    fn all_sound_off(&mut self) {
+        self.delay_line = [0; WAVETABLE_SIZE];
+        self.released = true;
+        self.silent_steps = WAVETABLE_SIZE as u32;
+    }
+
+    fn all_notes_off(&mut self) {
+        self.released = true;
+    }
+
+    fn reset_all_controllers(&mut self) {
+        self.pitch_bend_cents = 0;
+        self.note_bend_cents = 0;
+        self.update_pitch();
+    }
+
+    fn pitch_bend(&mut self, cents: i16) {
+        self.pitch_bend_cents = cents;
+        self.update_pitch();
+    }
+}
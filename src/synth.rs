@@ -1,25 +1,509 @@
+use crate::additive::{preset_drawbars, AdditiveSynth, DRAWBARS};
 use crate::adsr::Adsr;
+use crate::filter::{Filter, MAX_CUTOFF};
+use crate::fm::{FmSynth, OperatorSettings, FM_OPERATORS};
+use crate::intercore::{
+    DrawbarPreset, Engine, FilterMode, FilterType, FmAlgorithm, LfoShape, LfoSync, LfoTarget,
+    ModSource, ModTarget, ModalExciter, ModalMaterial, NoiseColor, OscillatorMode,
+    PressureDestination, ShaperMode, SubShape,
+};
+use crate::lfo::{Lfo, LfoSettings, LFO_COUNT, LFO_MAX, MAX_LFO_DEPTH};
+use crate::modal::{self, ModalSynth};
+use crate::modmatrix::{self, ModOutputs, ModSlot, ModSources, MOD_MAX, MOD_SLOTS};
+use crate::noise::Noise;
+use crate::sampler::SampleSynth;
+use crate::shaper::{Shaper, MAX_SHAPER_DRIVE};
+use crate::string::{StringSynth, DEFAULT_DAMPING, DEFAULT_DECAY};
 use crate::wavetables::{
-    WavetablePlayer, SAWTOOTH_WAVETABLE, SINE_WAVETABLE, SQUARE_WAVETABLE, WAVETABLE_SIZE,
+    step_wavetable, WavetablePlayer, MAX_PHASE_DISTORTION, MAX_PULSE_WIDTH, MAX_TIMBRE,
+    SAWTOOTH_WAVETABLE, SINE_WAVETABLE, SQUARE_WAVETABLE, WAVETABLES_BY_BRIGHTNESS, WAVETABLE_SIZE,
 };
 
+/// A voice engine, or the polyphonic synth that plays them.
+///
+/// Engines only have some of the parameters, `set_param` ignores the ones an engine does not have.
 pub trait Synth {
-    fn new() -> Self;
+    fn new() -> Self
+    where
+        Self: Sized;
     fn update(&mut self, elapsed_time_us: u32) -> u8;
     fn note_on(&mut self, note: u8, velocity: u8);
     fn note_off(&mut self, note: u8);
-    fn attack_control(&mut self, attack_ms: u16);
-    fn decay_control(&mut self, decay_ms: u16);
-    fn sustain_control(&mut self, sustain_level: u16);
-    fn release_control(&mut self, release_ms: u16);
-    fn set_wavetable(&mut self, wavetable: &'static [u8; WAVETABLE_SIZE]);
-    fn portamento_control(&mut self, portamento_time_ms: u16);
-    fn channel_aftertouch(&mut self, aftertouch: u8);
+    fn set_param(&mut self, param: Param);
+    fn all_sound_off(&mut self);
+    fn all_notes_off(&mut self);
+    fn reset_all_controllers(&mut self);
+    fn pitch_bend(&mut self, cents: i16);
+}
+
+/// A sound or controller setting, for `Synth::set_param`
+#[derive(Clone, Copy)]
+pub enum Param {
+    Attack(u16),
+    Decay(u16),
+    Sustain(u16),
+    Release(u16),
+    AttackCurve(i8),
+    DecayCurve(i8),
+    ReleaseCurve(i8),
+    Wavetable(&'static [u8; WAVETABLE_SIZE]),
+    Portamento(u16),
+    ChannelAftertouch(u8),
+    KeyPressure { note: u8, pressure: u8 },
+    PressureDestination(PressureDestination),
+    PressureDepth(u8),
+    ModWheel(u8),
+    VibratoRate(u16),
+    VibratoShape(LfoShape),
+    VibratoDelay(u16),
+    LfoRate { lfo: u8, rate_centihertz: u16 },
+    LfoShape { lfo: u8, shape: LfoShape },
+    LfoDepth { lfo: u8, depth: u8 },
+    LfoTarget { lfo: u8, target: LfoTarget },
+    LfoSync { lfo: u8, sync: LfoSync },
+    Tempo(u16),
+    ModSource { slot: u8, source: ModSource },
+    ModTarget { slot: u8, target: ModTarget },
+    ModDepth { slot: u8, depth: i8 },
+    ModKnob(u8),
+    FilterMode(FilterMode),
+    FilterCutoff(u8),
+    FilterResonance(u8),
+    FilterKeyTrack(u8),
+    FilterAttack(u16),
+    FilterDecay(u16),
+    FilterSustain(u16),
+    FilterRelease(u16),
+    FilterEnvDepth(u8),
+    FilterType(FilterType),
+    FilterDrive(u8),
+    ModAttack(u16),
+    ModDecay(u16),
+    ModSustain(u16),
+    ModRelease(u16),
+    Osc2Wavetable(&'static [u8; WAVETABLE_SIZE]),
+    Osc2Coarse(i8),
+    Osc2Fine(i8),
+    Osc2Level(u8),
+    Osc2Sync(bool),
+    Osc2Ring(bool),
+    SubShape(SubShape),
+    SubOctave(u8),
+    SubLevel(u8),
+    NoiseColor(NoiseColor),
+    NoiseLevel(u8),
+    PulseWidth(u8),
+    OscillatorMode(OscillatorMode),
+    PhaseDistortion(u8),
+    ShaperMode(ShaperMode),
+    ShaperDrive(u8),
+    FmAlgorithm(FmAlgorithm),
+    FmRatio { op: u8, ratio_halves: u8 },
+    FmLevel { op: u8, level: u8 },
+    FmAttack { op: u8, attack_ms: u16 },
+    FmDecay { op: u8, decay_ms: u16 },
+    FmSustain { op: u8, sustain_level: u16 },
+    FmRelease { op: u8, release_ms: u16 },
+    StringDamping(u8),
+    StringDecay(u8),
+    StringPickPosition(u8),
+    Drawbar { drawbar: u8, level: u8 },
+    DrawbarPreset(DrawbarPreset),
+    DrawbarTilt(i8),
+    ModalMaterial(ModalMaterial),
+    ModalExciter(ModalExciter),
+    ModalBrightness(u8),
+    ModalDecay(u8),
+    Timbre(u8),
+}
+
+const DEFAULT_VIBRATO_RATE_CENTIHERTZ: u16 = 550;
+/// Vibrato depth with the mod wheel all the way up
+const MAX_MOD_WHEEL_VIBRATO_CENTS: i32 = 100;
+/// Vibrato depth at full pressure
+const MAX_PRESSURE_VIBRATO_CENTS: i32 = 50;
+/// Cutoff rise at full pressure
+const MAX_PRESSURE_CUTOFF_CENTS: i32 = 3600;
+/// Pressure smoothing on each control tick, a shift of 3 is a time constant of about 8 ticks,
+/// long enough to hide the 7 bit steps
+const PRESSURE_SMOOTHING_SHIFT: u32 = 3;
+const MAX_PRESSURE_DEPTH: u8 = 127;
+/// Pitch swing of an LFO at full depth
+const MAX_LFO_PITCH_CENTS: i32 = 1200;
+/// Pitch swing of a modulation matrix slot at full depth
+const MAX_MOD_PITCH_CENTS: i32 = 1200;
+/// Time between evaluations of the modulation matrix, filter cutoff and oscillator shape
+const CONTROL_TICK_US: u32 = 1_000;
+/// Cutoff swing of a modulation matrix slot at full depth
+const MAX_MOD_CUTOFF_CENTS: i32 = 4800;
+/// Cutoff swing of the filter envelope at full depth
+const MAX_FILTER_ENV_CENTS: i32 = 7200;
+const MAX_FILTER_KEY_TRACK: u8 = 127;
+const MAX_FILTER_ENV_DEPTH: u8 = 127;
+/// Note the filter cutoff is set for, key tracking moves it for other notes
+const FILTER_KEY_TRACK_NOTE: i32 = 60;
+const MAX_OSC2_LEVEL: u8 = 127;
+const MAX_SUB_OCTAVES: u8 = 2;
+const MAX_SUB_LEVEL: u8 = 127;
+const MAX_NOISE_LEVEL: u8 = 127;
+/// Level of the oscillators in the mix, against the sub-oscillator and noise levels
+const OSC_LEVEL: i32 = 127;
+/// Offset of the samples from the wavetables, which are unsigned
+const SAMPLE_CENTER: i32 = 128;
+
+/// Scale channel or key pressure, 0..=127, to an envelope gain
+fn pressure_level(pressure: u8) -> u32 {
+    pressure as u32 * crate::adsr::MAX_LEVEL / 127
 }
 
 struct MonoSynth {
     oscilator: WavetablePlayer,
+    /// Wavetable of the first oscillator before the LFOs step it
+    wavetable: &'static [u8; WAVETABLE_SIZE],
+    wavetable_steps: i32,
+    oscilator2: WavetablePlayer,
+    /// Tuning of the second oscillator against the first
+    osc2_coarse_semitones: i8,
+    osc2_fine_cents: i8,
+    /// Mix of the second oscillator, from only the first at 0 to only the second
+    osc2_level: u8,
+    /// Restart the second oscillator's cycle with every cycle of the first
+    osc2_sync: bool,
+    /// Mix the ring modulation of the two oscillators in place of the second
+    osc2_ring: bool,
+    sub_oscilator: WavetablePlayer,
+    /// Octaves the sub-oscillator is below the first oscillator
+    sub_octaves: u8,
+    sub_level: u8,
+    noise: Noise,
+    noise_level: u8,
+    /// Gains of the oscillators, sub-oscillator and noise as 8.8 fixed point, scaled together so
+    /// the mix stays within full scale
+    osc_gain: i32,
+    sub_gain: i32,
+    noise_gain: i32,
+    /// Pulse width of the square wave before modulation
+    pulse_width: u8,
+    /// Phase distortion of the first oscillator before modulation
+    phase_distortion: u8,
+    shaper: Shaper,
+    /// Drive of the shaper before modulation
+    shaper_drive: u8,
     adsr: Adsr,
+    /// Pitch bend of the whole channel
+    pitch_bend_cents: i16,
+    /// Pitch bend of just this note, from an MPE member channel
+    note_bend_cents: i16,
+    timbre: u8,
+    channel_pressure: u8,
+    key_pressure: u8,
+    /// Pressure eased towards its target, as 16.16 fixed point
+    smoothed_pressure: i32,
+    /// Smoothed pressure after the depth, set on the control tick
+    pressure: u8,
+    pressure_destination: PressureDestination,
+    pressure_depth: u8,
+    vibrato: Lfo,
+    /// Pitch offset of the vibrato and LFOs
+    modulation_cents: i32,
+    mod_wheel: u8,
+    vibrato_delay_ms: u16,
+    /// Time since the note started, to delay and fade in the mod wheel vibrato
+    vibrato_time_us: u32,
+    lfos: [Lfo; LFO_COUNT],
+    /// Tempo of the MIDI clock, 0 until one is received
+    tempo_bpm_tenths: u16,
+    /// LFO outputs before their depth, as modulation matrix sources
+    lfo_outputs: [i32; LFO_COUNT],
+    velocity: u8,
+    /// Envelope level of the previous sample
+    envelope_level: u16,
+    mod_slots: [ModSlot; MOD_SLOTS],
+    mod_knob: u8,
+    mod_outputs: ModOutputs,
+    /// Time since the last control tick
+    control_time_us: u32,
+    filter: Filter,
+    filter_envelope: Adsr,
+    /// Filter envelope level of the last control tick
+    filter_envelope_level: u16,
+    /// Envelope that only feeds the modulation matrix
+    mod_envelope: Adsr,
+    mod_envelope_level: u16,
+    /// Cutoff before key tracking and modulation, as a MIDI note
+    filter_cutoff: u8,
+    filter_key_track: u8,
+    filter_env_depth: u8,
+}
+
+/// Sum of the LFO outputs for each target
+struct LfoModulation {
+    pitch_cents: i32,
+    /// Reduction of the envelope level
+    tremolo: u32,
+    pulse_width: i32,
+    timbre: i32,
+    /// Wavetables to step away from the selected one
+    wavetable_steps: i32,
+}
+
+impl MonoSynth {
+    fn note(&self) -> u8 {
+        self.oscilator.get_midi_note()
+    }
+
+    fn is_done(&self) -> bool {
+        self.adsr.is_done()
+    }
+
+    fn note_pitch_bend(&mut self, cents: i16) {
+        self.note_bend_cents = cents;
+        self.update_pitch();
+    }
+
+    fn update_pitch(&mut self) {
+        let cents =
+            self.pitch_bend_cents as i32 + self.note_bend_cents as i32 + self.modulation_cents;
+        self.oscilator.set_pitch_offset(cents);
+        let detune_cents = self.osc2_coarse_semitones as i32 * 100 + self.osc2_fine_cents as i32;
+        self.oscilator2.set_pitch_offset(cents + detune_cents);
+        self.sub_oscilator
+            .set_pitch_offset(cents - self.sub_octaves as i32 * 1200);
+    }
+
+    fn seed_noise(&mut self, voice: usize) {
+        self.noise.seed(voice);
+    }
+
+    /// Mix of the oscillators, sub-oscillator and noise, centered on zero
+    fn oscillators(&mut self, elapsed_time_us: u32) -> i32 {
+        let mut sample = self.main_oscillators(elapsed_time_us) * self.osc_gain;
+        if self.sub_level > 0 {
+            let sub = self.sub_oscilator.next_sample(elapsed_time_us) as i32 - SAMPLE_CENTER;
+            sample += sub * self.sub_gain;
+        }
+        if self.noise_level > 0 {
+            sample += self.noise.next_sample() * self.noise_gain;
+        }
+        sample >> 8
+    }
+
+    /// Share full scale between the oscillators, sub-oscillator and noise by their levels
+    fn update_mix(&mut self) {
+        let total = OSC_LEVEL + self.sub_level as i32 + self.noise_level as i32;
+        self.osc_gain = (OSC_LEVEL << 8) / total;
+        self.sub_gain = ((self.sub_level as i32) << 8) / total;
+        self.noise_gain = ((self.noise_level as i32) << 8) / total;
+    }
+
+    /// Mix of the two oscillators
+    fn main_oscillators(&mut self, elapsed_time_us: u32) -> i32 {
+        let sample = self.oscilator.next_sample(elapsed_time_us) as i32 - SAMPLE_CENTER;
+        if self.osc2_level == 0 {
+            return sample;
+        }
+        if self.osc2_sync && self.oscilator.wrapped() {
+            self.oscilator2.reset_phase();
+        }
+        let sample2 = self.oscilator2.next_sample(elapsed_time_us) as i32 - SAMPLE_CENTER;
+        let sample2 = if self.osc2_ring {
+            sample * sample2 / SAMPLE_CENTER
+        } else {
+            sample2
+        };
+        let level = self.osc2_level as i32;
+        (sample * (MAX_OSC2_LEVEL as i32 - level) + sample2 * level) / MAX_OSC2_LEVEL as i32
+    }
+
+    /// Ease the pressure towards the stronger of the channel and key pressure, once every control
+    /// tick, and work out the amount to modulate by after the depth is applied
+    fn smooth_pressure(&mut self) {
+        let target = (u8::max(self.channel_pressure, self.key_pressure) as i32) << 16;
+        let step = (target - self.smoothed_pressure) >> PRESSURE_SMOOTHING_SHIFT;
+        if step == 0 {
+            self.smoothed_pressure = target;
+        } else {
+            self.smoothed_pressure += step;
+        }
+        // A depth of 127 passes the pressure through whole and 0 leaves none of it
+        let depth = self.pressure_depth as i32 + 1;
+        self.pressure = (((self.smoothed_pressure >> 16) * depth) >> 7) as u8;
+    }
+
+    /// Split the pressure between its destinations: the gain to apply to the envelope, the
+    /// brightness to add to the timbre and the vibrato depth to add
+    fn apply_pressure(&self) -> (u32, u8, i32) {
+        let amount = self.pressure;
+        match self.pressure_destination {
+            PressureDestination::Amplitude => (pressure_level(amount), 0, 0),
+            PressureDestination::Brightness => (0, amount, 0),
+            PressureDestination::Vibrato => {
+                (0, 0, amount as i32 * MAX_PRESSURE_VIBRATO_CENTS / 127)
+            }
+            // Applied with the rest of the cutoff on the control tick
+            PressureDestination::FilterCutoff => (0, 0, 0),
+        }
+    }
+
+    fn apply_lfos(&mut self, elapsed_time_us: u32) -> LfoModulation {
+        let mut modulation = LfoModulation {
+            pitch_cents: 0,
+            tremolo: 0,
+            pulse_width: 0,
+            timbre: 0,
+            wavetable_steps: 0,
+        };
+        // Even at no depth, so the phase is where it should be when the depth is turned up
+        for (index, lfo) in self.lfos.iter_mut().enumerate() {
+            let depth = lfo.settings.depth as i32;
+            self.lfo_outputs[index] = lfo.update(elapsed_time_us, self.tempo_bpm_tenths);
+            let value = self.lfo_outputs[index] * depth / MAX_LFO_DEPTH as i32;
+            match lfo.settings.target {
+                LfoTarget::Pitch => {
+                    modulation.pitch_cents += value * MAX_LFO_PITCH_CENTS / LFO_MAX;
+                }
+                LfoTarget::Amplitude => {
+                    // Dips the level by up to the depth, from the peak of the LFO to its trough
+                    let peak = LFO_MAX * depth / MAX_LFO_DEPTH as i32;
+                    modulation.tremolo +=
+                        ((peak - value) as u32 * crate::adsr::MAX_LEVEL) / (2 * LFO_MAX as u32);
+                }
+                LfoTarget::PulseWidth => {
+                    modulation.pulse_width += value * MAX_PULSE_WIDTH as i32 / LFO_MAX;
+                }
+                LfoTarget::WavetablePosition => {
+                    modulation.timbre += value * MAX_TIMBRE as i32 / LFO_MAX;
+                }
+                LfoTarget::Wavetable => {
+                    // Rounded to the nearest wavetable, a full depth spans the whole set either way
+                    let span = WAVETABLES_BY_BRIGHTNESS.len() as i32 - 1;
+                    modulation.wavetable_steps += (value * span + LFO_MAX / 2).div_euclid(LFO_MAX);
+                }
+            }
+        }
+        modulation
+    }
+
+    /// Evaluate the modulation matrix and apply it with the other slow modulation, once every
+    /// control tick
+    fn control_tick(&mut self, elapsed_time_us: u32, brightness: u8, lfos: &LfoModulation) {
+        self.control_time_us += elapsed_time_us;
+        if self.control_time_us < CONTROL_TICK_US {
+            return;
+        }
+        let tick_us = self.control_time_us;
+        self.control_time_us = 0;
+        self.filter_envelope_level = self.filter_envelope.update(tick_us);
+        self.mod_envelope_level = self.mod_envelope.update(tick_us);
+        self.smooth_pressure();
+        self.update_mod_matrix();
+        self.update_filter_cutoff(elapsed_time_us);
+        self.update_shape(brightness, lfos);
+    }
+
+    /// Set the timbre, wavetable, pulse width, phase distortion and shaper drive from their knobs,
+    /// pressure, LFOs and modulation matrix
+    fn update_shape(&mut self, brightness: u8, lfos: &LfoModulation) {
+        let timbre = self.timbre as i32
+            + brightness as i32
+            + lfos.timbre
+            + self.mod_outputs.wavetable_position * MAX_TIMBRE as i32 / MOD_MAX;
+        let timbre = timbre.clamp(0, MAX_TIMBRE as i32) as u8;
+        self.oscilator.set_timbre(timbre);
+        self.oscilator2.set_timbre(timbre);
+        if lfos.wavetable_steps != self.wavetable_steps {
+            self.wavetable_steps = lfos.wavetable_steps;
+            self.oscilator
+                .set_wavetable(step_wavetable(self.wavetable, self.wavetable_steps));
+        }
+        let pulse_width = self.pulse_width as i32
+            + lfos.pulse_width
+            + self.mod_outputs.pulse_width * MAX_PULSE_WIDTH as i32 / MOD_MAX;
+        let pulse_width = pulse_width.clamp(0, MAX_PULSE_WIDTH as i32) as u8;
+        self.oscilator.set_pulse_width(pulse_width);
+        self.oscilator2.set_pulse_width(pulse_width);
+        let phase_distortion = self.phase_distortion as i32
+            + self.mod_outputs.phase_distortion * MAX_PHASE_DISTORTION as i32 / MOD_MAX;
+        let phase_distortion = phase_distortion.clamp(0, MAX_PHASE_DISTORTION as i32) as u8;
+        self.oscilator.set_phase_distortion(phase_distortion);
+        let shaper_drive = self.shaper_drive as i32
+            + self.mod_outputs.shaper_drive * MAX_SHAPER_DRIVE as i32 / MOD_MAX;
+        self.shaper
+            .set_drive(shaper_drive.clamp(0, MAX_SHAPER_DRIVE as i32) as u8);
+    }
+
+    fn update_mod_matrix(&mut self) {
+        let sources = ModSources {
+            velocity: ModSources::from_7bit(self.velocity),
+            aftertouch: ModSources::from_7bit((self.smoothed_pressure >> 16) as u8),
+            mod_wheel: ModSources::from_7bit(self.mod_wheel),
+            lfos: self.lfo_outputs,
+            envelope: ModSources::from_envelope(self.envelope_level),
+            filter_envelope: ModSources::from_envelope(self.filter_envelope_level),
+            mod_envelope: ModSources::from_envelope(self.mod_envelope_level),
+            key_track: ModSources::key_track(self.oscilator.get_midi_note()),
+            knob: ModSources::from_7bit(self.mod_knob),
+        };
+        self.mod_outputs = modmatrix::evaluate(&self.mod_slots, &sources);
+    }
+
+    /// Set the cutoff from the knob, key tracking, filter envelope, pressure and modulation matrix
+    fn update_filter_cutoff(&mut self, sample_interval_us: u32) {
+        let envelope = self.filter_envelope_level as i32;
+        let pressure_cents = if self.pressure_destination == PressureDestination::FilterCutoff {
+            self.pressure as i32 * MAX_PRESSURE_CUTOFF_CENTS / 127
+        } else {
+            0
+        };
+        let key_offset_cents =
+            (self.oscilator.get_midi_note() as i32 - FILTER_KEY_TRACK_NOTE) * 100;
+        let cutoff_cents = self.filter_cutoff as i32 * 100
+            + key_offset_cents * self.filter_key_track as i32 / MAX_FILTER_KEY_TRACK as i32
+            + envelope * self.filter_env_depth as i32 / MAX_FILTER_ENV_DEPTH as i32
+                * MAX_FILTER_ENV_CENTS
+                / crate::adsr::MAX_LEVEL as i32
+            + pressure_cents
+            + self.mod_outputs.filter_cutoff * MAX_MOD_CUTOFF_CENTS / MOD_MAX;
+        self.filter.set_cutoff(cutoff_cents, sample_interval_us);
+    }
+
+    /// Depth of the mod wheel vibrato, held off for the delay after the note starts and then
+    /// faded in over the same time again
+    fn mod_wheel_vibrato_cents(&mut self, elapsed_time_us: u32) -> i32 {
+        let depth_cents = self.mod_wheel as i32 * MAX_MOD_WHEEL_VIBRATO_CENTS / 127;
+        let delay_us = self.vibrato_delay_ms as u32 * 1_000;
+        // The time counts from the note even with the wheel down, so raising the wheel later does
+        // not start the delay over
+        if self.vibrato_time_us < 2 * delay_us {
+            self.vibrato_time_us += elapsed_time_us;
+        }
+        if depth_cents == 0 || self.vibrato_time_us >= 2 * delay_us {
+            return depth_cents;
+        }
+        if self.vibrato_time_us < delay_us {
+            return 0;
+        }
+        let fade_us = u32::min(self.vibrato_time_us - delay_us, delay_us);
+        (depth_cents as i64 * fade_us as i64 / delay_us as i64) as i32
+    }
+
+    fn update_modulation_pitch(
+        &mut self,
+        elapsed_time_us: u32,
+        vibrato_cents: i32,
+        lfo_cents: i32,
+    ) {
+        let vibrato_cents = if vibrato_cents == 0 {
+            0
+        } else {
+            self.vibrato.update(elapsed_time_us, self.tempo_bpm_tenths) * vibrato_cents / LFO_MAX
+        };
+        let modulation_cents = vibrato_cents + lfo_cents;
+        if modulation_cents != self.modulation_cents {
+            self.modulation_cents = modulation_cents;
+            self.update_pitch();
+        }
+    }
 }
 
 impl Synth for MonoSynth {
@@ -28,144 +512,1306 @@ impl Synth for MonoSynth {
         let adsr = Adsr::new();
         Self {
             oscilator: wavetable_player,
+            wavetable: &SAWTOOTH_WAVETABLE,
+            wavetable_steps: 0,
+            oscilator2: WavetablePlayer::new(&SAWTOOTH_WAVETABLE, 69),
+            osc2_coarse_semitones: 0,
+            osc2_fine_cents: 0,
+            osc2_level: 0,
+            osc2_sync: false,
+            osc2_ring: false,
+            sub_oscilator: WavetablePlayer::new(&SQUARE_WAVETABLE, 69),
+            sub_octaves: 1,
+            sub_level: 0,
+            noise: Noise::new(),
+            noise_level: 0,
+            osc_gain: 1 << 8,
+            sub_gain: 0,
+            noise_gain: 0,
+            pulse_width: 0,
+            phase_distortion: 0,
+            shaper: Shaper::new(),
+            shaper_drive: 0,
             adsr: adsr,
+            pitch_bend_cents: 0,
+            note_bend_cents: 0,
+            timbre: 0,
+            channel_pressure: 0,
+            key_pressure: 0,
+            smoothed_pressure: 0,
+            pressure: 0,
+            pressure_destination: PressureDestination::default(),
+            pressure_depth: MAX_PRESSURE_DEPTH,
+            vibrato: Lfo::new(DEFAULT_VIBRATO_RATE_CENTIHERTZ),
+            modulation_cents: 0,
+            mod_wheel: 0,
+            vibrato_delay_ms: 0,
+            vibrato_time_us: 0,
+            lfos: [Lfo::new(0), Lfo::new(0)],
+            tempo_bpm_tenths: 0,
+            lfo_outputs: [0; LFO_COUNT],
+            velocity: 0,
+            envelope_level: 0,
+            mod_slots: [ModSlot::new(); MOD_SLOTS],
+            mod_knob: 0,
+            mod_outputs: ModOutputs::new(),
+            control_time_us: 0,
+            filter: Filter::new(),
+            filter_envelope: Adsr::new(),
+            filter_envelope_level: 0,
+            mod_envelope: Adsr::new(),
+            mod_envelope_level: 0,
+            filter_cutoff: MAX_CUTOFF,
+            filter_key_track: 0,
+            filter_env_depth: 0,
         }
     }
 
     fn update(&mut self, elapsed_time_us: u32) -> u8 {
-        let level = self.adsr.update(elapsed_time_us);
-        let sample = (self.oscilator.next_sample(elapsed_time_us) as u32 * level as u32)
-            / crate::adsr::MAX_LEVEL as u32;
+        let (gain, brightness, pressure_vibrato_cents) = self.apply_pressure();
+        let mod_wheel_vibrato_cents = self.mod_wheel_vibrato_cents(elapsed_time_us);
+        let lfo_modulation = self.apply_lfos(elapsed_time_us);
+        self.control_tick(elapsed_time_us, brightness, &lfo_modulation);
+        self.update_modulation_pitch(
+            elapsed_time_us,
+            pressure_vibrato_cents + mod_wheel_vibrato_cents,
+            lfo_modulation.pitch_cents + self.mod_outputs.pitch * MAX_MOD_PITCH_CENTS / MOD_MAX,
+        );
+        // Pressure boosts the envelope in every stage, so it also follows the attack and release
+        let level = self.adsr.update(elapsed_time_us) as u32;
+        self.envelope_level = level as u16;
+        let level = u32::min(
+            level + level * gain / crate::adsr::MAX_LEVEL,
+            crate::adsr::MAX_LEVEL,
+        );
+        let level = level
+            - level * u32::min(lfo_modulation.tremolo, crate::adsr::MAX_LEVEL)
+                / crate::adsr::MAX_LEVEL;
+        // The matrix scales the level, a full negative depth silences the voice
+        let level = (level as i32 * (MOD_MAX + self.mod_outputs.amplitude) / MOD_MAX)
+            .clamp(0, crate::adsr::MAX_LEVEL as i32) as u32;
+        let sample = self.oscillators(elapsed_time_us);
+        let sample = self.shaper.process(sample);
+        let sample = (self.filter.process(sample) + SAMPLE_CENTER).clamp(0, u8::MAX as i32) as u32;
+        let sample = (sample * level as u32) / crate::adsr::MAX_LEVEL as u32;
         sample as u8
     }
 
     fn note_on(&mut self, note: u8, velocity: u8) {
         self.oscilator.set_midi_note(note);
+        self.oscilator2.set_midi_note(note);
+        self.sub_oscilator.set_midi_note(note);
         self.adsr.trigger(velocity);
+        self.filter_envelope.trigger(velocity);
+        self.mod_envelope.trigger(velocity);
+        self.velocity = velocity;
+        self.key_pressure = 0;
+        self.vibrato_time_us = 0;
+        // Evaluate the matrix on the first sample so the note starts with its modulation
+        self.control_time_us = CONTROL_TICK_US;
+        for lfo in self.lfos.iter_mut() {
+            lfo.key_on();
+        }
     }
 
     fn note_off(&mut self, _note: u8) {
         self.adsr.release();
+        self.filter_envelope.release();
+        self.mod_envelope.release();
     }
 
-    fn attack_control(&mut self, attack_ms: u16) {
-        self.adsr.set_attack(attack_ms as u32);
+    fn set_param(&mut self, param: Param) {
+        match param {
+            Param::Attack(attack_ms) => {
+                self.adsr.set_attack(attack_ms as u32);
+            }
+            Param::Decay(decay_ms) => {
+                self.adsr.set_decay(decay_ms as u32);
+            }
+            Param::Sustain(sustain_level) => {
+                self.adsr
+                    .set_sustain(core::cmp::min(sustain_level as u32, crate::adsr::MAX_LEVEL));
+            }
+            Param::Release(release_ms) => {
+                self.adsr.set_release(release_ms as u32);
+            }
+            Param::AttackCurve(curve) => {
+                self.adsr.set_attack_curve(curve);
+            }
+            Param::DecayCurve(curve) => {
+                self.adsr.set_decay_curve(curve);
+            }
+            Param::ReleaseCurve(curve) => {
+                self.adsr.set_release_curve(curve);
+            }
+            Param::Wavetable(wavetable) => {
+                self.wavetable = wavetable;
+                self.oscilator
+                    .set_wavetable(step_wavetable(wavetable, self.wavetable_steps));
+            }
+            Param::Portamento(portamento_time_ms) => {
+                self.oscilator.set_portamento(portamento_time_ms as u32);
+                self.oscilator2.set_portamento(portamento_time_ms as u32);
+                self.sub_oscilator.set_portamento(portamento_time_ms as u32);
+            }
+            Param::ChannelAftertouch(aftertouch) => {
+                self.channel_pressure = aftertouch;
+            }
+            Param::KeyPressure { note, pressure } => {
+                if note == self.oscilator.get_midi_note() {
+                    self.key_pressure = pressure;
+                }
+            }
+            Param::PressureDestination(destination) => {
+                self.pressure_destination = destination;
+            }
+            Param::PressureDepth(depth) => {
+                self.pressure_depth = u8::min(depth, MAX_PRESSURE_DEPTH);
+            }
+            Param::ModWheel(value) => {
+                self.mod_wheel = value;
+            }
+            Param::VibratoRate(rate_centihertz) => {
+                self.vibrato.set_rate(rate_centihertz);
+            }
+            Param::VibratoShape(shape) => {
+                self.vibrato.set_shape(shape);
+            }
+            Param::VibratoDelay(delay_ms) => {
+                self.vibrato_delay_ms = delay_ms;
+            }
+            Param::LfoRate {
+                lfo,
+                rate_centihertz,
+            } => {
+                if let Some(lfo) = self.lfos.get_mut(lfo as usize) {
+                    lfo.settings.rate_centihertz = rate_centihertz;
+                }
+            }
+            Param::LfoShape { lfo, shape } => {
+                if let Some(lfo) = self.lfos.get_mut(lfo as usize) {
+                    lfo.settings.shape = shape;
+                }
+            }
+            Param::LfoDepth { lfo, depth } => {
+                if let Some(lfo) = self.lfos.get_mut(lfo as usize) {
+                    lfo.settings.depth = u8::min(depth, MAX_LFO_DEPTH);
+                }
+            }
+            Param::LfoTarget { lfo, target } => {
+                if let Some(lfo) = self.lfos.get_mut(lfo as usize) {
+                    lfo.settings.target = target;
+                }
+            }
+            Param::LfoSync { lfo, sync } => {
+                if let Some(lfo) = self.lfos.get_mut(lfo as usize) {
+                    lfo.settings.sync = sync;
+                }
+            }
+            Param::Tempo(bpm_tenths) => {
+                self.tempo_bpm_tenths = bpm_tenths;
+            }
+            Param::ModSource { slot, source } => {
+                if let Some(slot) = self.mod_slots.get_mut(slot as usize) {
+                    slot.source = source;
+                }
+            }
+            Param::ModTarget { slot, target } => {
+                if let Some(slot) = self.mod_slots.get_mut(slot as usize) {
+                    slot.target = target;
+                }
+            }
+            Param::ModDepth { slot, depth } => {
+                if let Some(slot) = self.mod_slots.get_mut(slot as usize) {
+                    slot.depth = depth;
+                }
+            }
+            Param::ModKnob(value) => {
+                self.mod_knob = value;
+            }
+            Param::FilterMode(mode) => {
+                self.filter.set_mode(mode);
+            }
+            Param::FilterCutoff(cutoff) => {
+                self.filter_cutoff = u8::min(cutoff, MAX_CUTOFF);
+            }
+            Param::FilterResonance(resonance) => {
+                self.filter.set_resonance(resonance);
+            }
+            Param::FilterKeyTrack(amount) => {
+                self.filter_key_track = u8::min(amount, MAX_FILTER_KEY_TRACK);
+            }
+            Param::FilterAttack(attack_ms) => {
+                self.filter_envelope.set_attack(attack_ms as u32);
+            }
+            Param::FilterDecay(decay_ms) => {
+                self.filter_envelope.set_decay(decay_ms as u32);
+            }
+            Param::FilterSustain(sustain_level) => {
+                self.filter_envelope
+                    .set_sustain(core::cmp::min(sustain_level as u32, crate::adsr::MAX_LEVEL));
+            }
+            Param::FilterRelease(release_ms) => {
+                self.filter_envelope.set_release(release_ms as u32);
+            }
+            Param::FilterEnvDepth(depth) => {
+                self.filter_env_depth = u8::min(depth, MAX_FILTER_ENV_DEPTH);
+            }
+            Param::FilterType(filter_type) => {
+                self.filter.set_type(filter_type);
+            }
+            Param::FilterDrive(drive) => {
+                self.filter.set_drive(drive);
+            }
+            Param::ModAttack(attack_ms) => {
+                self.mod_envelope.set_attack(attack_ms as u32);
+            }
+            Param::ModDecay(decay_ms) => {
+                self.mod_envelope.set_decay(decay_ms as u32);
+            }
+            Param::ModSustain(sustain_level) => {
+                self.mod_envelope
+                    .set_sustain(core::cmp::min(sustain_level as u32, crate::adsr::MAX_LEVEL));
+            }
+            Param::ModRelease(release_ms) => {
+                self.mod_envelope.set_release(release_ms as u32);
+            }
+            Param::Osc2Wavetable(wavetable) => {
+                self.oscilator2.set_wavetable(wavetable);
+            }
+            Param::Osc2Coarse(semitones) => {
+                self.osc2_coarse_semitones = semitones;
+                self.update_pitch();
+            }
+            Param::Osc2Fine(cents) => {
+                self.osc2_fine_cents = cents;
+                self.update_pitch();
+            }
+            Param::Osc2Level(level) => {
+                self.osc2_level = u8::min(level, MAX_OSC2_LEVEL);
+            }
+            Param::Osc2Sync(on) => {
+                self.osc2_sync = on;
+            }
+            Param::Osc2Ring(on) => {
+                self.osc2_ring = on;
+            }
+            Param::SubShape(shape) => {
+                let wavetable = match shape {
+                    SubShape::Square => &SQUARE_WAVETABLE,
+                    SubShape::Sine => &SINE_WAVETABLE,
+                };
+                self.sub_oscilator.set_wavetable(wavetable);
+            }
+            Param::SubOctave(octaves) => {
+                self.sub_octaves = octaves.clamp(1, MAX_SUB_OCTAVES);
+                self.update_pitch();
+            }
+            Param::SubLevel(level) => {
+                self.sub_level = u8::min(level, MAX_SUB_LEVEL);
+                self.update_mix();
+            }
+            Param::NoiseColor(color) => {
+                self.noise.set_color(color);
+            }
+            Param::NoiseLevel(level) => {
+                self.noise_level = u8::min(level, MAX_NOISE_LEVEL);
+                self.update_mix();
+            }
+            Param::PulseWidth(width) => {
+                self.pulse_width = u8::min(width, MAX_PULSE_WIDTH);
+            }
+            Param::OscillatorMode(mode) => {
+                self.oscilator.set_mode(mode);
+            }
+            Param::PhaseDistortion(amount) => {
+                self.phase_distortion = u8::min(amount, MAX_PHASE_DISTORTION);
+            }
+            Param::ShaperMode(mode) => {
+                self.shaper.set_mode(mode);
+            }
+            Param::ShaperDrive(drive) => {
+                self.shaper_drive = u8::min(drive, MAX_SHAPER_DRIVE);
+            }
+            Param::Timbre(timbre) => {
+                self.timbre = timbre;
+            }
+            // The other engines' parameters
+            Param::FmAlgorithm(_)
+            | Param::FmRatio { .. }
+            | Param::FmLevel { .. }
+            | Param::FmAttack { .. }
+            | Param::FmDecay { .. }
+            | Param::FmSustain { .. }
+            | Param::FmRelease { .. }
+            | Param::StringDamping(_)
+            | Param::StringDecay(_)
+            | Param::StringPickPosition(_)
+            | Param::Drawbar { .. }
+            | Param::DrawbarPreset(_)
+            | Param::DrawbarTilt(_)
+            | Param::ModalMaterial(_)
+            | Param::ModalExciter(_)
+            | Param::ModalBrightness(_)
+            | Param::ModalDecay(_) => {}
+        }
     }
 
-    fn decay_control(&mut self, decay_ms: u16) {
-        self.adsr.set_decay(decay_ms as u32);
+    fn all_sound_off(&mut self) {
+        self.adsr.reset();
+        self.filter_envelope.reset();
+        self.mod_envelope.reset();
+        self.filter.reset();
     }
 
-    fn sustain_control(&mut self, sustain_level: u16) {
-        self.adsr
-            .set_sustain(core::cmp::min(sustain_level as u32, crate::adsr::MAX_LEVEL));
+    fn all_notes_off(&mut self) {
+        self.adsr.release();
+        self.filter_envelope.release();
+        self.mod_envelope.release();
     }
 
-    fn release_control(&mut self, release_ms: u16) {
-        self.adsr.set_release(release_ms as u32);
+    fn reset_all_controllers(&mut self) {
+        self.channel_pressure = 0;
+        self.key_pressure = 0;
+        self.mod_wheel = 0;
+        self.timbre = 0;
+        self.pitch_bend_cents = 0;
+        self.note_bend_cents = 0;
+        self.update_pitch();
     }
 
-    fn set_wavetable(&mut self, wavetable: &'static [u8; WAVETABLE_SIZE]) {
-        self.oscilator.set_wavetable(wavetable);
+    fn pitch_bend(&mut self, cents: i16) {
+        self.pitch_bend_cents = cents;
+        self.update_pitch();
     }
+}
+
+/// Number of parts in the multi-timbral synth
+pub const MAX_PARTS: usize = 4;
+const VOICE_COUNT: usize = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+enum VoiceState {
+    /// The key is down
+    Held,
+    /// The key is up but a pedal is keeping the note sounding
+    Pedal,
+    /// The envelope is releasing or finished
+    Released,
+}
+
+/// Sound and controller settings for one part of the multi-timbral synth.
+///
+/// Voices are shared between parts, so a voice picks up its part's settings when it starts a note.
+#[derive(Clone, Copy)]
+struct Part {
+    wavetable: &'static [u8; WAVETABLE_SIZE],
+    attack_ms: u16,
+    decay_ms: u16,
+    sustain_level: u16,
+    release_ms: u16,
+    attack_curve: i8,
+    decay_curve: i8,
+    release_curve: i8,
+    portamento_time_ms: u16,
+    aftertouch: u8,
+    pressure_destination: PressureDestination,
+    pressure_depth: u8,
+    mod_wheel: u8,
+    vibrato_rate_centihertz: u16,
+    vibrato_shape: LfoShape,
+    vibrato_delay_ms: u16,
+    lfos: [LfoSettings; LFO_COUNT],
+    mod_slots: [ModSlot; MOD_SLOTS],
+    mod_knob: u8,
+    filter_mode: FilterMode,
+    filter_cutoff: u8,
+    filter_resonance: u8,
+    filter_key_track: u8,
+    filter_attack_ms: u16,
+    filter_decay_ms: u16,
+    filter_sustain_level: u16,
+    filter_release_ms: u16,
+    filter_env_depth: u8,
+    filter_type: FilterType,
+    filter_drive: u8,
+    mod_attack_ms: u16,
+    mod_decay_ms: u16,
+    mod_sustain_level: u16,
+    mod_release_ms: u16,
+    osc2_wavetable: &'static [u8; WAVETABLE_SIZE],
+    osc2_coarse_semitones: i8,
+    osc2_fine_cents: i8,
+    osc2_level: u8,
+    osc2_sync: bool,
+    osc2_ring: bool,
+    sub_shape: SubShape,
+    sub_octaves: u8,
+    sub_level: u8,
+    noise_color: NoiseColor,
+    noise_level: u8,
+    pulse_width: u8,
+    oscillator_mode: OscillatorMode,
+    phase_distortion: u8,
+    shaper_mode: ShaperMode,
+    shaper_drive: u8,
+    engine: Engine,
+    fm_algorithm: FmAlgorithm,
+    fm_operators: [OperatorSettings; FM_OPERATORS],
+    string_damping: u8,
+    string_decay: u8,
+    string_pick_position: u8,
+    drawbars: [u8; DRAWBARS],
+    drawbar_tilt: i8,
+    modal_material: ModalMaterial,
+    modal_exciter: ModalExciter,
+    modal_brightness: u8,
+    modal_decay: u8,
+    pitch_bend_cents: i16,
+    timbre: u8,
+    /// Voices kept available for this part, other parts can not steal below it
+    reserve: u8,
+    sustain_pedal: bool,
+    sostenuto_pedal: bool,
+}
 
-    fn portamento_control(&mut self, portamento_time_ms: u16) {
-        self.oscilator.set_portamento(portamento_time_ms as u32);
+impl Part {
+    fn new() -> Self {
+        Self {
+            wavetable: &SAWTOOTH_WAVETABLE,
+            attack_ms: crate::adsr::DEFAULT_ATTACK_MS as u16,
+            decay_ms: crate::adsr::DEFAULT_DECAY_MS as u16,
+            sustain_level: crate::adsr::DEFAULT_SUSTAIN_LEVEL as u16,
+            release_ms: crate::adsr::DEFAULT_RELEASE_MS as u16,
+            attack_curve: 0,
+            decay_curve: 0,
+            release_curve: 0,
+            portamento_time_ms: 0,
+            aftertouch: 0,
+            pressure_destination: PressureDestination::Amplitude,
+            pressure_depth: MAX_PRESSURE_DEPTH,
+            mod_wheel: 0,
+            vibrato_rate_centihertz: DEFAULT_VIBRATO_RATE_CENTIHERTZ,
+            vibrato_shape: LfoShape::Sine,
+            vibrato_delay_ms: 0,
+            lfos: [LfoSettings::new(); LFO_COUNT],
+            mod_slots: [ModSlot::new(); MOD_SLOTS],
+            mod_knob: 0,
+            filter_mode: FilterMode::Lowpass,
+            filter_cutoff: MAX_CUTOFF,
+            filter_resonance: 0,
+            filter_key_track: 0,
+            filter_attack_ms: crate::adsr::DEFAULT_ATTACK_MS as u16,
+            filter_decay_ms: crate::adsr::DEFAULT_DECAY_MS as u16,
+            filter_sustain_level: crate::adsr::DEFAULT_SUSTAIN_LEVEL as u16,
+            filter_release_ms: crate::adsr::DEFAULT_RELEASE_MS as u16,
+            filter_env_depth: 0,
+            filter_type: FilterType::StateVariable,
+            filter_drive: 0,
+            mod_attack_ms: crate::adsr::DEFAULT_ATTACK_MS as u16,
+            mod_decay_ms: crate::adsr::DEFAULT_DECAY_MS as u16,
+            mod_sustain_level: crate::adsr::DEFAULT_SUSTAIN_LEVEL as u16,
+            mod_release_ms: crate::adsr::DEFAULT_RELEASE_MS as u16,
+            osc2_wavetable: &SAWTOOTH_WAVETABLE,
+            osc2_coarse_semitones: 0,
+            osc2_fine_cents: 0,
+            osc2_level: 0,
+            osc2_sync: false,
+            osc2_ring: false,
+            sub_shape: SubShape::Square,
+            sub_octaves: 1,
+            sub_level: 0,
+            noise_color: NoiseColor::White,
+            noise_level: 0,
+            pulse_width: 0,
+            oscillator_mode: OscillatorMode::Wavetable,
+            phase_distortion: 0,
+            shaper_mode: ShaperMode::Off,
+            shaper_drive: 0,
+            engine: Engine::Wavetable,
+            fm_algorithm: FmAlgorithm::TwoOperator,
+            fm_operators: OperatorSettings::defaults(),
+            string_damping: DEFAULT_DAMPING,
+            string_decay: DEFAULT_DECAY,
+            string_pick_position: 0,
+            drawbars: preset_drawbars(DrawbarPreset::Sine),
+            drawbar_tilt: 0,
+            modal_material: ModalMaterial::Wood,
+            modal_exciter: ModalExciter::Mallet,
+            modal_brightness: modal::DEFAULT_BRIGHTNESS,
+            modal_decay: modal::DEFAULT_DECAY,
+            pitch_bend_cents: 0,
+            timbre: 0,
+            reserve: 0,
+            sustain_pedal: false,
+            sostenuto_pedal: false,
+        }
     }
 
-    fn channel_aftertouch(&mut self, aftertouch: u8) {
-        self.adsr.set_aftertouch(aftertouch as u32 + 127);
+    /// Store a setting for the voices the part starts later
+    fn set(&mut self, param: Param) {
+        match param {
+            Param::Attack(attack_ms) => self.attack_ms = attack_ms,
+            Param::Decay(decay_ms) => self.decay_ms = decay_ms,
+            Param::Sustain(sustain_level) => self.sustain_level = sustain_level,
+            Param::Release(release_ms) => self.release_ms = release_ms,
+            Param::AttackCurve(curve) => self.attack_curve = curve,
+            Param::DecayCurve(curve) => self.decay_curve = curve,
+            Param::ReleaseCurve(curve) => self.release_curve = curve,
+            Param::Wavetable(wavetable) => self.wavetable = wavetable,
+            Param::Portamento(portamento_time_ms) => self.portamento_time_ms = portamento_time_ms,
+            Param::ChannelAftertouch(aftertouch) => self.aftertouch = aftertouch,
+            Param::PressureDestination(destination) => self.pressure_destination = destination,
+            Param::PressureDepth(depth) => self.pressure_depth = depth,
+            Param::ModWheel(value) => self.mod_wheel = value,
+            Param::VibratoRate(rate_centihertz) => self.vibrato_rate_centihertz = rate_centihertz,
+            Param::VibratoShape(shape) => self.vibrato_shape = shape,
+            Param::VibratoDelay(delay_ms) => self.vibrato_delay_ms = delay_ms,
+            Param::LfoRate {
+                lfo,
+                rate_centihertz,
+            } => {
+                if let Some(settings) = self.lfos.get_mut(lfo as usize) {
+                    settings.rate_centihertz = rate_centihertz;
+                }
+            }
+            Param::LfoShape { lfo, shape } => {
+                if let Some(settings) = self.lfos.get_mut(lfo as usize) {
+                    settings.shape = shape;
+                }
+            }
+            Param::LfoDepth { lfo, depth } => {
+                if let Some(settings) = self.lfos.get_mut(lfo as usize) {
+                    settings.depth = u8::min(depth, MAX_LFO_DEPTH);
+                }
+            }
+            Param::LfoTarget { lfo, target } => {
+                if let Some(settings) = self.lfos.get_mut(lfo as usize) {
+                    settings.target = target;
+                }
+            }
+            Param::LfoSync { lfo, sync } => {
+                if let Some(settings) = self.lfos.get_mut(lfo as usize) {
+                    settings.sync = sync;
+                }
+            }
+            Param::ModSource { slot, source } => {
+                if let Some(mod_slot) = self.mod_slots.get_mut(slot as usize) {
+                    mod_slot.source = source;
+                }
+            }
+            Param::ModTarget { slot, target } => {
+                if let Some(mod_slot) = self.mod_slots.get_mut(slot as usize) {
+                    mod_slot.target = target;
+                }
+            }
+            Param::ModDepth { slot, depth } => {
+                if let Some(mod_slot) = self.mod_slots.get_mut(slot as usize) {
+                    mod_slot.depth = depth;
+                }
+            }
+            Param::ModKnob(value) => self.mod_knob = value,
+            Param::FilterMode(mode) => self.filter_mode = mode,
+            Param::FilterCutoff(cutoff) => self.filter_cutoff = cutoff,
+            Param::FilterResonance(resonance) => self.filter_resonance = resonance,
+            Param::FilterKeyTrack(amount) => self.filter_key_track = amount,
+            Param::FilterAttack(attack_ms) => self.filter_attack_ms = attack_ms,
+            Param::FilterDecay(decay_ms) => self.filter_decay_ms = decay_ms,
+            Param::FilterSustain(sustain_level) => self.filter_sustain_level = sustain_level,
+            Param::FilterRelease(release_ms) => self.filter_release_ms = release_ms,
+            Param::FilterEnvDepth(depth) => self.filter_env_depth = depth,
+            Param::FilterType(filter_type) => self.filter_type = filter_type,
+            Param::FilterDrive(drive) => self.filter_drive = drive,
+            Param::ModAttack(attack_ms) => self.mod_attack_ms = attack_ms,
+            Param::ModDecay(decay_ms) => self.mod_decay_ms = decay_ms,
+            Param::ModSustain(sustain_level) => self.mod_sustain_level = sustain_level,
+            Param::ModRelease(release_ms) => self.mod_release_ms = release_ms,
+            Param::Osc2Wavetable(wavetable) => self.osc2_wavetable = wavetable,
+            Param::Osc2Coarse(semitones) => self.osc2_coarse_semitones = semitones,
+            Param::Osc2Fine(cents) => self.osc2_fine_cents = cents,
+            Param::Osc2Level(level) => self.osc2_level = level,
+            Param::Osc2Sync(on) => self.osc2_sync = on,
+            Param::Osc2Ring(on) => self.osc2_ring = on,
+            Param::SubShape(shape) => self.sub_shape = shape,
+            Param::SubOctave(octaves) => self.sub_octaves = octaves,
+            Param::SubLevel(level) => self.sub_level = level,
+            Param::NoiseColor(color) => self.noise_color = color,
+            Param::NoiseLevel(level) => self.noise_level = level,
+            Param::PulseWidth(width) => self.pulse_width = width,
+            Param::OscillatorMode(mode) => self.oscillator_mode = mode,
+            Param::PhaseDistortion(amount) => self.phase_distortion = amount,
+            Param::ShaperMode(mode) => self.shaper_mode = mode,
+            Param::ShaperDrive(drive) => self.shaper_drive = drive,
+            Param::FmAlgorithm(algorithm) => self.fm_algorithm = algorithm,
+            Param::FmRatio { op, ratio_halves } => {
+                if let Some(settings) = self.fm_operators.get_mut(op as usize) {
+                    settings.ratio_halves = ratio_halves;
+                }
+            }
+            Param::FmLevel { op, level } => {
+                if let Some(settings) = self.fm_operators.get_mut(op as usize) {
+                    settings.level = level;
+                }
+            }
+            Param::FmAttack { op, attack_ms } => {
+                if let Some(settings) = self.fm_operators.get_mut(op as usize) {
+                    settings.attack_ms = attack_ms;
+                }
+            }
+            Param::FmDecay { op, decay_ms } => {
+                if let Some(settings) = self.fm_operators.get_mut(op as usize) {
+                    settings.decay_ms = decay_ms;
+                }
+            }
+            Param::FmSustain { op, sustain_level } => {
+                if let Some(settings) = self.fm_operators.get_mut(op as usize) {
+                    settings.sustain_level = sustain_level;
+                }
+            }
+            Param::FmRelease { op, release_ms } => {
+                if let Some(settings) = self.fm_operators.get_mut(op as usize) {
+                    settings.release_ms = release_ms;
+                }
+            }
+            Param::StringDamping(damping) => self.string_damping = damping,
+            Param::StringDecay(decay) => self.string_decay = decay,
+            Param::StringPickPosition(position) => self.string_pick_position = position,
+            Param::Drawbar { drawbar, level } => {
+                if let Some(existing) = self.drawbars.get_mut(drawbar as usize) {
+                    *existing = level;
+                }
+            }
+            Param::DrawbarPreset(preset) => self.drawbars = preset_drawbars(preset),
+            Param::DrawbarTilt(tilt) => self.drawbar_tilt = tilt,
+            Param::ModalMaterial(material) => self.modal_material = material,
+            Param::ModalExciter(exciter) => self.modal_exciter = exciter,
+            Param::ModalBrightness(brightness) => self.modal_brightness = brightness,
+            Param::ModalDecay(decay) => self.modal_decay = decay,
+            Param::Timbre(timbre) => self.timbre = timbre,
+            // Key pressure only lasts for the note, and the tempo is not kept per part
+            Param::KeyPressure { .. } | Param::Tempo(_) => {}
+        }
+    }
+
+    /// Set up voice `index` to play a note for the part, switching it to the part's engine
+    fn apply(&self, voice: &mut Voice, index: usize) {
+        // Every field is named so a new setting can not be left out
+        let Part {
+            wavetable,
+            attack_ms,
+            decay_ms,
+            sustain_level,
+            release_ms,
+            attack_curve,
+            decay_curve,
+            release_curve,
+            portamento_time_ms,
+            aftertouch,
+            pressure_destination,
+            pressure_depth,
+            mod_wheel,
+            vibrato_rate_centihertz,
+            vibrato_shape,
+            vibrato_delay_ms,
+            lfos,
+            mod_slots,
+            mod_knob,
+            filter_mode,
+            filter_cutoff,
+            filter_resonance,
+            filter_key_track,
+            filter_attack_ms,
+            filter_decay_ms,
+            filter_sustain_level,
+            filter_release_ms,
+            filter_env_depth,
+            filter_type,
+            filter_drive,
+            mod_attack_ms,
+            mod_decay_ms,
+            mod_sustain_level,
+            mod_release_ms,
+            osc2_wavetable,
+            osc2_coarse_semitones,
+            osc2_fine_cents,
+            osc2_level,
+            osc2_sync,
+            osc2_ring,
+            sub_shape,
+            sub_octaves,
+            sub_level,
+            noise_color,
+            noise_level,
+            pulse_width,
+            oscillator_mode,
+            phase_distortion,
+            shaper_mode,
+            shaper_drive,
+            engine,
+            fm_algorithm,
+            fm_operators,
+            string_damping,
+            string_decay,
+            string_pick_position,
+            drawbars,
+            drawbar_tilt,
+            modal_material,
+            modal_exciter,
+            modal_brightness,
+            modal_decay,
+            pitch_bend_cents,
+            timbre,
+            reserve: _,
+            sustain_pedal: _,
+            sostenuto_pedal: _,
+        } = *self;
+        if voice.engine() != engine {
+            *voice = Voice::new(engine, index);
+        }
+        voice.note_pitch_bend(0);
+        let voice = voice.synth();
+        voice.pitch_bend(pitch_bend_cents);
+        for param in [
+            Param::Wavetable(wavetable),
+            Param::Attack(attack_ms),
+            Param::Decay(decay_ms),
+            Param::Sustain(sustain_level),
+            Param::Release(release_ms),
+            Param::AttackCurve(attack_curve),
+            Param::DecayCurve(decay_curve),
+            Param::ReleaseCurve(release_curve),
+            Param::Portamento(portamento_time_ms),
+            Param::ChannelAftertouch(aftertouch),
+            Param::PressureDestination(pressure_destination),
+            Param::PressureDepth(pressure_depth),
+            Param::ModWheel(mod_wheel),
+            Param::VibratoRate(vibrato_rate_centihertz),
+            Param::VibratoShape(vibrato_shape),
+            Param::VibratoDelay(vibrato_delay_ms),
+            Param::ModKnob(mod_knob),
+            Param::FilterMode(filter_mode),
+            Param::FilterCutoff(filter_cutoff),
+            Param::FilterResonance(filter_resonance),
+            Param::FilterKeyTrack(filter_key_track),
+            Param::FilterAttack(filter_attack_ms),
+            Param::FilterDecay(filter_decay_ms),
+            Param::FilterSustain(filter_sustain_level),
+            Param::FilterRelease(filter_release_ms),
+            Param::FilterEnvDepth(filter_env_depth),
+            Param::FilterType(filter_type),
+            Param::FilterDrive(filter_drive),
+            Param::ModAttack(mod_attack_ms),
+            Param::ModDecay(mod_decay_ms),
+            Param::ModSustain(mod_sustain_level),
+            Param::ModRelease(mod_release_ms),
+            Param::Osc2Wavetable(osc2_wavetable),
+            Param::Osc2Coarse(osc2_coarse_semitones),
+            Param::Osc2Fine(osc2_fine_cents),
+            Param::Osc2Level(osc2_level),
+            Param::Osc2Sync(osc2_sync),
+            Param::Osc2Ring(osc2_ring),
+            Param::SubShape(sub_shape),
+            Param::SubOctave(sub_octaves),
+            Param::SubLevel(sub_level),
+            Param::NoiseColor(noise_color),
+            Param::NoiseLevel(noise_level),
+            Param::PulseWidth(pulse_width),
+            Param::OscillatorMode(oscillator_mode),
+            Param::PhaseDistortion(phase_distortion),
+            Param::ShaperMode(shaper_mode),
+            Param::ShaperDrive(shaper_drive),
+            Param::FmAlgorithm(fm_algorithm),
+            Param::StringDamping(string_damping),
+            Param::StringDecay(string_decay),
+            Param::StringPickPosition(string_pick_position),
+            Param::DrawbarTilt(drawbar_tilt),
+            Param::ModalMaterial(modal_material),
+            Param::ModalExciter(modal_exciter),
+            Param::ModalBrightness(modal_brightness),
+            Param::ModalDecay(modal_decay),
+            Param::Timbre(timbre),
+        ] {
+            voice.set_param(param);
+        }
+        for (lfo, settings) in lfos.iter().enumerate() {
+            let lfo = lfo as u8;
+            voice.set_param(Param::LfoRate {
+                lfo,
+                rate_centihertz: settings.rate_centihertz,
+            });
+            voice.set_param(Param::LfoShape {
+                lfo,
+                shape: settings.shape,
+            });
+            voice.set_param(Param::LfoDepth {
+                lfo,
+                depth: settings.depth,
+            });
+            voice.set_param(Param::LfoTarget {
+                lfo,
+                target: settings.target,
+            });
+            voice.set_param(Param::LfoSync {
+                lfo,
+                sync: settings.sync,
+            });
+        }
+        for (slot, settings) in mod_slots.iter().enumerate() {
+            let slot = slot as u8;
+            voice.set_param(Param::ModSource {
+                slot,
+                source: settings.source,
+            });
+            voice.set_param(Param::ModTarget {
+                slot,
+                target: settings.target,
+            });
+            voice.set_param(Param::ModDepth {
+                slot,
+                depth: settings.depth,
+            });
+        }
+        for (op, settings) in fm_operators.iter().enumerate() {
+            let op = op as u8;
+            voice.set_param(Param::FmRatio {
+                op,
+                ratio_halves: settings.ratio_halves,
+            });
+            voice.set_param(Param::FmLevel {
+                op,
+                level: settings.level,
+            });
+            voice.set_param(Param::FmAttack {
+                op,
+                attack_ms: settings.attack_ms,
+            });
+            voice.set_param(Param::FmDecay {
+                op,
+                decay_ms: settings.decay_ms,
+            });
+            voice.set_param(Param::FmSustain {
+                op,
+                sustain_level: settings.sustain_level,
+            });
+            voice.set_param(Param::FmRelease {
+                op,
+                release_ms: settings.release_ms,
+            });
+        }
+        for (drawbar, level) in drawbars.iter().enumerate() {
+            voice.set_param(Param::Drawbar {
+                drawbar: drawbar as u8,
+                level: *level,
+            });
+        }
     }
 }
 
+/// A voice of `PolySynth`, playing the engine of the part it last played a note for
+enum Voice {
+    Wavetable(MonoSynth),
+    Fm(FmSynth),
+    String(StringSynth),
+    Additive(AdditiveSynth),
+    Sample(SampleSynth),
+    Modal(ModalSynth),
+}
+
+impl Voice {
+    /// A voice of the engine, the index of the voice gives it noise of its own
+    fn new(engine: Engine, index: usize) -> Self {
+        let mut voice = match engine {
+            Engine::Wavetable => Self::Wavetable(MonoSynth::new()),
+            Engine::Fm => Self::Fm(FmSynth::new()),
+            Engine::String => Self::String(StringSynth::new()),
+            Engine::Additive => Self::Additive(AdditiveSynth::new()),
+            Engine::Sample => Self::Sample(SampleSynth::new()),
+            Engine::Modal => Self::Modal(ModalSynth::new()),
+        };
+        match &mut voice {
+            Self::Wavetable(synth) => synth.seed_noise(index),
+            Self::String(synth) => synth.seed_noise(index),
+            Self::Modal(synth) => synth.seed_noise(index),
+            Self::Fm(_) | Self::Additive(_) | Self::Sample(_) => {}
+        }
+        voice
+    }
+
+    fn engine(&self) -> Engine {
+        match self {
+            Self::Wavetable(_) => Engine::Wavetable,
+            Self::Fm(_) => Engine::Fm,
+            Self::String(_) => Engine::String,
+            Self::Additive(_) => Engine::Additive,
+            Self::Sample(_) => Engine::Sample,
+            Self::Modal(_) => Engine::Modal,
+        }
+    }
+
+    fn synth(&mut self) -> &mut dyn Synth {
+        match self {
+            Self::Wavetable(voice) => voice,
+            Self::Fm(voice) => voice,
+            Self::String(voice) => voice,
+            Self::Additive(voice) => voice,
+            Self::Sample(voice) => voice,
+            Self::Modal(voice) => voice,
+        }
+    }
+
+    /// The note being played, or last played
+    fn note(&self) -> u8 {
+        match self {
+            Self::Wavetable(voice) => voice.note(),
+            Self::Fm(voice) => voice.note(),
+            Self::String(voice) => voice.note(),
+            Self::Additive(voice) => voice.note(),
+            Self::Sample(voice) => voice.note(),
+            Self::Modal(voice) => voice.note(),
+        }
+    }
+
+    /// True once the voice is silent
+    fn is_done(&self) -> bool {
+        match self {
+            Self::Wavetable(voice) => voice.is_done(),
+            Self::Fm(voice) => voice.is_done(),
+            Self::String(voice) => voice.is_done(),
+            Self::Additive(voice) => voice.is_done(),
+            Self::Sample(voice) => voice.is_done(),
+            Self::Modal(voice) => voice.is_done(),
+        }
+    }
+
+    /// Bend just this voice, on top of the pitch bend of the part
+    fn note_pitch_bend(&mut self, cents: i16) {
+        match self {
+            Self::Wavetable(voice) => voice.note_pitch_bend(cents),
+            Self::Fm(voice) => voice.note_pitch_bend(cents),
+            Self::String(voice) => voice.note_pitch_bend(cents),
+            Self::Additive(voice) => voice.note_pitch_bend(cents),
+            Self::Sample(voice) => voice.note_pitch_bend(cents),
+            Self::Modal(voice) => voice.note_pitch_bend(cents),
+        }
+    }
+}
+
+/// Multi-timbral polyphonic synth.
+///
+/// The voices are shared between `MAX_PARTS` parts. The `Synth` methods act on the part chosen
+/// with `select_part`, part 0 unless changed.
+///
+/// For MPE, `select_member` narrows the calls down to the voice started from that member channel,
+/// so pitch bend, aftertouch and timbre only affect that note.
 pub struct PolySynth {
-    voices: [MonoSynth; 5],
-    active_voices: [bool; 5],
+    voices: [Voice; VOICE_COUNT],
+    voice_states: [VoiceState; VOICE_COUNT],
+    /// Part each voice last played a note for
+    voice_parts: [usize; VOICE_COUNT],
+    /// MPE member channel each voice was started from
+    voice_members: [Option<u8>; VOICE_COUNT],
+    /// Voices latched by the sostenuto pedal
+    sostenuto_voices: [bool; VOICE_COUNT],
+    /// Value of `note_counter` when each voice was last triggered, used to steal the oldest
+    voice_ages: [u32; VOICE_COUNT],
+    note_counter: u32,
+    parts: [Part; MAX_PARTS],
+    part: usize,
+    member: Option<u8>,
+}
+
+impl PolySynth {
+    /// Direct the following `Synth` calls to a part
+    pub fn select_part(&mut self, part: usize) {
+        self.part = usize::min(part, MAX_PARTS - 1);
+    }
+
+    /// Direct the following `Synth` calls to the voice of an MPE member channel
+    pub fn select_member(&mut self, member: Option<u8>) {
+        self.member = member;
+    }
+
+    /// Set how many voices are reserved for the selected part
+    pub fn set_part_reserve(&mut self, voices: u8) {
+        self.parts[self.part].reserve = voices;
+    }
+
+    /// Set the engine of the selected part, notes already playing finish on their engine
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.parts[self.part].engine = engine;
+    }
+
+    /// Keep the notes of the selected part sounding after their keys are released
+    pub fn sustain_pedal(&mut self, on: bool) {
+        self.parts[self.part].sustain_pedal = on;
+        self.release_pedal_voices();
+    }
+
+    /// Pressing sostenuto latches only the notes whose keys are down at that moment
+    pub fn sostenuto_pedal(&mut self, on: bool) {
+        // Pedals can send a stream of values, only latch on the press
+        if on == self.parts[self.part].sostenuto_pedal {
+            return;
+        }
+        self.parts[self.part].sostenuto_pedal = on;
+        for i in 0..VOICE_COUNT {
+            if self.voice_parts[i] == self.part {
+                self.sostenuto_voices[i] = on && self.voice_states[i] == VoiceState::Held;
+            }
+        }
+        self.release_pedal_voices();
+    }
+
+    fn is_idle(&self, index: usize) -> bool {
+        self.voice_states[index] == VoiceState::Released && self.voices[index].is_done()
+    }
+
+    /// Whether the selected part may take a voice without breaking another part's reserve
+    fn may_take(&self, index: usize, voice_counts: &[u8; MAX_PARTS]) -> bool {
+        let owner = self.voice_parts[index];
+        if self.is_idle(index) {
+            // Idle voices are spare unless they are needed to fill the reserves of other parts
+            let idle_count = (0..VOICE_COUNT).filter(|i| self.is_idle(*i)).count() as u8;
+            let outstanding: u8 = (0..MAX_PARTS)
+                .filter(|part| *part != self.part)
+                .map(|part| self.parts[part].reserve.saturating_sub(voice_counts[part]))
+                .sum();
+            voice_counts[self.part] < self.parts[self.part].reserve || idle_count > outstanding
+        } else {
+            owner == self.part || voice_counts[owner] > self.parts[owner].reserve
+        }
+    }
+
+    /// Pick the voice for a new note on the selected part.
+    ///
+    /// A voice already playing the note is retriggered, otherwise silent voices are used first,
+    /// then the oldest releasing voice, then the oldest pedal-held voice and finally the oldest
+    /// held voice. Voices are only taken from other parts above their reserve.
+    fn allocate_voice(&self, note: u8) -> usize {
+        // A member channel only ever plays one note, so its voice is reused for the next one
+        let playing = (0..VOICE_COUNT).find(|i| {
+            self.voice_parts[*i] == self.part
+                && self.voice_members[*i] == self.member
+                && self.voice_states[*i] != VoiceState::Released
+                && (self.member.is_some() || self.voices[*i].note() == note)
+        });
+        if let Some(index) = playing {
+            return index;
+        }
+
+        let mut voice_counts = [0u8; MAX_PARTS];
+        for i in 0..VOICE_COUNT {
+            if !self.is_idle(i) {
+                voice_counts[self.voice_parts[i]] += 1;
+            }
+        }
+
+        // If the reserves can not be met, fall back to ignoring them
+        let restricted = (0..VOICE_COUNT).any(|i| self.may_take(i, &voice_counts));
+
+        let mut best_index = 0;
+        let mut best_rank = (u8::MAX, u32::MAX);
+        for i in 0..VOICE_COUNT {
+            if restricted && !self.may_take(i, &voice_counts) {
+                continue;
+            }
+            let priority = match self.voice_states[i] {
+                VoiceState::Released if self.is_idle(i) => 0,
+                VoiceState::Released => 1,
+                VoiceState::Pedal => 2,
+                VoiceState::Held => 3,
+            };
+            let rank = (priority, self.voice_ages[i]);
+            if rank < best_rank {
+                best_rank = rank;
+                best_index = i;
+            }
+        }
+        best_index
+    }
+
+    fn release_voice(&mut self, index: usize) {
+        let note = self.voices[index].note();
+        self.voices[index].synth().note_off(note);
+        self.voice_states[index] = VoiceState::Released;
+        self.sostenuto_voices[index] = false;
+    }
+
+    /// Release every voice of the selected part that is only sounding because of a pedal that is
+    /// no longer holding it
+    fn release_pedal_voices(&mut self) {
+        let sustain_pedal = self.parts[self.part].sustain_pedal;
+        for i in 0..VOICE_COUNT {
+            if self.voice_parts[i] == self.part
+                && self.voice_states[i] == VoiceState::Pedal
+                && !sustain_pedal
+                && !self.sostenuto_voices[i]
+            {
+                self.release_voice(i);
+            }
+        }
+    }
+
+    /// Voices currently playing for the selected part
+    fn part_voices(&mut self) -> impl Iterator<Item = &mut dyn Synth> {
+        let part = self.part;
+        self.voices
+            .iter_mut()
+            .zip(self.voice_parts.iter())
+            .filter(move |(_, voice_part)| **voice_part == part)
+            .map(|(voice, _)| voice.synth())
+    }
+
+    /// Voices of the selected member channel
+    fn member_voices(&mut self) -> impl Iterator<Item = &mut Voice> {
+        let part = self.part;
+        let member = self.member;
+        self.voices
+            .iter_mut()
+            .zip(self.voice_parts.iter().zip(self.voice_members.iter()))
+            .filter(move |(_, (voice_part, voice_member))| {
+                **voice_part == part && **voice_member == member
+            })
+            .map(|(voice, _)| voice)
+    }
 }
 
 impl Synth for PolySynth {
     fn new() -> Self {
-        let voices = [
-            MonoSynth::new(),
-            MonoSynth::new(),
-            MonoSynth::new(),
-            MonoSynth::new(),
-            MonoSynth::new(),
-        ];
+        let voices = core::array::from_fn(|index| Voice::new(Engine::Wavetable, index));
         Self {
             voices,
-            active_voices: [false, false, false, false, false],
+            voice_states: [VoiceState::Released; VOICE_COUNT],
+            voice_parts: [0; VOICE_COUNT],
+            voice_members: [None; VOICE_COUNT],
+            sostenuto_voices: [false; VOICE_COUNT],
+            voice_ages: [0; VOICE_COUNT],
+            note_counter: 0,
+            parts: [Part::new(); MAX_PARTS],
+            part: 0,
+            member: None,
         }
     }
 
     fn update(&mut self, elapsed_time_us: u32) -> u8 {
         let mut sample = 0;
         for voice in self.voices.iter_mut() {
-            sample += voice.update(elapsed_time_us) as u32;
+            sample += voice.synth().update(elapsed_time_us) as u32;
         }
         sample as u8
     }
 
     fn note_on(&mut self, note: u8, velocity: u8) {
-        let mut voice_index = 0;
-        for (i, active) in self.active_voices.iter().enumerate() {
-            if !*active {
-                voice_index = i;
-                break;
-            }
+        let voice_index = self.allocate_voice(note);
+        // Striking a latched note again keeps it latched, a voice taken for another note is not
+        let restruck = self.voice_states[voice_index] != VoiceState::Released
+            && self.voice_parts[voice_index] == self.part
+            && self.voices[voice_index].note() == note;
+        self.note_counter = self.note_counter.wrapping_add(1);
+        self.parts[self.part].apply(&mut self.voices[voice_index], voice_index);
+        self.voices[voice_index].synth().note_on(note, velocity);
+        self.voice_states[voice_index] = VoiceState::Held;
+        self.voice_parts[voice_index] = self.part;
+        self.voice_members[voice_index] = self.member;
+        if !restruck {
+            self.sostenuto_voices[voice_index] = false;
         }
-        self.voices[voice_index].note_on(note, velocity);
-        self.active_voices[voice_index] = true;
+        self.voice_ages[voice_index] = self.note_counter;
     }
 
     fn note_off(&mut self, note: u8) {
-        for (voice, active) in self.voices.iter_mut().zip(self.active_voices.iter_mut()) {
-            if *active && voice.oscilator.get_midi_note() == note {
-                voice.note_off(note);
-                *active = false;
+        let part = self.parts[self.part];
+        for i in 0..VOICE_COUNT {
+            if self.voice_parts[i] != self.part
+                || self.voice_members[i] != self.member
+                || self.voice_states[i] != VoiceState::Held
+                || self.voices[i].note() != note
+            {
+                continue;
+            }
+            if part.sustain_pedal || self.sostenuto_voices[i] {
+                self.voice_states[i] = VoiceState::Pedal;
+            } else {
+                self.release_voice(i);
             }
         }
     }
 
-    fn attack_control(&mut self, attack_ms: u16) {
-        for voice in self.voices.iter_mut() {
-            voice.attack_control(attack_ms);
-        }
-    }
-
-    fn decay_control(&mut self, decay_ms: u16) {
-        for voice in self.voices.iter_mut() {
-            voice.decay_control(decay_ms);
+    fn set_param(&mut self, param: Param) {
+        match param {
+            // Aftertouch and timbre on an MPE member channel are just that note's
+            Param::ChannelAftertouch(_) | Param::Timbre(_) if self.member.is_some() => {
+                for voice in self.member_voices() {
+                    voice.synth().set_param(param);
+                }
+            }
+            // Key pressure only reaches the voices still holding the note
+            Param::KeyPressure { .. } => {
+                for i in 0..VOICE_COUNT {
+                    if self.voice_parts[i] == self.part
+                        && self.voice_states[i] != VoiceState::Released
+                    {
+                        self.voices[i].synth().set_param(param);
+                    }
+                }
+            }
+            // The MIDI clock is shared by all parts
+            Param::Tempo(_) => {
+                for voice in self.voices.iter_mut() {
+                    voice.synth().set_param(param);
+                }
+            }
+            _ => {
+                self.parts[self.part].set(param);
+                for voice in self.part_voices() {
+                    voice.set_param(param);
+                }
+            }
         }
     }
 
-    fn sustain_control(&mut self, sustain_level: u16) {
-        for voice in self.voices.iter_mut() {
-            voice.sustain_control(sustain_level);
+    fn all_sound_off(&mut self) {
+        for i in 0..VOICE_COUNT {
+            if self.voice_parts[i] == self.part {
+                self.voices[i].synth().all_sound_off();
+                self.voice_states[i] = VoiceState::Released;
+                self.sostenuto_voices[i] = false;
+            }
         }
     }
 
-    fn release_control(&mut self, release_ms: u16) {
-        for voice in self.voices.iter_mut() {
-            voice.release_control(release_ms);
+    /// Releases pedal-held notes as well, so stuck voices can always be recovered
+    fn all_notes_off(&mut self) {
+        for i in 0..VOICE_COUNT {
+            if self.voice_parts[i] == self.part && self.voice_states[i] != VoiceState::Released {
+                self.release_voice(i);
+            }
         }
     }
 
-    fn set_wavetable(&mut self, wavetable: &'static [u8; WAVETABLE_SIZE]) {
-        for voice in self.voices.iter_mut() {
-            voice.set_wavetable(wavetable);
+    fn reset_all_controllers(&mut self) {
+        self.parts[self.part].aftertouch = 0;
+        self.parts[self.part].mod_wheel = 0;
+        self.parts[self.part].pitch_bend_cents = 0;
+        self.parts[self.part].timbre = 0;
+        for voice in self.part_voices() {
+            voice.reset_all_controllers();
         }
+        self.sustain_pedal(false);
+        self.sostenuto_pedal(false);
     }
 
-    fn portamento_control(&mut self, portamento_time_ms: u16) {
-        for voice in self.voices.iter_mut() {
-            voice.oscilator.set_portamento(portamento_time_ms as u32);
+    /// Pitch bend on an MPE member channel bends just that note, on top of the part's bend
+    fn pitch_bend(&mut self, cents: i16) {
+        if self.member.is_some() {
+            for voice in self.member_voices() {
+                voice.note_pitch_bend(cents);
+            }
+            return;
         }
-    }
-
-    fn channel_aftertouch(&mut self, aftertouch: u8) {
-        for voice in self.voices.iter_mut() {
-            voice.adsr.set_aftertouch(aftertouch as u32);
+        self.parts[self.part].pitch_bend_cents = cents;
+        for voice in self.part_voices() {
+            voice.pitch_bend(cents);
         }
     }
 }
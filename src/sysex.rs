@@ -0,0 +1,296 @@
+//! System exclusive commands, read from the raw USB MIDI event packets.
+//!
+//! Messages use the non-commercial manufacturer ID followed by a device ID:
+//! `F0 7D 53 <command> <data...> F7`
+use crate::controls::ReceiveChannel;
+use crate::intercore::{ModSource, ModTarget};
+use crate::modmatrix::{ModSlot, MAX_MOD_DEPTH};
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+const MANUFACTURER_ID: u8 = 0x7D;
+const DEVICE_ID: u8 = 0x53;
+/// Longest message we accept, anything longer is for another device
+const MAX_SYSEX_SIZE: usize = 16;
+
+/// USB MIDI code index numbers for SysEx packets
+const CIN_SYSEX_CONTINUE: u8 = 0x4;
+const CIN_SYSEX_END_1: u8 = 0x5;
+const CIN_SYSEX_END_2: u8 = 0x6;
+const CIN_SYSEX_END_3: u8 = 0x7;
+
+/// Command bytes
+const SET_RECEIVE_CHANNEL: u8 = 0x01;
+const SET_PART_COUNT: u8 = 0x02;
+const SET_PART_CHANNEL: u8 = 0x03;
+const SET_PART_RESERVE: u8 = 0x04;
+const SET_MOD_SLOT: u8 = 0x05;
+const SET_DRUM_MODE: u8 = 0x06;
+/// Data byte of a zero modulation depth
+const MOD_DEPTH_CENTER: i32 = 64;
+
+/// Each side of the center scales on its own, so both 00 and 7F reach full depth
+fn mod_depth(byte: u8) -> i8 {
+    let offset = byte as i32 - MOD_DEPTH_CENTER;
+    let depth = if offset > 0 {
+        offset * MAX_MOD_DEPTH / (0x7F - MOD_DEPTH_CENTER)
+    } else {
+        offset * MAX_MOD_DEPTH / MOD_DEPTH_CENTER
+    };
+    depth.clamp(-MAX_MOD_DEPTH, MAX_MOD_DEPTH) as i8
+}
+
+pub enum SysexCommand {
+    /// `F0 7D 53 01 <channel 0..=15, or 7F for omni> F7`
+    SetReceiveChannel(ReceiveChannel),
+    /// `F0 7D 53 02 <count 1..=4> F7`, 1 turns multi-timbral mode off
+    SetPartCount(u8),
+    /// `F0 7D 53 03 <part 1..=3> <channel 0..=15> F7`, part 0 uses the receive channel
+    SetPartChannel { part: u8, channel: u8 },
+    /// `F0 7D 53 04 <part 0..=3> <voices> F7`
+    SetPartReserve { part: u8, voices: u8 },
+    /// `F0 7D 53 05 <part 0..=3> <slot 0..=3> <source 0..=9> <target 0..=6> <depth> F7`,
+    /// depth 40 is zero which turns the slot off, 00 and 7F are full negative and positive depth
+    SetModSlot {
+        part: u8,
+        slot: u8,
+        mod_slot: ModSlot,
+    },
+    /// `F0 7D 53 06 <0 off, 1 on> F7`, on plays the drum kit from MIDI channel 10
+    SetDrumMode(bool),
+}
+
+/// Reassembles SysEx messages split across USB MIDI event packets.
+pub struct SysexReader {
+    buffer: [u8; MAX_SYSEX_SIZE],
+    length: usize,
+}
+
+impl SysexReader {
+    pub fn new() -> Self {
+        Self {
+            buffer: [0; MAX_SYSEX_SIZE],
+            length: 0,
+        }
+    }
+
+    /// Feed a 4 byte USB MIDI event packet, returns a command once a complete message is read
+    pub fn read_packet(&mut self, packet: &[u8]) -> Option<SysexCommand> {
+        let data = match packet[0] & 0x0F {
+            CIN_SYSEX_CONTINUE => &packet[1..4],
+            CIN_SYSEX_END_1 => &packet[1..2],
+            CIN_SYSEX_END_2 => &packet[1..3],
+            CIN_SYSEX_END_3 => &packet[1..4],
+            _ => return None,
+        };
+
+        for byte in data {
+            if *byte == SYSEX_START {
+                self.length = 0;
+            }
+            // Overlong messages are truncated, they fail to parse below
+            if self.length < MAX_SYSEX_SIZE {
+                self.buffer[self.length] = *byte;
+                self.length += 1;
+            }
+        }
+
+        if packet[0] & 0x0F == CIN_SYSEX_CONTINUE {
+            return None;
+        }
+        let command = self.parse();
+        self.length = 0;
+        command
+    }
+
+    fn parse(&self) -> Option<SysexCommand> {
+        match &self.buffer[..self.length] {
+            [SYSEX_START, MANUFACTURER_ID, DEVICE_ID, SET_RECEIVE_CHANNEL, channel, SYSEX_END] => {
+                Some(SysexCommand::SetReceiveChannel(ReceiveChannel::from_sysex(
+                    *channel,
+                )?))
+            }
+            [SYSEX_START, MANUFACTURER_ID, DEVICE_ID, SET_PART_COUNT, count, SYSEX_END] => {
+                Some(SysexCommand::SetPartCount(*count))
+            }
+            [SYSEX_START, MANUFACTURER_ID, DEVICE_ID, SET_PART_CHANNEL, part, channel, SYSEX_END]
+                if *channel < 16 =>
+            {
+                Some(SysexCommand::SetPartChannel {
+                    part: *part,
+                    channel: *channel,
+                })
+            }
+            [SYSEX_START, MANUFACTURER_ID, DEVICE_ID, SET_PART_RESERVE, part, voices, SYSEX_END] => {
+                Some(SysexCommand::SetPartReserve {
+                    part: *part,
+                    voices: *voices,
+                })
+            }
+            [SYSEX_START, MANUFACTURER_ID, DEVICE_ID, SET_MOD_SLOT, part, slot, source, target, depth, SYSEX_END] => {
+                Some(SysexCommand::SetModSlot {
+                    part: *part,
+                    slot: *slot,
+                    mod_slot: ModSlot {
+                        source: ModSource::from_u8(*source)?,
+                        target: ModTarget::from_u8(*target)?,
+                        depth: mod_depth(*depth),
+                    },
+                })
+            }
+            [SYSEX_START, MANUFACTURER_ID, DEVICE_ID, SET_DRUM_MODE, on, SYSEX_END] if *on < 2 => {
+                Some(SysexCommand::SetDrumMode(*on != 0))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Split a message into USB MIDI event packets and feed them to a reader
+    fn read(message: &[u8]) -> Option<SysexCommand> {
+        let mut reader = SysexReader::new();
+        let mut command = None;
+        for chunk in message.chunks(3) {
+            let cin = match chunk.len() {
+                _ if chunk.last() != Some(&SYSEX_END) => CIN_SYSEX_CONTINUE,
+                1 => CIN_SYSEX_END_1,
+                2 => CIN_SYSEX_END_2,
+                _ => CIN_SYSEX_END_3,
+            };
+            let mut packet = [cin, 0, 0, 0];
+            packet[1..1 + chunk.len()].copy_from_slice(chunk);
+            command = reader.read_packet(&packet);
+        }
+        command
+    }
+
+    #[test]
+    fn unknown_mod_source_is_rejected() {
+        let message = [
+            SYSEX_START,
+            MANUFACTURER_ID,
+            DEVICE_ID,
+            SET_MOD_SLOT,
+            0,
+            0,
+            10,
+            0,
+            0x7F,
+            SYSEX_END,
+        ];
+        assert!(read(&message).is_none());
+    }
+
+    #[test]
+    fn mod_depth_reaches_full_scale_on_both_sides() {
+        assert_eq!(mod_depth(0x00), -(MAX_MOD_DEPTH as i8));
+        assert_eq!(mod_depth(0x40), 0);
+        assert_eq!(mod_depth(0x7F), MAX_MOD_DEPTH as i8);
+    }
+
+    #[test]
+    fn receive_channel() {
+        let omni = [
+            SYSEX_START,
+            MANUFACTURER_ID,
+            DEVICE_ID,
+            SET_RECEIVE_CHANNEL,
+            0x7F,
+            SYSEX_END,
+        ];
+        assert!(matches!(
+            read(&omni),
+            Some(SysexCommand::SetReceiveChannel(ReceiveChannel::Omni))
+        ));
+        let channel = [
+            SYSEX_START,
+            MANUFACTURER_ID,
+            DEVICE_ID,
+            SET_RECEIVE_CHANNEL,
+            9,
+            SYSEX_END,
+        ];
+        assert!(matches!(
+            read(&channel),
+            Some(SysexCommand::SetReceiveChannel(ReceiveChannel::Channel(9)))
+        ));
+        let out_of_range = [
+            SYSEX_START,
+            MANUFACTURER_ID,
+            DEVICE_ID,
+            SET_RECEIVE_CHANNEL,
+            16,
+            SYSEX_END,
+        ];
+        assert!(read(&out_of_range).is_none());
+    }
+
+    #[test]
+    fn part_channel_must_be_a_midi_channel() {
+        let message = [
+            SYSEX_START,
+            MANUFACTURER_ID,
+            DEVICE_ID,
+            SET_PART_CHANNEL,
+            2,
+            15,
+            SYSEX_END,
+        ];
+        assert!(matches!(
+            read(&message),
+            Some(SysexCommand::SetPartChannel {
+                part: 2,
+                channel: 15
+            })
+        ));
+        let message = [
+            SYSEX_START,
+            MANUFACTURER_ID,
+            DEVICE_ID,
+            SET_PART_CHANNEL,
+            2,
+            16,
+            SYSEX_END,
+        ];
+        assert!(read(&message).is_none());
+    }
+
+    #[test]
+    fn drum_mode_is_on_or_off() {
+        let on = [
+            SYSEX_START,
+            MANUFACTURER_ID,
+            DEVICE_ID,
+            SET_DRUM_MODE,
+            1,
+            SYSEX_END,
+        ];
+        assert!(matches!(read(&on), Some(SysexCommand::SetDrumMode(true))));
+        let other = [
+            SYSEX_START,
+            MANUFACTURER_ID,
+            DEVICE_ID,
+            SET_DRUM_MODE,
+            2,
+            SYSEX_END,
+        ];
+        assert!(read(&other).is_none());
+    }
+
+    #[test]
+    fn other_manufacturers_are_ignored() {
+        let message = [
+            SYSEX_START,
+            0x7E,
+            DEVICE_ID,
+            SET_RECEIVE_CHANNEL,
+            1,
+            SYSEX_END,
+        ];
+        assert!(read(&message).is_none());
+    }
+}
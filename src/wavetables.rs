@@ -1,4 +1,7 @@
+use crate::intercore::OscillatorMode;
+
 pub const WAVETABLE_SIZE: usize = 128;
+const WAVETABLE_BITS: u32 = 7;
 pub static SINE_WAVETABLE: [u8; WAVETABLE_SIZE] = [
     127, 133, 139, 146, 152, 158, 164, 170, 176, 182, 187, 193, 198, 203, 208, 213, 217, 221, 226,
     229, 233, 236, 239, 242, 245, 247, 249, 251, 252, 253, 254, 254, 255, 254, 254, 253, 252, 251,
@@ -44,8 +47,62 @@ pub static MIDI_NOTE_TO_SAMPLE_INTERVAL_NS: [u32; 128] = [
     3139, 2963, 2796, 2639, 2491, 2351, 2219, 2095, 1977, 1866, 1762, 1663, 1569, 1481, 1398, 1320,
     1246, 1176, 1110, 1047, 989, 0, 0, 0, 0, 0, 0, 0, 0,
 ];
+/// Shortest interval in `MIDI_NOTE_TO_SAMPLE_INTERVAL_NS`, the highest pitch a player reaches
+const MIN_SAMPLE_INTERVAL_NS: u32 = 989;
+/// Wavetables in order of brightness, timbre blends a wavetable towards the next one
+pub static WAVETABLES_BY_BRIGHTNESS: [&[u8; WAVETABLE_SIZE]; 4] = [
+    &SINE_WAVETABLE,
+    &TRIANGLE_WAVETABLE,
+    &SAWTOOTH_WAVETABLE,
+    &SQUARE_WAVETABLE,
+];
+pub const MAX_TIMBRE: u8 = 127;
+pub const MAX_PULSE_WIDTH: u8 = 127;
+/// Samples of the square wave that are high at the narrowest pulse width
+const MIN_PULSE_SAMPLES: u32 = 4;
+pub const MAX_PHASE_DISTORTION: u8 = 127;
+/// A cycle of the phase distortion modes is 1 << PHASE_BITS
+const PHASE_BITS: u32 = 16;
+const HALF_PHASE: u32 = 1 << (PHASE_BITS - 1);
+/// Part of the cycle the bent phase takes to reach half way at full distortion
+const MIN_KNEE: u32 = 1 << (PHASE_BITS - 6);
+/// Highest frequency of the resonance, in multiples of the note
+const MAX_RESONANCE_RATIO: u32 = 16;
+
+/// 2^(k/12) for k in 0..=12, as 16.16 fixed point
+static SEMITONE_RATIOS: [u32; 13] = [
+    65536, 69433, 73562, 77936, 82570, 87480, 92682, 98193, 104032, 110218, 116772, 123715, 131072,
+];
+/// Sample interval ratio of an unbent note
+pub const PITCH_RATIO_UNITY: u32 = 1 << 16;
+/// Keeps the ratio within range of the 64 bit interval calculation
+const MAX_PITCH_OFFSET_CENTS: i32 = 9600;
+
+/// Convert a pitch offset into the ratio to scale a sample interval by, as 16.16 fixed point.
+///
+/// Raising the pitch shortens the interval, so the ratio is 2^(-cents/1200).
+pub fn pitch_ratio(cents: i32) -> u32 {
+    let cents = -cents.clamp(-MAX_PITCH_OFFSET_CENTS, MAX_PITCH_OFFSET_CENTS);
+    let octaves = cents.div_euclid(1200);
+    let remainder = cents.rem_euclid(1200) as u32;
+    let semitone = (remainder / 100) as usize;
+    let low = SEMITONE_RATIOS[semitone];
+    let high = SEMITONE_RATIOS[semitone + 1];
+    let ratio = low + (high - low) * (remainder % 100) / 100;
+    if octaves >= 0 {
+        ratio << octaves
+    } else {
+        ratio >> -octaves
+    }
+}
+
 pub struct WavetablePlayer {
     wavetable: &'static [u8; WAVETABLE_SIZE],
+    /// The next brighter wavetable, blended in by `timbre`
+    timbre_wavetable: &'static [u8; WAVETABLE_SIZE],
+    timbre: u8,
+    /// Scales the sample interval to apply pitch bend, see `pitch_ratio`
+    pitch_ratio: u32,
     note: u8,
     sample_interval_ns: u32,
     note_counter_ns: u32,
@@ -54,6 +111,12 @@ pub struct WavetablePlayer {
     portamento_target_sample_interval_ns: u32,
     portamento_prev_sample_interval_ns: u32,
     protamento_counter_ns: u32,
+    /// Whether the last sample started a new cycle, for hard sync
+    wrapped: bool,
+    /// Index the square wave goes high at, half way through the cycle for an even square
+    pulse_start: u32,
+    mode: OscillatorMode,
+    phase_distortion: u8,
 }
 
 impl WavetablePlayer {
@@ -61,6 +124,9 @@ impl WavetablePlayer {
         let sample_interval_ns = MIDI_NOTE_TO_SAMPLE_INTERVAL_NS[midi_note as usize];
         Self {
             wavetable,
+            timbre_wavetable: brighter_wavetable(wavetable),
+            timbre: 0,
+            pitch_ratio: PITCH_RATIO_UNITY,
             note: midi_note,
             sample_interval_ns,
             note_counter_ns: 0,
@@ -69,11 +135,43 @@ impl WavetablePlayer {
             portamento_target_sample_interval_ns: sample_interval_ns,
             portamento_prev_sample_interval_ns: sample_interval_ns,
             protamento_counter_ns: 0,
+            wrapped: false,
+            pulse_start: WAVETABLE_SIZE as u32 / 2,
+            mode: OscillatorMode::Wavetable,
+            phase_distortion: 0,
         }
     }
 
     pub fn set_wavetable(&mut self, wavetable: &'static [u8; WAVETABLE_SIZE]) {
         self.wavetable = wavetable;
+        self.timbre_wavetable = brighter_wavetable(wavetable);
+    }
+
+    /// Blend towards the next brighter wavetable, 0 to `MAX_TIMBRE`
+    pub fn set_timbre(&mut self, timbre: u8) {
+        self.timbre = u8::min(timbre, MAX_TIMBRE);
+    }
+
+    /// Narrow the high part of the square wave, from an even square at 0 to a thin pulse at
+    /// `MAX_PULSE_WIDTH`. Other wavetables are not affected.
+    pub fn set_pulse_width(&mut self, width: u8) {
+        let half = WAVETABLE_SIZE as u32 / 2;
+        let width = u8::min(width, MAX_PULSE_WIDTH) as u32;
+        self.pulse_start = half + (half - MIN_PULSE_SAMPLES) * width / MAX_PULSE_WIDTH as u32;
+    }
+
+    pub fn set_mode(&mut self, mode: OscillatorMode) {
+        self.mode = mode;
+    }
+
+    /// Amount the phase distortion modes bend the phase, from none to `MAX_PHASE_DISTORTION`
+    pub fn set_phase_distortion(&mut self, amount: u8) {
+        self.phase_distortion = u8::min(amount, MAX_PHASE_DISTORTION);
+    }
+
+    /// Offset the pitch of the note, e.g. for pitch bend
+    pub fn set_pitch_offset(&mut self, cents: i32) {
+        self.pitch_ratio = pitch_ratio(cents);
     }
 
     pub fn set_portamento(&mut self, glide_time_ms: u32) {
@@ -93,6 +191,17 @@ impl WavetablePlayer {
         self.note
     }
 
+    /// True when the last call to `next_sample` started a new cycle of the wavetable
+    pub fn wrapped(&self) -> bool {
+        self.wrapped
+    }
+
+    /// Restart the cycle from the beginning of the wavetable
+    pub fn reset_phase(&mut self) {
+        self.wavetable_index = 0;
+        self.note_counter_ns = 0;
+    }
+
     pub fn next_sample(&mut self, elapsed_time_us: u32) -> u8 {
         if !(self.sample_interval_ns == self.portamento_target_sample_interval_ns) {
             self.protamento_counter_ns += elapsed_time_us;
@@ -115,21 +224,102 @@ impl WavetablePlayer {
             }
         }
 
+        let sample_interval_ns = if self.pitch_ratio == PITCH_RATIO_UNITY {
+            self.sample_interval_ns
+        } else {
+            ((self.sample_interval_ns as u64 * self.pitch_ratio as u64) >> 16) as u32
+        };
+        // A wide MPE bend on a high note can't go past the top of the note table
+        let sample_interval_ns = u32::max(sample_interval_ns, MIN_SAMPLE_INTERVAL_NS);
+
         self.note_counter_ns += elapsed_time_us * 1_000;
 
-        if self.note_counter_ns >= self.sample_interval_ns {
-            let wavetable_inc = self.note_counter_ns / self.sample_interval_ns;
+        if self.note_counter_ns >= sample_interval_ns {
+            let wavetable_inc = self.note_counter_ns / sample_interval_ns;
             self.wavetable_index += wavetable_inc;
-            self.note_counter_ns = self.note_counter_ns - self.sample_interval_ns * wavetable_inc;
+            self.note_counter_ns = self.note_counter_ns - sample_interval_ns * wavetable_inc;
+        }
+
+        // A long update can step over more than one cycle
+        self.wrapped = self.wavetable_index >= self.wavetable.len() as u32;
+        if self.wrapped {
+            self.wavetable_index %= self.wavetable.len() as u32;
         }
 
-        if self.wavetable_index >= self.wavetable.len() as u32 {
-            let diff = self.wavetable_index - self.wavetable.len() as u32;
-            self.wavetable_index = diff;
+        if self.mode != OscillatorMode::Wavetable {
+            return self.phase_distorted_sample();
         }
 
-        let sample = self.wavetable[self.wavetable_index as usize];
+        let sample = self.sample_at(self.wavetable);
+        if self.timbre == 0 {
+            return sample;
+        }
+
+        let target = self.sample_at(self.timbre_wavetable) as i32;
+        let blend = (target - sample as i32) * self.timbre as i32 / MAX_TIMBRE as i32;
+        (sample as i32 + blend) as u8
+    }
+
+    /// Read the sine table through a bent phase, in the manner of the Casio CZ
+    fn phase_distorted_sample(&self) -> u8 {
+        let phase = self.wavetable_index << (PHASE_BITS - WAVETABLE_BITS);
+        let amount = self.phase_distortion as u32;
+        match self.mode {
+            OscillatorMode::PdResonant => {
+                // A sine up to MAX_RESONANCE_RATIO times faster, reset at the start of each cycle
+                // and faded out over it so the reset does not click
+                let ratio = (1 << 8)
+                    + ((MAX_RESONANCE_RATIO - 1) << 8) * amount / MAX_PHASE_DISTORTION as u32;
+                let resonance_phase = ((phase * ratio) >> 8) & ((1 << PHASE_BITS) - 1);
+                let sample = SINE_WAVETABLE
+                    [(resonance_phase >> (PHASE_BITS - WAVETABLE_BITS)) as usize]
+                    as i32;
+                let window = ((1 << PHASE_BITS) - phase) as i32;
+                (128 + (((sample - 128) * window) >> PHASE_BITS)) as u8
+            }
+            _ => {
+                // The first half of the sine is squeezed into the part of the cycle before the
+                // knee, which moves earlier with the amount
+                let knee =
+                    HALF_PHASE - (HALF_PHASE - MIN_KNEE) * amount / MAX_PHASE_DISTORTION as u32;
+                let bent_phase = if phase < knee {
+                    phase * HALF_PHASE / knee
+                } else {
+                    HALF_PHASE + (phase - knee) * HALF_PHASE / ((1 << PHASE_BITS) - knee)
+                };
+                SINE_WAVETABLE[(bent_phase >> (PHASE_BITS - WAVETABLE_BITS)) as usize]
+            }
+        }
+    }
 
-        sample as u8
+    /// The sample of a wavetable at the current index, the square wave is generated by comparing
+    /// the index with the pulse width
+    fn sample_at(&self, wavetable: &'static [u8; WAVETABLE_SIZE]) -> u8 {
+        if core::ptr::eq(wavetable, &SQUARE_WAVETABLE) {
+            if self.wavetable_index >= self.pulse_start {
+                u8::MAX
+            } else {
+                0
+            }
+        } else {
+            wavetable[self.wavetable_index as usize]
+        }
     }
 }
+
+fn brighter_wavetable(wavetable: &'static [u8; WAVETABLE_SIZE]) -> &'static [u8; WAVETABLE_SIZE] {
+    step_wavetable(wavetable, 1)
+}
+
+/// The wavetable `steps` brighter, or darker when negative, stopping at either end
+pub fn step_wavetable(
+    wavetable: &'static [u8; WAVETABLE_SIZE],
+    steps: i32,
+) -> &'static [u8; WAVETABLE_SIZE] {
+    let index = WAVETABLES_BY_BRIGHTNESS
+        .iter()
+        .position(|candidate| core::ptr::eq(*candidate, wavetable))
+        .unwrap_or(0) as i32;
+    let stepped = (index + steps).clamp(0, WAVETABLES_BY_BRIGHTNESS.len() as i32 - 1);
+    WAVETABLES_BY_BRIGHTNESS[stepped as usize]
+}